@@ -0,0 +1,97 @@
+//! Derive macros for the `rudderanalytics` crate.
+//!
+//! These are re-exported from `rudderanalytics::event` when the `derive`
+//! feature is enabled and should not be used directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, LitStr};
+
+/// Implement `rudderanalytics::event::TrackEvent` for a struct.
+///
+/// The event name is taken from `#[rudder(event = "...")]`, or defaults to
+/// the struct name. The struct must also implement `serde::Serialize`.
+#[proc_macro_derive(TrackEvent, attributes(rudder))]
+pub fn derive_track_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_track_event(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Implement `rudderanalytics::event::IdentifyTraits` for a struct.
+///
+/// The struct must also implement `serde::Serialize`.
+#[proc_macro_derive(IdentifyTraits)]
+pub fn derive_identify_traits(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_identify_traits(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_track_event(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    check_object_shape(input, "TrackEvent")?;
+    let event = event_name(input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::rudderanalytics::event::TrackEvent for #name #ty_generics #where_clause {
+            const EVENT_NAME: &'static str = #event;
+        }
+    })
+}
+
+fn expand_identify_traits(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    check_object_shape(input, "IdentifyTraits")?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::rudderanalytics::event::IdentifyTraits for #name #ty_generics #where_clause {}
+    })
+}
+
+// Properties and traits are sent as JSON objects, so only structs with named
+// fields (or no fields at all) are accepted.
+fn check_object_shape(input: &DeriveInput, derive: &str) -> syn::Result<()> {
+    match &input.data {
+        Data::Struct(data) => match data.fields {
+            Fields::Named(_) | Fields::Unit => Ok(()),
+            Fields::Unnamed(_) => Err(Error::new_spanned(
+                &input.ident,
+                format!("{} cannot be derived for tuple structs", derive),
+            )),
+        },
+        _ => Err(Error::new_spanned(
+            &input.ident,
+            format!("{} can only be derived for structs", derive),
+        )),
+    }
+}
+
+fn event_name(input: &DeriveInput) -> syn::Result<LitStr> {
+    let mut event = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("rudder"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("event") {
+                let value: LitStr = meta.value()?.parse()?;
+                if value.value().trim().is_empty() {
+                    return Err(meta.error("event name must not be empty"));
+                }
+                event = Some(value);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported rudder attribute, expected `event`"))
+            }
+        })?;
+    }
+
+    Ok(event.unwrap_or_else(|| LitStr::new(&input.ident.to_string(), Span::call_site())))
+}
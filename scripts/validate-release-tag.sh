@@ -2,7 +2,12 @@
 
 set -euo pipefail
 
-crate_version="$(cargo metadata --no-deps --format-version 1 | jq -r '.packages[0].version')"
+package_version() {
+  cargo metadata --no-deps --format-version 1 | jq -r --arg name "$1" '.packages[] | select(.name == $name) | .version'
+}
+
+crate_version="$(package_version rudderanalytics)"
+derive_version="$(package_version rudderanalytics-derive)"
 expected_tag="v${crate_version}"
 
 if [ "${RELEASE_TAG}" != "${expected_tag}" ]; then
@@ -10,3 +15,9 @@ if [ "${RELEASE_TAG}" != "${expected_tag}" ]; then
   echo "Expected release tag: ${expected_tag}" >&2
   exit 1
 fi
+
+# release-please keeps the derive crate's version linked to the main crate's
+if [ "${derive_version}" != "${crate_version}" ]; then
+  echo "rudderanalytics-derive version ${derive_version} does not match rudderanalytics version ${crate_version}." >&2
+  exit 1
+fi
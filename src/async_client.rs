@@ -0,0 +1,166 @@
+//! Async Rudderanalytics client built on reqwest's async API.
+//!
+//! Available with the `async` cargo feature. Validation, payload conversion,
+//! redaction and retry decisions are shared with the blocking client; only
+//! the HTTP call and the sleep between retries are async.
+
+use crate::client::{parse_rudder_message, validate_and_path, DEFAULT_CONNECT_TIMEOUT};
+use crate::compression::{encode_body, CompressionConfig, RequestBody};
+use crate::errors::Error as AnalyticsError;
+use crate::message::Message;
+use crate::redaction::{RedactionConfig, Redactor};
+use crate::retry::{RetryConfig, RetryState};
+use crate::ruddermessage::Ruddermessage;
+use crate::stats::{DeliveryEvent, DeliveryObserver, DeliveryStats, StatsSnapshot};
+use crate::transport::request_headers;
+use log::debug;
+use reqwest::Response;
+use std::time::{Duration, Instant};
+
+// Async Rudderanalytics client
+pub struct AsyncRudderAnalytics {
+    pub write_key: String,
+    pub data_plane_url: String,
+    pub client: reqwest::Client,
+    pub retry_config: RetryConfig,
+    pub compression: CompressionConfig,
+    redactor: Option<Redactor>,
+    stats: DeliveryStats,
+    observers: Vec<Box<dyn DeliveryObserver>>,
+}
+
+impl AsyncRudderAnalytics {
+    /// Initialize the async client with write-key and data-plane-url.
+    pub fn load(write_key: String, data_plane_url: String) -> AsyncRudderAnalytics {
+        Self::load_with_retry_config(write_key, data_plane_url, RetryConfig::default())
+    }
+
+    /// Initialize the async client with a custom retry configuration.
+    pub fn load_with_retry_config(
+        write_key: String,
+        data_plane_url: String,
+        retry_config: RetryConfig,
+    ) -> AsyncRudderAnalytics {
+        AsyncRudderAnalytics {
+            write_key,
+            data_plane_url,
+            client: reqwest::Client::builder()
+                .connect_timeout(DEFAULT_CONNECT_TIMEOUT)
+                .build()
+                .unwrap(),
+            retry_config,
+            compression: CompressionConfig::default(),
+            redactor: None,
+            stats: DeliveryStats::default(),
+            observers: Vec::new(),
+        }
+    }
+
+    /// Redact personal data from `traits`, `properties` and `context` when
+    /// events are converted to the Ruddermessage format, before they reach
+    /// the network.
+    pub fn with_redaction(
+        mut self,
+        config: RedactionConfig,
+    ) -> Result<AsyncRudderAnalytics, AnalyticsError> {
+        self.redactor = Some(Redactor::new(config)?);
+        Ok(self)
+    }
+
+    /// Call `observer` with every delivery event, e.g. to report them to a
+    /// metrics system.
+    pub fn with_observer(
+        mut self,
+        observer: impl DeliveryObserver + 'static,
+    ) -> AsyncRudderAnalytics {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Delivery counters since the client was created.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Validate the message, convert it to the Ruddermessage format and send
+    /// it to the data plane url, retrying transient failures without blocking
+    /// the runtime.
+    pub async fn send(&self, msg: &Message) -> Result<(), AnalyticsError> {
+        let path = validate_and_path(msg)?;
+        let mut rudder_message = parse_rudder_message(msg);
+        if let Some(redactor) = &self.redactor {
+            redactor.redact(&mut rudder_message);
+        }
+        let mut retry = RetryState::new(&self.retry_config);
+
+        debug!("rudder_message: {:#?}", rudder_message);
+
+        let events = match &rudder_message {
+            Ruddermessage::Batch(b_) => b_.batch.len(),
+            _ => 1,
+        };
+        let result = self.deliver(path, &rudder_message, &mut retry).await;
+        match &result {
+            Ok(()) => self.emit(DeliveryEvent::Delivered { path, events }),
+            Err(_) => self.emit(DeliveryEvent::Failed { path, events }),
+        }
+        result
+    }
+
+    async fn deliver(
+        &self,
+        path: &str,
+        rudder_message: &Ruddermessage,
+        retry: &mut RetryState<'_>,
+    ) -> Result<(), AnalyticsError> {
+        let body = encode_body(&self.compression, rudder_message)?;
+        loop {
+            let started = Instant::now();
+            let response = self.post(path, &body).await;
+            self.emit(DeliveryEvent::Attempt {
+                path,
+                status: response.as_ref().ok().map(|res| res.status().as_u16()),
+                latency: started.elapsed(),
+                bytes: body.bytes.len(),
+            });
+
+            let (status, delay) = match response {
+                Ok(res) if res.status().is_success() => return Ok(()),
+                Ok(res) => {
+                    let status = res.status();
+                    let headers = res.headers().clone();
+                    let body = res.text().await.unwrap_or_default();
+                    (
+                        Some(status.as_u16()),
+                        retry.retry_status(status, &headers, body)?,
+                    )
+                }
+                Err(err) => (None, retry.retry_error(err.into())?),
+            };
+            self.emit(DeliveryEvent::Retry {
+                path,
+                status,
+                delay,
+            });
+            if delay > Duration::from_secs(0) {
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+
+    fn emit(&self, event: DeliveryEvent) {
+        self.stats.record(&event);
+        for observer in &self.observers {
+            observer.observe(&event);
+        }
+    }
+
+    async fn post(&self, path: &str, body: &RequestBody) -> Result<Response, reqwest::Error> {
+        self.client
+            .post(format!("{}{}", self.data_plane_url, path))
+            .headers(request_headers(&self.write_key, body.gzipped))
+            .body(body.bytes.clone())
+            .send()
+            .await
+    }
+}
@@ -2,55 +2,68 @@
 
 use crate::errors::Error as AnalyticsError;
 use crate::message::{Batch, BatchMessage, Message};
-use failure::Error;
-use serde_json::Value;
+use crate::utils;
 use chrono::prelude::*;
+use serde_json::{json, Value};
 
-const MAX_MESSAGE_SIZE: usize = 1024 * 32;
-const MAX_BATCH_SIZE: usize = 1024 * 512;
+/// Maximum size of a single event in RudderStack's wire format.
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 32;
+/// Maximum size of a `/v1/batch` request body.
+pub const MAX_BATCH_SIZE: usize = 1024 * 512;
 
-// / A batcher can accept messages into an internal buffer, and report when
-// / messages must be flushed.
-// /
-// / The recommended usage pattern looks something like this:
-// /
-// / ```
-// / use rudderanalytics::batcher::Batcher;
-// / use rudderanalytics::client::RudderAnalytics;
-// / use rudderanalytics::message::{BatchMessage, Track};
-// / use serde_json::json;
-// /
-// / let mut batcher = Batcher::new(None);
-// / let rudderAnalytics = RudderAnalytics::load("WRITE-KEY","DATA-PLANE-URL");
-// /
-// / for i in 0..100 {
-// /     let msg = BatchMessage::Track(Track {
-// /         user_id: Some(format!("user-{}", i)),
-// /         event: "Example".to_owned(),
-// /         properties: Some(json!({ "foo": "bar" })),
-// /         ..Default::default()
-// /     });
-// /
-// /     // Batcher returns back ownership of a message if the internal buffer
-// /     // would overflow.
-// /     //
-// /     // When this occurs, we flush the batcher, create a new batcher, and add
-// /     // the message into the new batcher.
-// /     if let Some(msg) = batcher.push(msg).unwrap() {
-// /         rudderAnalytics.send(&batcher.into_message()).unwrap();
-// /         batcher = Batcher::new(None);
-// /         batcher.push(msg).unwrap();
-// /     }
-// / }
+/// A batcher can accept messages into an internal buffer, and report when
+/// messages must be flushed.
+///
+/// The recommended usage pattern looks something like this:
+///
+/// ```no_run
+/// use rudderanalytics::batcher::Batcher;
+/// use rudderanalytics::client::RudderAnalytics;
+/// use rudderanalytics::message::{BatchMessage, Track};
+/// use serde_json::json;
+///
+/// let mut batcher = Batcher::new(None);
+/// let rudder_analytics = RudderAnalytics::load(
+///     "WRITE_KEY".to_string(),
+///     "DATA_PLANE_URL".to_string(),
+/// );
+///
+/// for i in 0..100 {
+///     let msg = BatchMessage::Track(Track {
+///         user_id: Some(format!("user-{}", i)),
+///         event: "Example".to_owned(),
+///         properties: Some(json!({ "foo": "bar" })),
+///         ..Default::default()
+///     });
+///
+///     // Batcher returns back ownership of a message if the internal buffer
+///     // would overflow.
+///     //
+///     // When this occurs, we flush the batcher, create a new batcher, and add
+///     // the message into the new batcher.
+///     if let Some(msg) = batcher.push(msg).unwrap() {
+///         rudder_analytics.send(&batcher.into_message()).unwrap();
+///         batcher = Batcher::new(None);
+///         batcher.push(msg).unwrap();
+///     }
+/// }
+///
+/// if !batcher.is_empty() {
+///     rudder_analytics.send(&batcher.into_message()).unwrap();
+/// }
 /// ```
 ///
-// / Batcher will attempt to fit messages into maximally-sized batches, thus
-// / reducing the number of round trips required with RudderStack's tracking API.
-// / However, if you produce messages infrequently, this may significantly delay
-// / the sending of messages to RudderStack.
-// /
-// / If this delay is a concern, it is recommended that you periodically flush
-// / the batcher on your own by calling `into_message`.
+/// Sizes are measured against the payload that is actually sent, including
+/// the `context.library` block and the batch context injected into every
+/// member.
+///
+/// Batcher will attempt to fit messages into maximally-sized batches, thus
+/// reducing the number of round trips required with RudderStack's tracking API.
+/// However, if you produce messages infrequently, this may significantly delay
+/// the sending of messages to RudderStack.
+///
+/// If this delay is a concern, it is recommended that you periodically flush
+/// the batcher on your own by calling `into_message`.
 pub struct Batcher {
     buf: Vec<BatchMessage>,
     byte_count: usize,
@@ -63,9 +76,17 @@ impl Batcher {
     /// Optionally, you may specify a `context` that should be set on every
     /// batch returned by `into_message`.
     pub fn new(context: Option<Value>) -> Self {
+        let envelope = utils::parse_batch(&Batch {
+            context: context.clone(),
+            ..Default::default()
+        });
+        let byte_count = serde_json::to_vec(&envelope)
+            .map(|envelope| envelope.len())
+            .unwrap_or(0);
+
         Self {
             buf: Vec::new(),
-            byte_count: 0,
+            byte_count,
             context,
         }
     }
@@ -82,24 +103,36 @@ impl Batcher {
     ///
     /// Returns an error if the message is too large to be sent to RudderStack's
     /// API.
-    pub fn push(&mut self, msg: BatchMessage) -> Result<Option<BatchMessage>, Error> {
-        let size = serde_json::to_vec(&msg)?.len();
+    pub fn push(&mut self, msg: BatchMessage) -> Result<Option<BatchMessage>, AnalyticsError> {
+        let size = self.wire_size(&msg)?;
         if size > MAX_MESSAGE_SIZE {
-            return Err(AnalyticsError::MessageTooLarge(String::from(
-                "status code: 400, message: Message too large",
-            ))
-            .into());
+            return Err(AnalyticsError::MessageTooLarge(format!(
+                "message is {} bytes, limit is {} bytes",
+                size, MAX_MESSAGE_SIZE
+            )));
         }
 
-        self.byte_count += size + 1; // +1 to account for Serialized data's extra commas
-        if self.byte_count > MAX_BATCH_SIZE {
+        // +1 to account for the comma separating serialized batch members
+        let byte_count = self.byte_count + size + 1;
+        if byte_count > MAX_BATCH_SIZE {
             return Ok(Some(msg));
         }
 
+        self.byte_count = byte_count;
         self.buf.push(msg);
         Ok(None)
     }
 
+    /// Number of messages currently held by the batcher.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Whether the batcher holds no messages.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
     /// Consumes this batcher and converts it into a message that can be sent to
     /// RudderStack.
     pub fn into_message(self) -> Message {
@@ -110,4 +143,11 @@ impl Batcher {
             original_timestamp: Some(Utc::now()),
         })
     }
+
+    fn wire_size(&self, msg: &BatchMessage) -> Result<usize, AnalyticsError> {
+        let batch_context = self.context.clone().unwrap_or_else(|| json!({}));
+        let now = Utc::now();
+        let rudder_message = utils::parse_batch_message(msg, &batch_context, Some(now), now);
+        Ok(serde_json::to_vec(&rudder_message)?.len())
+    }
 }
@@ -0,0 +1,303 @@
+//! Background queue-and-flush client.
+//!
+//! `BufferedRudderAnalytics` accepts messages into an in-memory queue and
+//! delivers them as `/v1/batch` requests from a dedicated worker thread, so
+//! callers never wait on the HTTP round trip or on retry sleeps.
+
+use crate::batcher::Batcher;
+use crate::client::{validate_and_path, RudderAnalytics};
+use crate::errors::Error as AnalyticsError;
+use crate::message::{BatchMessage, Message};
+use crate::rate_limit::RateLimiter;
+use crate::stats::{DeliveryEvent, DeliveryStats, StatsSnapshot};
+use crate::tracking_plan::PlanValidation;
+use crate::utils;
+use chrono::{DateTime, Utc};
+use log::{debug, error};
+use serde_json::Value;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Configuration for the background flush worker.
+#[derive(Debug, Clone)]
+pub struct BufferConfig {
+    /// Number of queued events that triggers an immediate flush. Batches are
+    /// also flushed early when they reach the 512 KB request size limit.
+    pub max_batch_size: usize,
+    /// Maximum time an event waits in the queue before it is flushed.
+    pub flush_interval: Duration,
+    /// Maximum number of messages held in memory. `enqueue` fails once full,
+    /// and a batch is only queued if all of its members fit.
+    pub queue_capacity: usize,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 100,
+            flush_interval: Duration::from_secs(5),
+            queue_capacity: 10_000,
+        }
+    }
+}
+
+enum Command {
+    Event(BatchMessage),
+    Flush(mpsc::Sender<()>),
+    Shutdown,
+}
+
+/// A client that queues messages and sends them from a background thread.
+///
+/// Messages are validated, including against the client's tracking plan,
+/// when they are enqueued, so invalid events are still reported to the
+/// caller. Delivery failures happen on the worker thread and are logged,
+/// since there is no caller left to return them to.
+///
+/// Dropping the client flushes any queued messages and stops the worker.
+pub struct BufferedRudderAnalytics {
+    sender: SyncSender<Command>,
+    // events sent to the worker that it has not taken off the queue yet
+    queued: Arc<AtomicUsize>,
+    queue_capacity: usize,
+    tracking_plan: Option<Arc<PlanValidation>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    stats: Arc<DeliveryStats>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl BufferedRudderAnalytics {
+    /// Start a worker thread that delivers queued messages with `analytics`.
+    pub fn new(analytics: RudderAnalytics, config: BufferConfig) -> BufferedRudderAnalytics {
+        let (sender, receiver) = mpsc::sync_channel(config.queue_capacity);
+        let queued = Arc::new(AtomicUsize::new(0));
+        let queue_capacity = config.queue_capacity;
+        let tracking_plan = analytics.tracking_plan.clone();
+        let rate_limiter = analytics.rate_limiter.clone();
+        let stats = Arc::clone(&analytics.stats);
+        let worker_queued = Arc::clone(&queued);
+        let worker = thread::Builder::new()
+            .name("rudderanalytics-flush".to_string())
+            .spawn(move || run_worker(analytics, config, receiver, worker_queued))
+            .expect("failed to spawn rudderanalytics flush thread");
+
+        BufferedRudderAnalytics {
+            sender,
+            queued,
+            queue_capacity,
+            tracking_plan,
+            rate_limiter,
+            stats,
+            worker: Some(worker),
+        }
+    }
+
+    /// Validate a message and add it to the queue without blocking.
+    ///
+    /// Events without an `original_timestamp` are stamped with the time they
+    /// were enqueued, not the time their batch is flushed. Batch messages are
+    /// split into their members, with the batch context, integrations and
+    /// timestamp applied to every member. A batch that does not fit in the
+    /// queue fails with `Error::QueueFull` without queueing any member.
+    pub fn enqueue(&self, mut msg: Message) -> Result<(), AnalyticsError> {
+        validate_and_path(&msg)?;
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.check(&msg)?;
+        }
+        if let Some(tracking_plan) = &self.tracking_plan {
+            if let Some(annotated) = tracking_plan.apply(&msg)? {
+                msg = annotated;
+            }
+        }
+        let enqueued_at = Utc::now();
+        let events = into_batch_messages(msg);
+        self.reserve(events.len())?;
+        for mut event in events {
+            batch_message_timestamp(&mut event).get_or_insert(enqueued_at);
+            // the reservation leaves room for the event, so this only waits
+            // for a flush command that is ahead of it
+            if self.sender.send(Command::Event(event)).is_err() {
+                return Err(AnalyticsError::QueueClosed);
+            }
+        }
+        Ok(())
+    }
+
+    // Claim room in the queue for `count` events, all or none.
+    fn reserve(&self, count: usize) -> Result<(), AnalyticsError> {
+        self.queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                queued
+                    .checked_add(count)
+                    .filter(|queued| *queued <= self.queue_capacity)
+            })
+            .map(|_| ())
+            .map_err(|_| AnalyticsError::QueueFull)
+    }
+
+    /// Delivery counters of the underlying client.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Send every message queued so far, blocking until the worker is done.
+    pub fn flush(&self) -> Result<(), AnalyticsError> {
+        let (ack_sender, ack_receiver) = mpsc::channel();
+        self.sender
+            .send(Command::Flush(ack_sender))
+            .map_err(|_| AnalyticsError::QueueClosed)?;
+        ack_receiver.recv().map_err(|_| AnalyticsError::QueueClosed)
+    }
+
+    /// Flush queued messages and stop the worker thread.
+    pub fn shutdown(mut self) {
+        self.stop_worker();
+    }
+
+    fn stop_worker(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            let _ = self.sender.send(Command::Shutdown);
+            if worker.join().is_err() {
+                error!("rudderanalytics flush thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for BufferedRudderAnalytics {
+    fn drop(&mut self) {
+        self.stop_worker();
+    }
+}
+
+fn run_worker(
+    analytics: RudderAnalytics,
+    config: BufferConfig,
+    receiver: mpsc::Receiver<Command>,
+    queued: Arc<AtomicUsize>,
+) {
+    let max_batch_size = config.max_batch_size.max(1);
+    let mut batcher = Batcher::new(None);
+    let mut next_flush = Instant::now() + config.flush_interval;
+
+    loop {
+        let timeout = next_flush.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(timeout) {
+            Ok(Command::Event(event)) => {
+                queued.fetch_sub(1, Ordering::SeqCst);
+                push_event(&analytics, &mut batcher, event);
+                if batcher.len() >= max_batch_size {
+                    send_batch(&analytics, &mut batcher);
+                    next_flush = Instant::now() + config.flush_interval;
+                }
+            }
+            Ok(Command::Flush(ack)) => {
+                send_batch(&analytics, &mut batcher);
+                next_flush = Instant::now() + config.flush_interval;
+                let _ = ack.send(());
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                send_batch(&analytics, &mut batcher);
+                next_flush = Instant::now() + config.flush_interval;
+            }
+            Ok(Command::Shutdown) | Err(RecvTimeoutError::Disconnected) => {
+                send_batch(&analytics, &mut batcher);
+                return;
+            }
+        }
+    }
+}
+
+fn push_event(analytics: &RudderAnalytics, batcher: &mut Batcher, event: BatchMessage) {
+    let result = match batcher.push(event) {
+        Ok(Some(event)) => {
+            send_batch(analytics, batcher);
+            batcher.push(event)
+        }
+        result => result,
+    };
+
+    if let Err(err) = result {
+        error!("dropping event that cannot be batched: {:?}", err);
+        analytics.emit(DeliveryEvent::Dropped { events: 1 });
+    }
+}
+
+fn send_batch(analytics: &RudderAnalytics, batcher: &mut Batcher) {
+    if batcher.is_empty() {
+        return;
+    }
+
+    let count = batcher.len();
+    let msg = std::mem::replace(batcher, Batcher::new(None)).into_message();
+
+    match analytics.send_checked(&msg) {
+        Ok(()) => debug!("flushed batch of {} events", count),
+        Err(err) => error!("failed to flush batch of {} events: {:?}", count, err),
+    }
+}
+
+fn into_batch_messages(msg: Message) -> Vec<BatchMessage> {
+    match msg {
+        Message::Identify(b_) => vec![BatchMessage::Identify(b_)],
+        Message::Track(b_) => vec![BatchMessage::Track(b_)],
+        Message::Page(b_) => vec![BatchMessage::Page(b_)],
+        Message::Screen(b_) => vec![BatchMessage::Screen(b_)],
+        Message::Group(b_) => vec![BatchMessage::Group(b_)],
+        Message::Alias(b_) => vec![BatchMessage::Alias(b_)],
+        Message::Batch(b_) => {
+            let context = b_.context;
+            let integrations = b_.integrations;
+            let original_timestamp = b_.original_timestamp;
+            b_.batch
+                .into_iter()
+                .map(|mut event| {
+                    let (event_context, event_integrations) = batch_message_fields(&mut event);
+                    inherit(event_context, &context);
+                    inherit(event_integrations, &integrations);
+                    let event_timestamp = batch_message_timestamp(&mut event);
+                    if event_timestamp.is_none() {
+                        *event_timestamp = original_timestamp;
+                    }
+                    event
+                })
+                .collect()
+        }
+    }
+}
+
+fn batch_message_fields(event: &mut BatchMessage) -> (&mut Option<Value>, &mut Option<Value>) {
+    match event {
+        BatchMessage::Identify(a_) => (&mut a_.context, &mut a_.integrations),
+        BatchMessage::Track(a_) => (&mut a_.context, &mut a_.integrations),
+        BatchMessage::Page(a_) => (&mut a_.context, &mut a_.integrations),
+        BatchMessage::Screen(a_) => (&mut a_.context, &mut a_.integrations),
+        BatchMessage::Group(a_) => (&mut a_.context, &mut a_.integrations),
+        BatchMessage::Alias(a_) => (&mut a_.context, &mut a_.integrations),
+    }
+}
+
+fn batch_message_timestamp(event: &mut BatchMessage) -> &mut Option<DateTime<Utc>> {
+    match event {
+        BatchMessage::Identify(a_) => &mut a_.original_timestamp,
+        BatchMessage::Track(a_) => &mut a_.original_timestamp,
+        BatchMessage::Page(a_) => &mut a_.original_timestamp,
+        BatchMessage::Screen(a_) => &mut a_.original_timestamp,
+        BatchMessage::Group(a_) => &mut a_.original_timestamp,
+        BatchMessage::Alias(a_) => &mut a_.original_timestamp,
+    }
+}
+
+// layer the batch level value underneath the event level one
+fn inherit(event_value: &mut Option<Value>, batch_value: &Option<Value>) {
+    if let Some(batch_value) = batch_value {
+        let mut merged = batch_value.clone();
+        if let Some(event_value) = event_value.take() {
+            utils::merge(&mut merged, event_value);
+        }
+        *event_value = Some(merged);
+    }
+}
@@ -0,0 +1,518 @@
+//! Builder-style configuration for `RudderAnalytics`.
+
+use crate::buffered::{BufferConfig, BufferedRudderAnalytics};
+use crate::capture::CaptureSink;
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
+use crate::client::{RudderAnalytics, DEFAULT_CONNECT_TIMEOUT};
+use crate::compression::CompressionConfig;
+use crate::errors::Error as AnalyticsError;
+use crate::failover::{Failover, FailoverConfig};
+use crate::middleware::Middleware;
+use crate::mirror::{Destination, Mirror};
+use crate::rate_limit::{RateLimitConfig, RateLimiter};
+use crate::redaction::{RedactionConfig, Redactor};
+use crate::retry::RetryConfig;
+use crate::sampling::{Sampler, SamplingConfig};
+use crate::spool::SpoolConfig;
+use crate::stats::DeliveryObserver;
+use crate::tracking_plan::{PlanValidation, ValidationMode, Validator};
+use crate::transport::Transport;
+use crate::utils;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Proxy, Url};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Configures and builds a `RudderAnalytics` client.
+///
+/// The data plane url and every other setting are validated by `build`, so
+/// configuration mistakes surface at startup instead of on the first `send`.
+///
+/// ```no_run
+/// use rudderanalytics::client::RudderAnalytics;
+/// use rudderanalytics::retry::RetryConfig;
+/// use serde_json::json;
+/// use std::time::Duration;
+///
+/// let rudder_analytics = RudderAnalytics::builder(
+///     "WRITE_KEY".to_string(),
+///     "https://hosted.rudderlabs.com".to_string(),
+/// )
+/// .timeout(Duration::from_secs(5))
+/// .user_agent("my-service/1.0")
+/// .retry_config(RetryConfig {
+///     max_retries: 5,
+///     ..Default::default()
+/// })
+/// .default_context(json!({ "app": { "name": "my-service" } }))
+/// .build()
+/// .unwrap();
+/// ```
+pub struct RudderAnalyticsBuilder {
+    write_key: String,
+    data_plane_url: String,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    proxies: Vec<Proxy>,
+    user_agent: Option<String>,
+    headers: Vec<(String, String)>,
+    #[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+    root_certificates: Vec<reqwest::Certificate>,
+    #[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+    built_in_root_certs: Option<bool>,
+    http_client: Option<reqwest::blocking::Client>,
+    transport: Option<Box<dyn Transport>>,
+    retry_config: RetryConfig,
+    compression: CompressionConfig,
+    buffer_config: BufferConfig,
+    spool_config: Option<SpoolConfig>,
+    default_context: Option<Value>,
+    tracking_plan: Option<PlanValidation>,
+    middleware: Vec<Box<dyn Middleware>>,
+    redaction: Option<RedactionConfig>,
+    sampling: Option<SamplingConfig>,
+    rate_limit: Option<RateLimitConfig>,
+    observers: Vec<Box<dyn DeliveryObserver>>,
+    capture: Option<Box<dyn CaptureSink>>,
+    circuit_breaker: Option<CircuitBreakerConfig>,
+    fallback: Option<Box<dyn CaptureSink>>,
+    failover: Option<FailoverConfig>,
+    mirrors: Vec<Destination>,
+}
+
+impl RudderAnalyticsBuilder {
+    /// Start a builder with the default settings used by `RudderAnalytics::load`.
+    pub fn new(write_key: String, data_plane_url: String) -> Self {
+        Self {
+            write_key,
+            data_plane_url,
+            connect_timeout: None,
+            timeout: None,
+            proxies: Vec::new(),
+            user_agent: None,
+            headers: Vec::new(),
+            #[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+            root_certificates: Vec::new(),
+            #[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+            built_in_root_certs: None,
+            http_client: None,
+            transport: None,
+            retry_config: RetryConfig::default(),
+            compression: CompressionConfig::default(),
+            buffer_config: BufferConfig::default(),
+            spool_config: None,
+            default_context: None,
+            tracking_plan: None,
+            middleware: Vec::new(),
+            redaction: None,
+            sampling: None,
+            rate_limit: None,
+            observers: Vec::new(),
+            capture: None,
+            circuit_breaker: None,
+            fallback: None,
+            failover: None,
+            mirrors: Vec::new(),
+        }
+    }
+
+    /// Timeout for establishing a connection. Defaults to 10 seconds.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for each request attempt, from connecting until the response
+    /// body has been read. Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Route requests through a proxy. May be called more than once.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// `User-Agent` header sent with every request.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Extra header sent with every request. May be called more than once.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Trust an additional root certificate, e.g. for a data plane behind a
+    /// private certificate authority.
+    #[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+    pub fn add_root_certificate(mut self, certificate: reqwest::Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Whether the platform's built-in root certificates are trusted.
+    /// Defaults to `true`.
+    #[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+    pub fn tls_built_in_root_certs(mut self, enabled: bool) -> Self {
+        self.built_in_root_certs = Some(enabled);
+        self
+    }
+
+    /// Use a pre-built `reqwest` client instead of building one.
+    ///
+    /// Cannot be combined with the timeout, proxy, user agent, header or TLS
+    /// settings, which only apply to a client built by this builder.
+    pub fn http_client(mut self, client: reqwest::blocking::Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Make requests with a custom `Transport` instead of a `reqwest` client.
+    ///
+    /// Cannot be combined with `http_client` or with the http client
+    /// settings.
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(Box::new(transport));
+        self
+    }
+
+    /// Retry behavior for transient delivery failures.
+    pub fn retry_config(mut self, retry_config: RetryConfig) -> Self {
+        self.retry_config = retry_config;
+        self
+    }
+
+    /// Gzip compression of request bodies.
+    pub fn compression(mut self, compression: CompressionConfig) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Batching settings used by `build_buffered`.
+    pub fn buffer_config(mut self, buffer_config: BufferConfig) -> Self {
+        self.buffer_config = buffer_config;
+        self
+    }
+
+    /// Spool events that fail with transient errors to disk.
+    pub fn spool(mut self, spool_config: SpoolConfig) -> Self {
+        self.spool_config = Some(spool_config);
+        self
+    }
+
+    /// Context merged into every event. Keys set on an event take precedence.
+    pub fn default_context(mut self, context: Value) -> Self {
+        self.default_context = Some(context);
+        self
+    }
+
+    /// Check track properties and identify traits with `validator` before
+    /// events are sent, handling violations according to `mode`.
+    pub fn validator(mut self, validator: impl Validator + 'static, mode: ValidationMode) -> Self {
+        self.tracking_plan = Some(PlanValidation::new(Box::new(validator), mode));
+        self
+    }
+
+    /// Redact personal data from `traits`, `properties` and `context` before
+    /// events are sent.
+    pub fn redaction(mut self, config: RedactionConfig) -> Self {
+        self.redaction = Some(config);
+        self
+    }
+
+    /// Sample track events before they are sent. The sampler runs ahead of
+    /// any other middleware.
+    pub fn sampling(mut self, config: SamplingConfig) -> Self {
+        self.sampling = Some(config);
+        self
+    }
+
+    /// Limit how many events are sent per second. Events over the limit fail
+    /// with `Error::RateLimited`.
+    pub fn rate_limit(mut self, config: RateLimitConfig) -> Self {
+        self.rate_limit = Some(config);
+        self
+    }
+
+    /// Call `observer` with every delivery event. May be called more than
+    /// once.
+    pub fn observer(mut self, observer: impl DeliveryObserver + 'static) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Hand every payload to `sink` instead of sending it, e.g. to assert on
+    /// outgoing events in tests without a data plane.
+    pub fn capture(mut self, sink: impl CaptureSink + 'static) -> Self {
+        self.capture = Some(Box::new(sink));
+        self
+    }
+
+    /// Stop making requests after repeated retryable failures, failing fast
+    /// with `Error::CircuitOpen` until a probe request succeeds.
+    pub fn circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = Some(config);
+        self
+    }
+
+    /// Hand payloads to `sink` instead of failing while the circuit breaker
+    /// is open.
+    pub fn fallback(mut self, sink: impl CaptureSink + 'static) -> Self {
+        self.fallback = Some(Box::new(sink));
+        self
+    }
+
+    /// Fail over between several data plane urls. The first endpoint
+    /// replaces the data plane url the builder was created with.
+    pub fn failover(mut self, config: FailoverConfig) -> Self {
+        self.failover = Some(config);
+        self
+    }
+
+    /// Also deliver every payload, or a sampled fraction of them, to
+    /// `destination`. May be called more than once.
+    ///
+    /// Mirrors are delivered on the sending thread after the client's own
+    /// data plane, so `send` also waits for each destination, including any
+    /// retries set in its `retry_config`.
+    pub fn mirror(mut self, destination: Destination) -> Self {
+        self.mirrors.push(destination);
+        self
+    }
+
+    /// Append a middleware to the chain every outgoing event is passed
+    /// through. May be called more than once; middleware runs in order.
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Validate the configuration and build the client.
+    pub fn build(mut self) -> Result<RudderAnalytics, AnalyticsError> {
+        let mut data_plane_url = validate_data_plane_url(&self.data_plane_url)?;
+        let failover = match self.failover.take() {
+            Some(config) => Some(Arc::new(Failover::new(config)?)),
+            None => None,
+        };
+        if let Some(failover) = &failover {
+            data_plane_url = failover.url(0).to_string();
+        }
+        if let Some(context) = &self.default_context {
+            validate_default_context(context)?;
+        }
+        let transport: Box<dyn Transport> = match self.transport.take() {
+            Some(_) if self.http_client.is_some() || self.has_client_settings() => {
+                return Err(AnalyticsError::InvalidConfig(String::from(
+                    "http client settings cannot be combined with a custom transport",
+                )));
+            }
+            Some(transport) => transport,
+            None => Box::new(self.build_http_client()?),
+        };
+        let mut middleware = self.middleware;
+        if let Some(config) = self.sampling {
+            middleware.insert(0, Box::new(Sampler::new(config)?));
+        }
+
+        let analytics = RudderAnalytics {
+            write_key: self.write_key,
+            data_plane_url,
+            retry_config: self.retry_config,
+            compression: self.compression,
+            default_context: self.default_context,
+            spool: None,
+            tracking_plan: self.tracking_plan.map(Arc::new),
+            middleware,
+            redactor: match self.redaction {
+                Some(config) => Some(Redactor::new(config)?),
+                None => None,
+            },
+            rate_limiter: match self.rate_limit {
+                Some(config) => Some(Arc::new(RateLimiter::new(config)?)),
+                None => None,
+            },
+            stats: Arc::default(),
+            observers: self.observers,
+            capture: self.capture,
+            circuit_breaker: self
+                .circuit_breaker
+                .map(|config| Arc::new(CircuitBreaker::new(config))),
+            fallback: self.fallback,
+            failover,
+            mirrors: self
+                .mirrors
+                .into_iter()
+                .map(Mirror::new)
+                .collect::<Result<_, _>>()?,
+            transport,
+        };
+
+        match self.spool_config {
+            Some(spool_config) => analytics.with_spool(spool_config),
+            None => Ok(analytics),
+        }
+    }
+
+    /// Validate the configuration and build a client that queues messages
+    /// and delivers them in batches from a background thread.
+    pub fn build_buffered(self) -> Result<BufferedRudderAnalytics, AnalyticsError> {
+        let buffer_config = self.buffer_config.clone();
+        Ok(BufferedRudderAnalytics::new(self.build()?, buffer_config))
+    }
+
+    fn has_client_settings(&self) -> bool {
+        let has_tls_settings = {
+            #[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+            {
+                !self.root_certificates.is_empty() || self.built_in_root_certs.is_some()
+            }
+            #[cfg(not(any(feature = "default-tls", feature = "rustls-tls")))]
+            {
+                false
+            }
+        };
+
+        self.connect_timeout.is_some()
+            || self.timeout.is_some()
+            || !self.proxies.is_empty()
+            || self.user_agent.is_some()
+            || !self.headers.is_empty()
+            || has_tls_settings
+    }
+
+    fn build_http_client(&self) -> Result<reqwest::blocking::Client, AnalyticsError> {
+        if let Some(client) = &self.http_client {
+            if self.has_client_settings() {
+                return Err(AnalyticsError::InvalidConfig(String::from(
+                    "http client settings cannot be combined with a pre-built http client",
+                )));
+            }
+            return Ok(client.clone());
+        }
+
+        let mut builder = reqwest::blocking::Client::builder()
+            .connect_timeout(self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT))
+            .default_headers(parse_headers(&self.headers)?);
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        for proxy in &self.proxies {
+            builder = builder.proxy(proxy.clone());
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent.as_str());
+        }
+        #[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+        {
+            for certificate in &self.root_certificates {
+                builder = builder.add_root_certificate(certificate.clone());
+            }
+            if let Some(enabled) = self.built_in_root_certs {
+                builder = builder.tls_built_in_root_certs(enabled);
+            }
+        }
+
+        builder
+            .build()
+            .map_err(|err| AnalyticsError::InvalidConfig(format!("http client: {}", err)))
+    }
+}
+
+// Check that the url is an absolute http(s) url and strip any trailing
+// slash, since request paths are appended to it.
+pub(crate) fn validate_data_plane_url(data_plane_url: &str) -> Result<String, AnalyticsError> {
+    let url = Url::parse(data_plane_url.trim()).map_err(|err| {
+        AnalyticsError::InvalidConfig(format!(
+            "data plane url {:?} is invalid: {}",
+            data_plane_url, err
+        ))
+    })?;
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(AnalyticsError::InvalidConfig(format!(
+            "data plane url {:?} must use http or https",
+            data_plane_url
+        )));
+    }
+    if url.host_str().is_none() {
+        return Err(AnalyticsError::InvalidConfig(format!(
+            "data plane url {:?} has no host",
+            data_plane_url
+        )));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(AnalyticsError::InvalidConfig(format!(
+            "data plane url {:?} must not have a query or fragment",
+            data_plane_url
+        )));
+    }
+
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
+
+fn validate_default_context(context: &Value) -> Result<(), AnalyticsError> {
+    if !context.is_object() {
+        return Err(AnalyticsError::InvalidConfig(String::from(
+            "default context must be a JSON object",
+        )));
+    }
+    if utils::check_reserved_keywords_conflict(context.clone()) {
+        return Err(AnalyticsError::InvalidConfig(String::from(
+            "Reserve keyword present in default context",
+        )));
+    }
+    Ok(())
+}
+
+fn parse_headers(headers: &[(String, String)]) -> Result<HeaderMap, AnalyticsError> {
+    let mut header_map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+            AnalyticsError::InvalidConfig(format!("invalid header name {:?}", name))
+        })?;
+        let value = HeaderValue::from_str(value).map_err(|_| {
+            AnalyticsError::InvalidConfig(format!("invalid value for header {:?}", name))
+        })?;
+        header_map.append(name, value);
+    }
+    Ok(header_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_trailing_slash() {
+        assert_eq!(
+            validate_data_plane_url("https://example.com/").unwrap(),
+            "https://example.com"
+        );
+        assert_eq!(
+            validate_data_plane_url("http://localhost:8080/rudder//").unwrap(),
+            "http://localhost:8080/rudder"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_data_plane_urls() {
+        for url in [
+            "",
+            "example.com",
+            "ftp://example.com",
+            "https://example.com/?a=b",
+            "https://example.com/#fragment",
+        ] {
+            match validate_data_plane_url(url) {
+                Err(AnalyticsError::InvalidConfig(_)) => {}
+                other => panic!("expected {:?} to be rejected, got {:?}", url, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Offline mode that captures payloads instead of sending them.
+//!
+//! A client with a `CaptureSink` runs the whole send pipeline (validation,
+//! the tracking plan, default context, redaction and middleware) and hands
+//! the resulting payloads to the sink instead of the data plane. No network
+//! calls are made, and the payloads are counted as `events_captured` rather
+//! than delivered. `MemorySink` keeps them for assertions in tests, and
+//! `FileSink` appends them to a newline delimited JSON file.
+//!
+//! ```no_run
+//! use rudderanalytics::capture::MemorySink;
+//! use rudderanalytics::client::RudderAnalytics;
+//! use rudderanalytics::message::{Message, Track};
+//!
+//! let sink = MemorySink::new();
+//! let rudder_analytics = RudderAnalytics::builder(
+//!     "WRITE_KEY".to_string(),
+//!     "https://hosted.rudderlabs.com".to_string(),
+//! )
+//! .capture(sink.clone())
+//! .build()
+//! .unwrap();
+//!
+//! rudder_analytics
+//!     .send(&Message::Track(Track {
+//!         user_id: Some("user-1".to_string()),
+//!         event: "Signup".to_string(),
+//!         ..Default::default()
+//!     }))
+//!     .unwrap();
+//!
+//! let captured = sink.drain();
+//! assert_eq!(captured[0].path, "/v1/track");
+//! ```
+
+use crate::errors::Error as AnalyticsError;
+use crate::ruddermessage::Ruddermessage;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Receives the payloads a capturing client would have sent.
+pub trait CaptureSink: Send + Sync {
+    /// Record a payload that would have been posted to `path`. An error fails
+    /// the `send` call that produced the payload.
+    fn capture(&self, path: &str, payload: &Ruddermessage) -> Result<(), AnalyticsError>;
+}
+
+/// A payload recorded by a capture sink.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Captured {
+    /// The API path the payload would have been posted to, e.g. `/v1/track`.
+    pub path: String,
+    pub payload: Ruddermessage,
+}
+
+/// Keeps captured payloads in memory until they are drained.
+///
+/// Clones share the same buffer, so keep one clone to inspect what a client
+/// holding the other has captured.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    captured: Arc<Mutex<Vec<Captured>>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take every payload captured so far, oldest first.
+    pub fn drain(&self) -> Vec<Captured> {
+        std::mem::take(&mut *self.captured.lock().unwrap())
+    }
+
+    /// Number of payloads waiting to be drained.
+    pub fn len(&self) -> usize {
+        self.captured.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl CaptureSink for MemorySink {
+    fn capture(&self, path: &str, payload: &Ruddermessage) -> Result<(), AnalyticsError> {
+        self.captured.lock().unwrap().push(Captured {
+            path: path.to_string(),
+            payload: payload.clone(),
+        });
+        Ok(())
+    }
+}
+
+/// Appends captured payloads to a file, one JSON object with `path` and
+/// `payload` keys per line.
+#[derive(Debug)]
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    /// Open `path` for appending, creating it if it does not exist.
+    pub fn new(path: impl AsRef<Path>) -> Result<FileSink, AnalyticsError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(AnalyticsError::CaptureError)?;
+        Ok(FileSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl CaptureSink for FileSink {
+    fn capture(&self, path: &str, payload: &Ruddermessage) -> Result<(), AnalyticsError> {
+        let mut line = serde_json::to_vec(&Captured {
+            path: path.to_string(),
+            payload: payload.clone(),
+        })?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&line)
+            .and_then(|()| file.flush())
+            .map_err(AnalyticsError::CaptureError)
+    }
+}
@@ -0,0 +1,170 @@
+//! A circuit breaker that stops requests to an unavailable data plane.
+//!
+//! The breaker counts consecutive retryable failures (429s, 5xx responses,
+//! connection errors and timeouts) across every thread using the client.
+//! Once `failure_threshold` is reached it opens, and deliveries fail with
+//! `Error::CircuitOpen` without making a request. After `open_duration` it
+//! turns half-open and lets a single probe request through: a success
+//! closes it, a failure opens it again.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// When the circuit breaker opens and for how long.
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive retryable failures that open the circuit.
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before a probe request is allowed.
+    pub open_duration: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold,
+            open_duration,
+        }
+    }
+}
+
+/// The state of a circuit breaker, e.g. for health checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests are made as usual.
+    Closed,
+    /// Requests fail without being made.
+    Open,
+    /// The open duration has passed and a probe request may be made.
+    HalfOpen,
+}
+
+pub(crate) struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    circuit: Mutex<Circuit>,
+}
+
+struct Circuit {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            circuit: Mutex::new(Circuit {
+                consecutive_failures: 0,
+                opened_at: None,
+                probing: false,
+            }),
+        }
+    }
+
+    pub(crate) fn state(&self) -> CircuitState {
+        let circuit = self.circuit.lock().unwrap();
+        self.state_of(&circuit)
+    }
+
+    /// Whether a request may be made now. In the half-open state only the
+    /// first caller is allowed through, as the probe.
+    pub(crate) fn try_acquire(&self) -> bool {
+        let mut circuit = self.circuit.lock().unwrap();
+        match self.state_of(&circuit) {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen if circuit.probing => false,
+            CircuitState::HalfOpen => {
+                circuit.probing = true;
+                true
+            }
+        }
+    }
+
+    /// Record the outcome of a request allowed by `try_acquire`.
+    pub(crate) fn record(&self, retryable_failure: bool) {
+        let mut circuit = self.circuit.lock().unwrap();
+        let was_probe = circuit.probing;
+        circuit.probing = false;
+        if !retryable_failure {
+            circuit.consecutive_failures = 0;
+            circuit.opened_at = None;
+            return;
+        }
+
+        circuit.consecutive_failures = circuit.consecutive_failures.saturating_add(1);
+        if was_probe || circuit.consecutive_failures >= self.config.failure_threshold {
+            circuit.opened_at = Some(Instant::now());
+        }
+    }
+
+    fn state_of(&self, circuit: &Circuit) -> CircuitState {
+        match circuit.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.config.open_duration => {
+                CircuitState::Open
+            }
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(open_duration: Duration) -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig::new(2, open_duration))
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = breaker(Duration::from_secs(60));
+
+        breaker.record(true);
+        breaker.record(false);
+        breaker.record(true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        breaker.record(true);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.try_acquire());
+    }
+
+    #[test]
+    fn half_open_allows_a_single_probe() {
+        let breaker = breaker(Duration::from_millis(0));
+        breaker.record(true);
+        breaker.record(true);
+
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.try_acquire());
+        assert!(!breaker.try_acquire());
+
+        breaker.record(false);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.try_acquire());
+    }
+
+    #[test]
+    fn failed_probe_reopens_the_circuit() {
+        let breaker = breaker(Duration::from_millis(50));
+        breaker.record(true);
+        breaker.record(true);
+        std::thread::sleep(Duration::from_millis(60));
+
+        assert!(breaker.try_acquire());
+        breaker.record(true);
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+}
@@ -1,24 +1,77 @@
+use crate::builder::RudderAnalyticsBuilder;
+use crate::capture::CaptureSink;
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig, CircuitState};
+use crate::compression::{encode_body, CompressionConfig};
+use crate::config::ClientConfig;
 use crate::errors::Error as AnalyticsError;
+use crate::failover::{EndpointHealth, Failover, FailoverConfig};
 use crate::message::Message;
-use crate::retry::{is_error_retryable, is_status_retryable, retry_delay, RetryConfig};
+use crate::middleware::{self, Middleware};
+use crate::mirror::{Destination, DestinationStats, Mirror};
+use crate::rate_limit::{RateLimitConfig, RateLimiter};
+use crate::redaction::{RedactionConfig, Redactor};
+use crate::retry::{is_status_retryable, RetryConfig, RetryState};
 use crate::ruddermessage::Ruddermessage;
+use crate::sampling::{Sampler, SamplingConfig};
+use crate::spool::{ReplayError, Spool, SpoolConfig};
+use crate::stats::{DeliveryEvent, DeliveryObserver, DeliveryStats, StatsSnapshot};
+use crate::tracking_plan::{PlanValidation, ValidationMode, Validator};
+use crate::transport::{request_headers, Transport, TransportErrorKind, TransportRequest};
 use crate::utils;
-use log::debug;
-use reqwest::blocking::Response;
-use reqwest::StatusCode;
+use log::{debug, error};
+use serde::Serialize;
 use serde_json::Value;
+use std::path::Path;
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+pub(crate) const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
 // Rudderanalytics client
 pub struct RudderAnalytics {
     pub write_key: String,
     pub data_plane_url: String,
-    pub client: reqwest::blocking::Client,
     pub retry_config: RetryConfig,
+    pub compression: CompressionConfig,
+    pub default_context: Option<Value>,
+    pub(crate) spool: Option<Spool>,
+    pub(crate) tracking_plan: Option<Arc<PlanValidation>>,
+    pub(crate) middleware: Vec<Box<dyn Middleware>>,
+    pub(crate) redactor: Option<Redactor>,
+    pub(crate) rate_limiter: Option<Arc<RateLimiter>>,
+    pub(crate) stats: Arc<DeliveryStats>,
+    pub(crate) observers: Vec<Box<dyn DeliveryObserver>>,
+    pub(crate) capture: Option<Box<dyn CaptureSink>>,
+    pub(crate) circuit_breaker: Option<Arc<CircuitBreaker>>,
+    pub(crate) fallback: Option<Box<dyn CaptureSink>>,
+    pub(crate) failover: Option<Arc<Failover>>,
+    pub(crate) mirrors: Vec<Mirror>,
+    pub(crate) transport: Box<dyn Transport>,
 }
 
 impl RudderAnalytics {
+    /// Start configuring a client with a `RudderAnalyticsBuilder`.
+    pub fn builder(write_key: String, data_plane_url: String) -> RudderAnalyticsBuilder {
+        RudderAnalyticsBuilder::new(write_key, data_plane_url)
+    }
+
+    /// Build a client from the `RUDDER_WRITE_KEY` and `RUDDER_DATA_PLANE_URL`
+    /// environment variables.
+    pub fn from_env() -> Result<RudderAnalytics, AnalyticsError> {
+        ClientConfig::from_env().builder()?.build()
+    }
+
+    /// Build a client from a JSON or TOML config file. `RUDDER_WRITE_KEY` and
+    /// `RUDDER_DATA_PLANE_URL` override the write key and data plane url of
+    /// the file when they are set.
+    pub fn from_config_file(path: impl AsRef<Path>) -> Result<RudderAnalytics, AnalyticsError> {
+        ClientConfig::from_file(path)?
+            .merge(ClientConfig::from_env())
+            .builder()?
+            .build()
+    }
+
     // Function to initialize the Rudderanalytics client with write-key and data-plane-url
     pub fn load(write_key: String, data_plane_url: String) -> RudderAnalytics {
         Self::load_with_retry_config(write_key, data_plane_url, RetryConfig::default())
@@ -33,92 +86,496 @@ impl RudderAnalytics {
         RudderAnalytics {
             write_key,
             data_plane_url,
-            client: reqwest::blocking::Client::builder()
-                .connect_timeout(Duration::new(10, 0))
-                .build()
-                .unwrap(),
             retry_config,
+            compression: CompressionConfig::default(),
+            default_context: None,
+            spool: None,
+            tracking_plan: None,
+            middleware: Vec::new(),
+            redactor: None,
+            rate_limiter: None,
+            stats: Arc::default(),
+            observers: Vec::new(),
+            capture: None,
+            circuit_breaker: None,
+            fallback: None,
+            failover: None,
+            mirrors: Vec::new(),
+            transport: Box::new(
+                reqwest::blocking::Client::builder()
+                    .connect_timeout(DEFAULT_CONNECT_TIMEOUT)
+                    .build()
+                    .unwrap(),
+            ),
         }
     }
 
+    /// Persist events that fail with transient errors into an on-disk spool
+    /// instead of dropping them once the retry budget is spent.
+    ///
+    /// Spooled events are delivered by `replay_spool`, which should be called
+    /// on startup, or periodically by a `spool::SpoolReplayer`.
+    pub fn with_spool(mut self, config: SpoolConfig) -> Result<RudderAnalytics, AnalyticsError> {
+        self.spool = Some(Spool::open(config)?);
+        Ok(self)
+    }
+
+    /// Check track properties and identify traits with `validator` before
+    /// events are sent, handling violations according to `mode`.
+    pub fn with_validator(
+        mut self,
+        validator: impl Validator + 'static,
+        mode: ValidationMode,
+    ) -> RudderAnalytics {
+        self.tracking_plan = Some(Arc::new(PlanValidation::new(Box::new(validator), mode)));
+        self
+    }
+
+    /// Redact personal data from `traits`, `properties` and `context` when
+    /// events are converted to the Ruddermessage format, before they reach
+    /// any middleware, the spool or the network.
+    pub fn with_redaction(
+        mut self,
+        config: RedactionConfig,
+    ) -> Result<RudderAnalytics, AnalyticsError> {
+        self.redactor = Some(Redactor::new(config)?);
+        Ok(self)
+    }
+
+    /// Sample track events before they are sent. The sampler runs ahead of
+    /// any other middleware.
+    pub fn with_sampling(
+        mut self,
+        config: SamplingConfig,
+    ) -> Result<RudderAnalytics, AnalyticsError> {
+        self.middleware.insert(0, Box::new(Sampler::new(config)?));
+        Ok(self)
+    }
+
+    /// Fail `send` with `Error::RateLimited` once events are sent faster than
+    /// the configured rate. Tokens are taken after events are sampled, so
+    /// sampled out events do not count.
+    pub fn with_rate_limit(
+        mut self,
+        config: RateLimitConfig,
+    ) -> Result<RudderAnalytics, AnalyticsError> {
+        self.rate_limiter = Some(Arc::new(RateLimiter::new(config)?));
+        Ok(self)
+    }
+
+    /// Append `middleware` to the chain that every outgoing event is passed
+    /// through after it has been converted to a `Ruddermessage`.
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> RudderAnalytics {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Call `observer` with every delivery event, e.g. to report them to a
+    /// metrics system.
+    pub fn with_observer(mut self, observer: impl DeliveryObserver + 'static) -> RudderAnalytics {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Make requests to the data plane with `transport` instead of the
+    /// default reqwest client.
+    pub fn with_transport(mut self, transport: impl Transport + 'static) -> RudderAnalytics {
+        self.transport = Box::new(transport);
+        self
+    }
+
+    /// Hand every payload to `sink` instead of sending it to the data plane.
+    pub fn with_capture(mut self, sink: impl CaptureSink + 'static) -> RudderAnalytics {
+        self.capture = Some(Box::new(sink));
+        self
+    }
+
+    /// Stop making requests after repeated retryable failures. While the
+    /// circuit is open, deliveries fail with `Error::CircuitOpen`, or go to
+    /// the fallback sink if one is set.
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> RudderAnalytics {
+        self.circuit_breaker = Some(Arc::new(CircuitBreaker::new(config)));
+        self
+    }
+
+    /// Hand payloads to `sink` instead of failing while the circuit breaker
+    /// is open. Has no effect without a circuit breaker.
+    pub fn with_fallback(mut self, sink: impl CaptureSink + 'static) -> RudderAnalytics {
+        self.fallback = Some(Box::new(sink));
+        self
+    }
+
+    /// The state of the circuit breaker, if one is configured.
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.circuit_breaker
+            .as_ref()
+            .map(|circuit_breaker| circuit_breaker.state())
+    }
+
+    /// Send requests to the endpoints of `config` instead of a single data
+    /// plane url, moving to the next endpoint on connection errors and 5xx
+    /// responses. `data_plane_url` is set to the first endpoint.
+    pub fn with_failover(
+        mut self,
+        config: FailoverConfig,
+    ) -> Result<RudderAnalytics, AnalyticsError> {
+        let failover = Failover::new(config)?;
+        self.data_plane_url = failover.url(0).to_string();
+        self.failover = Some(Arc::new(failover));
+        Ok(self)
+    }
+
+    /// The health of each failover endpoint, in the configured order. Empty
+    /// without failover.
+    pub fn endpoint_health(&self) -> Vec<EndpointHealth> {
+        self.failover
+            .as_ref()
+            .map(|failover| failover.health())
+            .unwrap_or_default()
+    }
+
+    /// Also deliver every payload, or a sampled fraction of them, to
+    /// `destination`. May be called more than once.
+    ///
+    /// Mirrors are delivered on the sending thread after the client's own
+    /// data plane, so `send` also waits for each destination, including any
+    /// retries set in its `retry_config`.
+    pub fn with_mirror(
+        mut self,
+        destination: Destination,
+    ) -> Result<RudderAnalytics, AnalyticsError> {
+        self.mirrors.push(Mirror::new(destination)?);
+        Ok(self)
+    }
+
+    /// Delivery outcomes for each mirror destination, in the order they were
+    /// added.
+    pub fn mirror_stats(&self) -> Vec<DestinationStats> {
+        self.mirrors.iter().map(Mirror::stats).collect()
+    }
+
+    /// Delivery counters since the client was created.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// The on-disk spool, if one is configured.
+    pub fn spool(&self) -> Option<&Spool> {
+        self.spool.as_ref()
+    }
+
+    /// Deliver events from the spool, oldest first, returning how many were
+    /// delivered. Replay stops at the first transient failure; the remaining
+    /// events stay in the spool for the next attempt. Replayed events are
+    /// sent with a fresh `sentAt`.
+    pub fn replay_spool(&self) -> Result<usize, AnalyticsError> {
+        let spool = match &self.spool {
+            Some(spool) => spool,
+            None => return Ok(0),
+        };
+
+        spool.replay(|path, payload| {
+            let mut retry = RetryState::new(&self.retry_config);
+            match self.deliver(path, payload, &mut retry) {
+                Ok(()) => Ok(()),
+                Err(err) if retry.failed_transiently() => Err(ReplayError::Transient(err)),
+                Err(err) => Err(ReplayError::Rejected(err)),
+            }
+        })
+    }
+
     // Function that will receive user event data
     // and after validation
     // modify it to Ruddermessage format and send the event to data plane url
     pub fn send(&self, msg: &Message) -> Result<(), AnalyticsError> {
-        let path = validate_and_path(msg)?;
-        let rudder_message = parse_rudder_message(msg);
-        let retry_config = &self.retry_config;
-        let mut retries = 0;
+        validate_and_path(msg)?;
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.check(msg)?;
+        }
+        let annotated = match &self.tracking_plan {
+            Some(tracking_plan) => tracking_plan.apply(msg)?,
+            None => None,
+        };
+        let (events, rudder_messages) = self.prepare_checked(annotated.as_ref().unwrap_or(msg))?;
+        // only the events left after sampling and middleware count
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(rudder_messages.iter().map(event_count).sum())?;
+        }
+        self.send_prepared(events, rudder_messages)
+    }
+
+    /// Run everything `send` does short of delivery and return the payloads
+    /// it would post: validation, the tracking plan, the default context,
+    /// redaction and middleware. The rate limit is not consumed.
+    pub fn prepare(&self, msg: &Message) -> Result<Vec<Ruddermessage>, AnalyticsError> {
+        validate_and_path(msg)?;
+        let annotated = match &self.tracking_plan {
+            Some(tracking_plan) => tracking_plan.apply(msg)?,
+            None => None,
+        };
+        let (_, rudder_messages) = self.prepare_checked(annotated.as_ref().unwrap_or(msg))?;
+        Ok(rudder_messages)
+    }
+
+    // Build the payloads for a message that has already been checked against
+    // the tracking plan, along with the number of events that went into the
+    // middleware chain.
+    fn prepare_checked(
+        &self,
+        msg: &Message,
+    ) -> Result<(usize, Vec<Ruddermessage>), AnalyticsError> {
+        validate_and_path(msg)?;
+        let mut rudder_message = parse_rudder_message(msg);
+        if let Some(default_context) = &self.default_context {
+            utils::apply_default_context(&mut rudder_message, default_context);
+        }
+        if let Some(redactor) = &self.redactor {
+            redactor.redact(&mut rudder_message);
+        }
+
+        let events = event_count(&rudder_message);
+        Ok((
+            events,
+            middleware::run_chain(&self.middleware, rudder_message),
+        ))
+    }
+
+    // Send a message that has already been checked against the tracking plan.
+    // Every message the middleware chain produces is sent, and the first
+    // failure is returned. Events over the rate limit are dropped rather than
+    // failing the whole message, since the buffered worker has no caller to
+    // return `Error::RateLimited` to.
+    pub(crate) fn send_checked(&self, msg: &Message) -> Result<(), AnalyticsError> {
+        let (events, mut rudder_messages) = self.prepare_checked(msg)?;
+        if let Some(rate_limiter) = &self.rate_limiter {
+            let mut tokens =
+                rate_limiter.acquire_up_to(rudder_messages.iter().map(event_count).sum());
+            rudder_messages.retain_mut(|rudder_message| {
+                let kept = truncate_events(rudder_message, tokens);
+                tokens -= kept;
+                kept > 0
+            });
+        }
+        self.send_prepared(events, rudder_messages)
+    }
+
+    // Send the payloads built from `events` events by `prepare_checked`.
+    fn send_prepared(
+        &self,
+        events: usize,
+        rudder_messages: Vec<Ruddermessage>,
+    ) -> Result<(), AnalyticsError> {
+        let sent_events: usize = rudder_messages.iter().map(event_count).sum();
+        if sent_events < events {
+            self.emit(DeliveryEvent::Dropped {
+                events: events - sent_events,
+            });
+        }
+
+        let mut result = Ok(());
+        for rudder_message in rudder_messages {
+            result = result.and(self.send_rudder_message(&rudder_message));
+        }
+        result
+    }
+
+    fn send_rudder_message(&self, rudder_message: &Ruddermessage) -> Result<(), AnalyticsError> {
+        let path = rudder_message_path(rudder_message);
+        let mut retry = RetryState::new(&self.retry_config);
 
         debug!("rudder_message: {:#?}", rudder_message);
 
-        loop {
-            let attempt = retries + 1;
-            match self.post(path, &rudder_message) {
-                Ok(res) => {
-                    let status = res.status();
-                    if status.is_success() {
-                        return Ok(());
-                    }
+        let events = event_count(rudder_message);
+        if let Some(capture) = &self.capture {
+            let result = capture.capture(path, rudder_message);
+            match &result {
+                Ok(()) => self.emit(DeliveryEvent::Captured { path, events }),
+                Err(_) => self.emit(DeliveryEvent::Failed { path, events }),
+            }
+            return result;
+        }
 
-                    let can_retry = retry_config.enabled
-                        && is_status_retryable(status)
-                        && retries < retry_config.max_retries;
-
-                    if !can_retry {
-                        return Err(AnalyticsError::InvalidRequest(invalid_request_message(
-                            status,
-                            attempt,
-                            retry_config.enabled
-                                && is_status_retryable(status)
-                                && retries >= retry_config.max_retries,
-                        )));
-                    }
+        let result = self.deliver(path, rudder_message, &mut retry);
+        self.send_mirrors(path, rudder_message, events);
+        if let (Err(AnalyticsError::CircuitOpen), Some(fallback)) = (&result, &self.fallback) {
+            if fallback.capture(path, rudder_message).is_ok() {
+                self.emit(DeliveryEvent::Diverted { path, events });
+                return Ok(());
+            }
+        }
+        match &result {
+            Ok(()) => self.emit(DeliveryEvent::Delivered { path, events }),
+            Err(_) => self.emit(DeliveryEvent::Failed { path, events }),
+        }
+        if result.is_err() && retry.failed_transiently() {
+            self.spool_message(path, rudder_message);
+        }
+        result
+    }
 
-                    retries += 1;
-                    let delay = retry_delay(retry_config, retries, Some(res.headers()));
+    fn deliver<T: Serialize>(
+        &self,
+        path: &str,
+        body: &T,
+        retry: &mut RetryState,
+    ) -> Result<(), AnalyticsError> {
+        let body = encode_body(&self.compression, body)?;
+        let headers = request_headers(&self.write_key, body.gzipped);
+        let mut endpoint = self.failover.as_ref().map(|failover| failover.select());
+        loop {
+            if let Some(circuit_breaker) = &self.circuit_breaker {
+                if !circuit_breaker.try_acquire() {
+                    return Err(retry.circuit_open());
+                }
+            }
+            let started = Instant::now();
+            let data_plane_url = match (&self.failover, endpoint) {
+                (Some(failover), Some(index)) => failover.url(index),
+                _ => &self.data_plane_url,
+            };
+            let response = self.transport.send(&TransportRequest {
+                url: format!("{}{}", data_plane_url, path),
+                path,
+                headers: &headers,
+                body: &body.bytes,
+            });
+            self.emit(DeliveryEvent::Attempt {
+                path,
+                status: response.as_ref().ok().map(|res| res.status.as_u16()),
+                latency: started.elapsed(),
+                bytes: body.bytes.len(),
+            });
+            if let Some(circuit_breaker) = &self.circuit_breaker {
+                circuit_breaker.record(match &response {
+                    Ok(res) => is_status_retryable(res.status),
+                    Err(err) => err.is_retryable(),
+                });
+            }
+            let endpoint_failed = match &response {
+                Ok(res) => res.status.is_server_error(),
+                Err(err) => err.kind() == TransportErrorKind::Connect,
+            };
+            if let (Some(failover), Some(index)) = (&self.failover, endpoint) {
+                failover.record(index, endpoint_failed);
+            }
+            // A healthy endpoint is not affected by this one's backoff, so
+            // retry on it right away. With every endpoint down, the retry
+            // waits as usual.
+            let mut next_endpoint = endpoint;
+            if let (Some(failover), Some(index), true) = (&self.failover, endpoint, endpoint_failed)
+            {
+                let next = failover.select();
+                if next != index {
                     debug!(
-                        "retrying request after status {} in {:?} (attempt {} of {})",
-                        status,
-                        delay,
-                        attempt,
-                        retry_config.max_retries + 1
+                        "failing over from {} to {}",
+                        failover.url(index),
+                        failover.url(next)
                     );
-                    sleep_retry_delay(delay);
+                    next_endpoint = Some(next);
+                    if failover.healthy(next) {
+                        retry.skip_backoff();
+                    }
                 }
-                Err(err) => {
-                    let can_retry = retry_config.enabled
-                        && is_error_retryable(&err)
-                        && retries < retry_config.max_retries;
+            }
 
-                    if !can_retry {
-                        return Err(AnalyticsError::SendRequestError(err));
-                    }
+            let (status, delay) = match response {
+                Ok(res) if res.status.is_success() => return Ok(()),
+                Ok(res) => (
+                    Some(res.status.as_u16()),
+                    retry.retry_status(res.status, &res.headers, res.body)?,
+                ),
+                Err(err) => (None, retry.retry_error(err)?),
+            };
+            if self.circuit_state() == Some(CircuitState::Open) {
+                return Err(retry.circuit_open());
+            }
+            endpoint = next_endpoint;
+            self.emit(DeliveryEvent::Retry {
+                path,
+                status,
+                delay,
+            });
+            sleep_retry_delay(delay);
+        }
+    }
 
-                    retries += 1;
-                    let delay = retry_delay(retry_config, retries, None);
-                    debug!(
-                        "retrying request after transport error in {:?} (attempt {} of {})",
-                        delay,
-                        attempt,
-                        retry_config.max_retries + 1
-                    );
-                    sleep_retry_delay(delay);
-                }
+    // Deliver a payload to every mirror, whatever happened on the client's
+    // own data plane.
+    fn send_mirrors(&self, path: &str, rudder_message: &Ruddermessage, events: usize) {
+        for mirror in &self.mirrors {
+            if !mirror.sample(events) {
+                continue;
+            }
+            let result = self.deliver_mirror(mirror, path, rudder_message);
+            if let Err(err) = &result {
+                error!("failed to deliver to mirror {}: {}", mirror.name(), err);
+            }
+            mirror.record(events, &result);
+        }
+    }
+
+    // Deliver a payload to a mirror destination, with its own write key,
+    // retries and circuit breaker but without the client's stats or
+    // failover, which describe the primary data plane.
+    fn deliver_mirror(
+        &self,
+        mirror: &Mirror,
+        path: &str,
+        rudder_message: &Ruddermessage,
+    ) -> Result<(), AnalyticsError> {
+        let body = encode_body(&self.compression, rudder_message)?;
+        let headers = request_headers(&mirror.write_key, body.gzipped);
+        let mut retry = RetryState::new(&mirror.retry_config);
+        loop {
+            if !mirror.circuit_breaker.try_acquire() {
+                return Err(retry.circuit_open());
             }
+            let response = self.transport.send(&TransportRequest {
+                url: format!("{}{}", mirror.data_plane_url, path),
+                path,
+                headers: &headers,
+                body: &body.bytes,
+            });
+            mirror.circuit_breaker.record(match &response {
+                Ok(res) => is_status_retryable(res.status),
+                Err(err) => err.is_retryable(),
+            });
+            let delay = match response {
+                Ok(res) if res.status.is_success() => return Ok(()),
+                Ok(res) => retry.retry_status(res.status, &res.headers, res.body)?,
+                Err(err) => retry.retry_error(err)?,
+            };
+            if mirror.circuit_breaker.state() == CircuitState::Open {
+                return Err(retry.circuit_open());
+            }
+            sleep_retry_delay(delay);
+        }
+    }
+
+    pub(crate) fn emit(&self, event: DeliveryEvent) {
+        self.stats.record(&event);
+        for observer in &self.observers {
+            observer.observe(&event);
         }
     }
 
-    fn post(&self, path: &str, rudder_message: &Ruddermessage) -> Result<Response, reqwest::Error> {
-        self.client
-            .post(&format!("{}{}", self.data_plane_url, path))
-            .basic_auth(self.write_key.to_string(), Some(""))
-            .json(rudder_message)
-            .send()
+    fn spool_message(&self, path: &str, rudder_message: &Ruddermessage) {
+        if let Some(spool) = &self.spool {
+            match spool.append(path, rudder_message) {
+                Ok(()) => debug!("spooled undelivered event for {}", path),
+                Err(err) => error!("failed to spool undelivered event: {:?}", err),
+            }
+        }
     }
 }
 
-fn validate_and_path(msg: &Message) -> Result<&'static str, AnalyticsError> {
+/// Check a message the way `send` does before delivering it. Members of a
+/// batch are not checked individually.
+pub fn validate(msg: &Message) -> Result<(), AnalyticsError> {
+    validate_and_path(msg).map(|_| ())
+}
+
+pub(crate) fn validate_and_path(msg: &Message) -> Result<&'static str, AnalyticsError> {
     match msg {
         Message::Identify(b_) => {
             validate_user_or_anonymous_id(&b_.user_id, &b_.anonymous_id)?;
@@ -156,14 +613,45 @@ fn validate_and_path(msg: &Message) -> Result<&'static str, AnalyticsError> {
     }
 }
 
+fn event_count(rudder_message: &Ruddermessage) -> usize {
+    match rudder_message {
+        Ruddermessage::Batch(b_) => b_.batch.len(),
+        _ => 1,
+    }
+}
+
+// Keep at most `max` events of a payload and return how many are left.
+fn truncate_events(rudder_message: &mut Ruddermessage, max: usize) -> usize {
+    match rudder_message {
+        Ruddermessage::Batch(b_) => {
+            b_.batch.truncate(max);
+            b_.batch.len()
+        }
+        _ => max.min(1),
+    }
+}
+
+fn rudder_message_path(rudder_message: &Ruddermessage) -> &'static str {
+    match rudder_message {
+        Ruddermessage::Identify(_) => "/v1/identify",
+        Ruddermessage::Track(_) => "/v1/track",
+        Ruddermessage::Page(_) => "/v1/page",
+        Ruddermessage::Screen(_) => "/v1/screen",
+        Ruddermessage::Group(_) => "/v1/group",
+        Ruddermessage::Alias(_) => "/v1/alias",
+        Ruddermessage::Batch(_) => "/v1/batch",
+    }
+}
+
 fn validate_user_or_anonymous_id(
     user_id: &Option<String>,
     anonymous_id: &Option<String>,
 ) -> Result<(), AnalyticsError> {
     if user_id.is_none() && anonymous_id.is_none() {
-        Err(AnalyticsError::InvalidRequest(String::from(
-            "Either of user_id or anonymous_id is required",
-        )))
+        Err(AnalyticsError::Validation {
+            field: String::from("user_id"),
+            message: String::from("Either of user_id or anonymous_id is required"),
+        })
     } else {
         Ok(())
     }
@@ -175,15 +663,16 @@ fn validate_context(context: &Option<Value>) -> Result<(), AnalyticsError> {
         .map(|context| utils::check_reserved_keywords_conflict(context.clone()))
         .unwrap_or(false)
     {
-        Err(AnalyticsError::InvalidRequest(String::from(
-            "Reserve keyword present in context",
-        )))
+        Err(AnalyticsError::Validation {
+            field: String::from("context"),
+            message: String::from("Reserve keyword present in context"),
+        })
     } else {
         Ok(())
     }
 }
 
-fn parse_rudder_message(msg: &Message) -> Ruddermessage {
+pub(crate) fn parse_rudder_message(msg: &Message) -> Ruddermessage {
     match msg {
         Message::Identify(b_) => utils::parse_identify(b_),
         Message::Track(b_) => utils::parse_track(b_),
@@ -195,22 +684,133 @@ fn parse_rudder_message(msg: &Message) -> Ruddermessage {
     }
 }
 
-fn invalid_request_message(status: StatusCode, attempts: u32, retries_exhausted: bool) -> String {
-    if retries_exhausted {
-        format!(
-            "status code: {}, attempts: {}, message: retries exhausted",
-            status, attempts
-        )
-    } else {
-        format!(
-            "status code: {}, attempts: {}, message: Invalid request",
-            status, attempts
-        )
-    }
-}
-
 fn sleep_retry_delay(delay: Duration) {
     if delay > Duration::from_secs(0) {
         thread::sleep(delay);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Track;
+    use crate::transport::{TransportError, TransportErrorKind, TransportResponse};
+    use reqwest::header::{HeaderValue, AUTHORIZATION, RETRY_AFTER};
+    use reqwest::StatusCode;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    type Script = Arc<Mutex<VecDeque<Result<TransportResponse, TransportError>>>>;
+
+    // A client whose transport replays `responses` in order and records the
+    // url and headers of every request.
+    fn scripted(
+        responses: Vec<Result<TransportResponse, TransportError>>,
+    ) -> (RudderAnalytics, Arc<Mutex<Vec<String>>>) {
+        let script: Script = Arc::new(Mutex::new(responses.into()));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        let retry_config = RetryConfig {
+            max_retries: 2,
+            base_delay: Duration::from_millis(0),
+            jitter_ratio: 0.0,
+            ..Default::default()
+        };
+        let analytics = RudderAnalytics::load_with_retry_config(
+            "write-key".to_string(),
+            "https://dataplane.example.com".to_string(),
+            retry_config,
+        )
+        .with_transport(move |request: &TransportRequest| {
+            recorded.lock().unwrap().push(request.url.clone());
+            assert_eq!(
+                request.headers.get(AUTHORIZATION).unwrap(),
+                "Basic d3JpdGUta2V5Og=="
+            );
+            script
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("unexpected request")
+        });
+        (analytics, requests)
+    }
+
+    fn status(code: u16) -> Result<TransportResponse, TransportError> {
+        Ok(TransportResponse::new(StatusCode::from_u16(code).unwrap()))
+    }
+
+    fn connect_error() -> Result<TransportResponse, TransportError> {
+        Err(TransportError::new(
+            TransportErrorKind::Connect,
+            "connection refused",
+        ))
+    }
+
+    fn track() -> Message {
+        Message::Track(Track {
+            user_id: Some("user-1".to_string()),
+            event: "Test".to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn retries_retryable_status_until_success() {
+        let mut throttled = TransportResponse::new(StatusCode::TOO_MANY_REQUESTS);
+        throttled
+            .headers
+            .insert(RETRY_AFTER, HeaderValue::from_static("0"));
+        let (analytics, requests) = scripted(vec![Ok(throttled), status(503), status(200)]);
+
+        assert!(analytics.send(&track()).is_ok());
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec!["https://dataplane.example.com/v1/track"; 3]
+        );
+        assert_eq!(analytics.stats().retries(), 2);
+    }
+
+    #[test]
+    fn rejected_status_is_not_retried() {
+        let mut rejected = TransportResponse::new(StatusCode::BAD_REQUEST);
+        rejected.body = "invalid payload".to_string();
+        let (analytics, requests) = scripted(vec![Ok(rejected)]);
+
+        let err = analytics.send(&track()).unwrap_err();
+
+        assert!(matches!(err, AnalyticsError::Rejected { .. }));
+        assert_eq!(err.response_body(), Some("invalid payload"));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn transport_errors_are_retried_until_exhausted() {
+        let (analytics, requests) =
+            scripted(vec![connect_error(), connect_error(), connect_error()]);
+
+        match analytics.send(&track()) {
+            Err(AnalyticsError::RetriesExhausted {
+                attempts, source, ..
+            }) => {
+                assert_eq!(attempts, 3);
+                assert_eq!(source.unwrap().kind(), TransportErrorKind::Connect);
+            }
+            other => panic!("expected retries exhausted, got {:?}", other),
+        }
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn other_transport_errors_fail_immediately() {
+        let (analytics, requests) = scripted(vec![Err(TransportError::new(
+            TransportErrorKind::Other,
+            "invalid url",
+        ))]);
+
+        let result = analytics.send(&track());
+
+        assert!(matches!(result, Err(AnalyticsError::SendRequestError(_))));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,89 @@
+//! Optional gzip compression of request bodies.
+
+use crate::errors::Error as AnalyticsError;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::debug;
+use serde::Serialize;
+use std::io::Write;
+
+/// Configuration for gzip compression of request bodies.
+///
+/// Compression is disabled by default. When enabled, bodies of at least
+/// `min_size` bytes are gzipped and sent with `Content-Encoding: gzip`, so
+/// small single-event calls are not slowed down for no gain.
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    /// Whether request bodies may be compressed.
+    pub enabled: bool,
+    /// Bodies smaller than this many bytes are sent uncompressed.
+    pub min_size: usize,
+    /// gzip compression level, from 0 (none) to 9 (best).
+    pub level: u32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_size: 1024,
+            level: 6,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Return a configuration that gzips bodies of 1 KB or more.
+    pub fn gzip() -> Self {
+        Self {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// A serialized request body, ready to be sent on every attempt.
+pub(crate) struct RequestBody {
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) gzipped: bool,
+}
+
+pub(crate) fn encode_body<T: Serialize>(
+    config: &CompressionConfig,
+    body: &T,
+) -> Result<RequestBody, AnalyticsError> {
+    let bytes = serde_json::to_vec(body)?;
+    if !config.enabled || bytes.len() < config.min_size {
+        return Ok(RequestBody {
+            bytes,
+            gzipped: false,
+        });
+    }
+
+    match gzip(&bytes, config.level) {
+        Ok(compressed) => {
+            debug!(
+                "compressed body from {} to {} bytes",
+                bytes.len(),
+                compressed.len()
+            );
+            Ok(RequestBody {
+                bytes: compressed,
+                gzipped: true,
+            })
+        }
+        Err(err) => {
+            debug!("sending uncompressed body, gzip failed: {}", err);
+            Ok(RequestBody {
+                bytes,
+                gzipped: false,
+            })
+        }
+    }
+}
+
+fn gzip(bytes: &[u8], level: u32) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level.min(9)));
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
@@ -0,0 +1,208 @@
+//! Client settings read from the environment or from a config file.
+//!
+//! A config file is a JSON or TOML document (chosen by the `.toml`
+//! extension, which needs the `toml` feature) of the form:
+//!
+//! ```toml
+//! write_key = "WRITE_KEY"
+//! data_plane_url = "https://hosted.rudderlabs.com"
+//! timeout_ms = 5000
+//! connect_timeout_ms = 2000
+//!
+//! [retry]
+//! max_retries = 5
+//! base_delay_ms = 200
+//!
+//! [default_context.app]
+//! name = "my-service"
+//! ```
+//!
+//! Every key is optional, so a file can hold the shared settings while the
+//! write key comes from `RUDDER_WRITE_KEY`. Settings are layered from the
+//! file, then the environment, then any explicit overrides such as command
+//! line flags, each overriding the one before.
+
+use crate::builder::RudderAnalyticsBuilder;
+use crate::errors::Error as AnalyticsError;
+use crate::retry::RetryConfig;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+use std::time::Duration;
+
+/// Environment variable holding the write key.
+pub const WRITE_KEY_ENV: &str = "RUDDER_WRITE_KEY";
+/// Environment variable holding the data plane url.
+pub const DATA_PLANE_URL_ENV: &str = "RUDDER_DATA_PLANE_URL";
+
+/// Client settings, as read from the environment or a config file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub write_key: Option<String>,
+    pub data_plane_url: Option<String>,
+    /// Total request timeout in milliseconds.
+    pub timeout_ms: Option<u64>,
+    /// Connection timeout in milliseconds.
+    pub connect_timeout_ms: Option<u64>,
+    /// Overrides for the default `RetryConfig`.
+    pub retry: RetrySettings,
+    pub default_context: Option<Value>,
+}
+
+/// Retry settings of a `ClientConfig`. Unset fields keep the values of
+/// `RetryConfig::default()`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetrySettings {
+    pub enabled: Option<bool>,
+    pub max_retries: Option<u32>,
+    pub base_delay_ms: Option<u64>,
+    pub max_backoff_delay_ms: Option<u64>,
+    pub jitter_ratio: Option<f64>,
+    pub deadline_ms: Option<u64>,
+    pub max_retry_after_ms: Option<u64>,
+}
+
+impl RetrySettings {
+    /// The default retry configuration with these settings applied.
+    pub fn to_retry_config(&self) -> RetryConfig {
+        let mut config = RetryConfig::default();
+        if let Some(enabled) = self.enabled {
+            config.enabled = enabled;
+        }
+        if let Some(max_retries) = self.max_retries {
+            config.max_retries = max_retries;
+        }
+        if let Some(delay) = self.base_delay_ms {
+            config.base_delay = Duration::from_millis(delay);
+        }
+        if let Some(delay) = self.max_backoff_delay_ms {
+            config.max_backoff_delay = Duration::from_millis(delay);
+        }
+        if let Some(jitter_ratio) = self.jitter_ratio {
+            config.jitter_ratio = jitter_ratio;
+        }
+        if let Some(deadline) = self.deadline_ms {
+            config.deadline = Some(Duration::from_millis(deadline));
+        }
+        if let Some(delay) = self.max_retry_after_ms {
+            config.max_retry_after = Some(Duration::from_millis(delay));
+        }
+        config
+    }
+
+    fn merge(self, other: RetrySettings) -> RetrySettings {
+        RetrySettings {
+            enabled: other.enabled.or(self.enabled),
+            max_retries: other.max_retries.or(self.max_retries),
+            base_delay_ms: other.base_delay_ms.or(self.base_delay_ms),
+            max_backoff_delay_ms: other.max_backoff_delay_ms.or(self.max_backoff_delay_ms),
+            jitter_ratio: other.jitter_ratio.or(self.jitter_ratio),
+            deadline_ms: other.deadline_ms.or(self.deadline_ms),
+            max_retry_after_ms: other.max_retry_after_ms.or(self.max_retry_after_ms),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Read the write key and data plane url from `RUDDER_WRITE_KEY` and
+    /// `RUDDER_DATA_PLANE_URL`. Unset or empty variables are left as `None`.
+    pub fn from_env() -> ClientConfig {
+        ClientConfig {
+            write_key: env_var(WRITE_KEY_ENV),
+            data_plane_url: env_var(DATA_PLANE_URL_ENV),
+            ..Default::default()
+        }
+    }
+
+    /// Load a JSON config file, or a TOML one if the path ends in `.toml`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<ClientConfig, AnalyticsError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            AnalyticsError::InvalidConfig(format!(
+                "cannot read config file {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+
+        if path.extension() == Some("toml".as_ref()) {
+            return Self::from_toml(path, &contents);
+        }
+        serde_json::from_str(&contents).map_err(|err| {
+            AnalyticsError::InvalidConfig(format!(
+                "config file {} is not valid: {}",
+                path.display(),
+                err
+            ))
+        })
+    }
+
+    #[cfg(feature = "toml")]
+    fn from_toml(path: &Path, contents: &str) -> Result<ClientConfig, AnalyticsError> {
+        toml::from_str(contents).map_err(|err| {
+            AnalyticsError::InvalidConfig(format!(
+                "config file {} is not valid: {}",
+                path.display(),
+                err
+            ))
+        })
+    }
+
+    #[cfg(not(feature = "toml"))]
+    fn from_toml(path: &Path, _contents: &str) -> Result<ClientConfig, AnalyticsError> {
+        Err(AnalyticsError::InvalidConfig(format!(
+            "cannot read config file {}: TOML support requires the `toml` feature",
+            path.display()
+        )))
+    }
+
+    /// Layer `other` on top of this config: every setting present in `other`
+    /// wins, and the rest are kept from `self`. The default context is
+    /// replaced as a whole, not merged.
+    pub fn merge(self, other: ClientConfig) -> ClientConfig {
+        ClientConfig {
+            write_key: other.write_key.or(self.write_key),
+            data_plane_url: other.data_plane_url.or(self.data_plane_url),
+            timeout_ms: other.timeout_ms.or(self.timeout_ms),
+            connect_timeout_ms: other.connect_timeout_ms.or(self.connect_timeout_ms),
+            retry: self.retry.merge(other.retry),
+            default_context: other.default_context.or(self.default_context),
+        }
+    }
+
+    /// Start a `RudderAnalyticsBuilder` with these settings. Fails if the
+    /// write key or the data plane url is missing.
+    pub fn builder(self) -> Result<RudderAnalyticsBuilder, AnalyticsError> {
+        let write_key = self.write_key.ok_or_else(|| {
+            AnalyticsError::InvalidConfig(format!(
+                "write key is not configured, set {} or write_key",
+                WRITE_KEY_ENV
+            ))
+        })?;
+        let data_plane_url = self.data_plane_url.ok_or_else(|| {
+            AnalyticsError::InvalidConfig(format!(
+                "data plane url is not configured, set {} or data_plane_url",
+                DATA_PLANE_URL_ENV
+            ))
+        })?;
+
+        let mut builder = RudderAnalyticsBuilder::new(write_key, data_plane_url)
+            .retry_config(self.retry.to_retry_config());
+        if let Some(timeout) = self.timeout_ms {
+            builder = builder.timeout(Duration::from_millis(timeout));
+        }
+        if let Some(timeout) = self.connect_timeout_ms {
+            builder = builder.connect_timeout(Duration::from_millis(timeout));
+        }
+        if let Some(context) = self.default_context {
+            builder = builder.default_context(context);
+        }
+        Ok(builder)
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
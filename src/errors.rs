@@ -1,18 +1,125 @@
 //! Errors which may arise from this crate.
 
+use crate::transport::TransportError;
 use thiserror::Error;
 
-/// An enum of errors this crate may produce. These are compatible with
-/// `failure` errors.
+/// An enum of errors this crate may produce.
 #[derive(Debug, Error)]
 pub enum Error {
     /// The given message is too large to be sent to RudderStack's API.
     #[error("message too large")]
     MessageTooLarge(String),
 
-    #[error("Invalid request")]
-    InvalidRequest(String),
+    /// The message failed validation and was not sent.
+    #[error("invalid {field}: {message}")]
+    Validation { field: String, message: String },
+
+    /// The data plane rejected the request with a status that is not
+    /// retried, such as 400 or 401.
+    #[error("request rejected with status {status} after {attempts} attempt(s)")]
+    Rejected {
+        status: u16,
+        attempts: u32,
+        body: String,
+    },
+
+    /// Every attempt failed with a retryable status or transport error, and
+    /// the retry budget has been used up. `status` and `body` are those of
+    /// the last response, and are `None` when the last attempt got no
+    /// response.
+    #[error("retries exhausted after {attempts} attempt(s)")]
+    RetriesExhausted {
+        attempts: u32,
+        status: Option<u16>,
+        body: Option<String>,
+        #[source]
+        source: Option<TransportError>,
+    },
+
+    /// Retrying would run past `RetryConfig::deadline`, so delivery stopped
+    /// before the retry budget was used up. `status`, `body` and `source`
+    /// describe the last attempt as for `RetriesExhausted`.
+    #[error("retry deadline exceeded after {attempts} attempt(s)")]
+    DeadlineExceeded {
+        attempts: u32,
+        status: Option<u16>,
+        body: Option<String>,
+        #[source]
+        source: Option<TransportError>,
+    },
+
+    /// The client configuration is invalid.
+    #[error("Invalid configuration")]
+    InvalidConfig(String),
 
     #[error("Error sending request")]
-    SendRequestError(#[from] reqwest::Error),
+    SendRequestError(#[from] TransportError),
+
+    #[error("Error serializing message")]
+    SerializeError(#[from] serde_json::Error),
+
+    #[error("Error accessing the spool")]
+    SpoolError(#[from] std::io::Error),
+
+    /// A capture sink could not record a payload.
+    #[error("Error writing captured payload")]
+    CaptureError(#[source] std::io::Error),
+
+    /// The background queue is full and cannot accept more messages.
+    #[error("queue is full")]
+    QueueFull,
+
+    /// The background worker has stopped and cannot accept more messages.
+    #[error("queue is closed")]
+    QueueClosed,
+
+    /// The client's rate limit has been reached and the message was not sent.
+    #[error("rate limit exceeded")]
+    RateLimited,
+
+    /// The circuit breaker is open after repeated failures, so no request
+    /// was made.
+    #[error("circuit breaker is open")]
+    CircuitOpen,
+}
+
+impl Error {
+    /// The name of the field that failed validation.
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            Error::Validation { field, .. } => Some(field),
+            _ => None,
+        }
+    }
+
+    /// The HTTP status of the last response from the data plane.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::Rejected { status, .. } => Some(*status),
+            Error::RetriesExhausted { status, .. } => *status,
+            Error::DeadlineExceeded { status, .. } => *status,
+            _ => None,
+        }
+    }
+
+    /// The number of requests made before giving up.
+    pub fn attempts(&self) -> Option<u32> {
+        match self {
+            Error::Rejected { attempts, .. } => Some(*attempts),
+            Error::RetriesExhausted { attempts, .. } => Some(*attempts),
+            Error::DeadlineExceeded { attempts, .. } => Some(*attempts),
+            _ => None,
+        }
+    }
+
+    /// The body of the last response from the data plane, which usually
+    /// explains why a request was rejected.
+    pub fn response_body(&self) -> Option<&str> {
+        match self {
+            Error::Rejected { body, .. } => Some(body),
+            Error::RetriesExhausted { body, .. } => body.as_deref(),
+            Error::DeadlineExceeded { body, .. } => body.as_deref(),
+            _ => None,
+        }
+    }
 }
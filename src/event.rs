@@ -0,0 +1,80 @@
+//! Typed events.
+//!
+//! Implement `TrackEvent` or `IdentifyTraits` for a `Serialize` struct so the
+//! shape of its properties or traits is checked by the compiler instead of
+//! being assembled by hand as a `serde_json::Value`. With the `derive`
+//! feature both traits can be derived:
+//!
+#![cfg_attr(feature = "derive", doc = "```")]
+#![cfg_attr(not(feature = "derive"), doc = "```ignore")]
+//! use rudderanalytics::event::TrackEvent;
+//! use rudderanalytics::message::Track;
+//! use serde::Serialize;
+//!
+//! #[derive(Serialize, TrackEvent)]
+//! #[rudder(event = "Order Completed")]
+//! struct OrderCompleted {
+//!     order_id: String,
+//!     revenue: f64,
+//! }
+//!
+//! let track = Track {
+//!     user_id: Some("user-1".to_string()),
+//!     ..OrderCompleted { order_id: "1001".to_string(), revenue: 42.0 }.to_track()?
+//! };
+//! assert_eq!(track.event, "Order Completed");
+//! # Ok::<(), rudderanalytics::errors::Error>(())
+//! ```
+
+use crate::errors::Error as AnalyticsError;
+use crate::message::{Identify, Track};
+use serde::Serialize;
+use serde_json::Value;
+
+#[cfg(feature = "derive")]
+pub use rudderanalytics_derive::{IdentifyTraits, TrackEvent};
+
+/// A struct whose fields are the properties of a track event with a fixed
+/// name.
+pub trait TrackEvent: Serialize {
+    /// The name of the event being tracked.
+    const EVENT_NAME: &'static str;
+
+    /// Build a track message with this event's name and properties. The
+    /// user ids, context and other fields are left for the caller to set.
+    fn to_track(&self) -> Result<Track, AnalyticsError> {
+        Ok(Track {
+            event: Self::EVENT_NAME.to_string(),
+            properties: to_object(self, "properties")?,
+            ..Default::default()
+        })
+    }
+}
+
+/// A struct whose fields are the traits of an identify event.
+pub trait IdentifyTraits: Serialize {
+    /// Build an identify message with these traits. The user ids, context
+    /// and other fields are left for the caller to set.
+    fn to_identify(&self) -> Result<Identify, AnalyticsError> {
+        Ok(Identify {
+            traits: to_object(self, "traits")?,
+            ..Default::default()
+        })
+    }
+}
+
+// Serialize the value, which must be a JSON object. Unit structs serialize to
+// null and leave the field unset.
+fn to_object<T: Serialize + ?Sized>(
+    value: &T,
+    field: &str,
+) -> Result<Option<Value>, AnalyticsError> {
+    match serde_json::to_value(value)? {
+        Value::Null => Ok(None),
+        Value::Object(map) => Ok(Some(Value::Object(map))),
+        _ => Err(AnalyticsError::Validation {
+            field: field.to_string(),
+            message: String::from("must serialize to a JSON object"),
+        }),
+    }
+}
@@ -0,0 +1,278 @@
+//! Failover across several data plane urls.
+//!
+//! A client with a `FailoverConfig` sends each request to one of its
+//! endpoints. A connection error or 5xx response marks that endpoint
+//! unhealthy for `cool_down`, and the retry goes to the next endpoint right
+//! away instead of waiting out the backoff. With the `Ordered` strategy the
+//! first healthy endpoint is used, so traffic returns to the primary once
+//! its cool-down has passed. With `Weighted`, healthy endpoints are picked
+//! at random in proportion to their weights. When no endpoint is healthy,
+//! the one that failed longest ago is tried, after the usual backoff.
+
+use crate::builder::validate_data_plane_url;
+use crate::errors::Error as AnalyticsError;
+use rand::Rng;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How a healthy endpoint is chosen for a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailoverStrategy {
+    /// The first healthy endpoint, in the configured order.
+    Ordered,
+    /// A random healthy endpoint, in proportion to the endpoint weights.
+    Weighted,
+}
+
+/// A data plane url and its share of traffic under `Weighted`.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub url: String,
+    pub weight: u32,
+}
+
+/// The data plane urls to fail over between.
+#[derive(Debug, Clone)]
+pub struct FailoverConfig {
+    pub endpoints: Vec<Endpoint>,
+    pub strategy: FailoverStrategy,
+    /// How long a failed endpoint is skipped before it is tried again.
+    pub cool_down: Duration,
+}
+
+impl FailoverConfig {
+    /// Prefer `urls` in order, the first being the primary.
+    pub fn ordered<I, S>(urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            endpoints: urls
+                .into_iter()
+                .map(|url| Endpoint {
+                    url: url.into(),
+                    weight: 1,
+                })
+                .collect(),
+            strategy: FailoverStrategy::Ordered,
+            cool_down: Duration::from_secs(60),
+        }
+    }
+
+    /// Spread requests over `(url, weight)` endpoints.
+    pub fn weighted<I, S>(endpoints: I) -> Self
+    where
+        I: IntoIterator<Item = (S, u32)>,
+        S: Into<String>,
+    {
+        Self {
+            endpoints: endpoints
+                .into_iter()
+                .map(|(url, weight)| Endpoint {
+                    url: url.into(),
+                    weight,
+                })
+                .collect(),
+            strategy: FailoverStrategy::Weighted,
+            cool_down: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The health of a failover endpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndpointHealth {
+    pub url: String,
+    /// False while the endpoint is cooling down after a failure.
+    pub healthy: bool,
+    /// Failures since the last successful request to the endpoint.
+    pub consecutive_failures: u32,
+}
+
+pub(crate) struct Failover {
+    strategy: FailoverStrategy,
+    cool_down: Duration,
+    endpoints: Vec<Endpoint>,
+    health: Mutex<Vec<Health>>,
+}
+
+#[derive(Clone, Default)]
+struct Health {
+    consecutive_failures: u32,
+    failed_at: Option<Instant>,
+}
+
+impl Failover {
+    pub(crate) fn new(config: FailoverConfig) -> Result<Self, AnalyticsError> {
+        if config.endpoints.is_empty() {
+            return Err(AnalyticsError::InvalidConfig(String::from(
+                "failover needs at least one data plane url",
+            )));
+        }
+        if config.strategy == FailoverStrategy::Weighted
+            && config.endpoints.iter().all(|endpoint| endpoint.weight == 0)
+        {
+            return Err(AnalyticsError::InvalidConfig(String::from(
+                "weighted failover needs an endpoint with a weight above zero",
+            )));
+        }
+
+        let mut endpoints = Vec::with_capacity(config.endpoints.len());
+        for endpoint in config.endpoints {
+            endpoints.push(Endpoint {
+                url: validate_data_plane_url(&endpoint.url)?,
+                weight: endpoint.weight,
+            });
+        }
+        Ok(Self {
+            strategy: config.strategy,
+            cool_down: config.cool_down,
+            health: Mutex::new(vec![Health::default(); endpoints.len()]),
+            endpoints,
+        })
+    }
+
+    pub(crate) fn url(&self, index: usize) -> &str {
+        &self.endpoints[index].url
+    }
+
+    /// Pick the endpoint for the next request.
+    pub(crate) fn select(&self) -> usize {
+        let health = self.health.lock().unwrap();
+        let healthy: Vec<usize> = (0..self.endpoints.len())
+            .filter(|index| self.is_healthy(&health[*index]))
+            .collect();
+
+        let selected = match self.strategy {
+            FailoverStrategy::Ordered => healthy.first().copied(),
+            FailoverStrategy::Weighted => self.pick_weighted(&healthy),
+        };
+        selected.unwrap_or_else(|| {
+            (0..self.endpoints.len())
+                .min_by_key(|index| health[*index].failed_at)
+                .unwrap_or(0)
+        })
+    }
+
+    /// Whether the endpoint at `index` is out of its cool-down.
+    pub(crate) fn healthy(&self, index: usize) -> bool {
+        let health = self.health.lock().unwrap();
+        self.is_healthy(&health[index])
+    }
+
+    /// Record the outcome of a request to the endpoint at `index`. Only
+    /// failures specific to the endpoint count, i.e. connection errors and
+    /// 5xx responses.
+    pub(crate) fn record(&self, index: usize, failed: bool) {
+        let mut health = self.health.lock().unwrap();
+        let endpoint = &mut health[index];
+        if failed {
+            endpoint.consecutive_failures = endpoint.consecutive_failures.saturating_add(1);
+            endpoint.failed_at = Some(Instant::now());
+        } else {
+            *endpoint = Health::default();
+        }
+    }
+
+    pub(crate) fn health(&self) -> Vec<EndpointHealth> {
+        let health = self.health.lock().unwrap();
+        self.endpoints
+            .iter()
+            .zip(health.iter())
+            .map(|(endpoint, health)| EndpointHealth {
+                url: endpoint.url.clone(),
+                healthy: self.is_healthy(health),
+                consecutive_failures: health.consecutive_failures,
+            })
+            .collect()
+    }
+
+    fn is_healthy(&self, health: &Health) -> bool {
+        match health.failed_at {
+            Some(failed_at) => failed_at.elapsed() >= self.cool_down,
+            None => true,
+        }
+    }
+
+    fn pick_weighted(&self, candidates: &[usize]) -> Option<usize> {
+        let total: u64 = candidates
+            .iter()
+            .map(|index| u64::from(self.endpoints[*index].weight))
+            .sum();
+        if total == 0 {
+            return None;
+        }
+        let mut point = rand::thread_rng().gen_range(0..total);
+        for index in candidates {
+            let weight = u64::from(self.endpoints[*index].weight);
+            if point < weight {
+                return Some(*index);
+            }
+            point -= weight;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failover(config: FailoverConfig) -> Failover {
+        Failover::new(config).unwrap()
+    }
+
+    #[test]
+    fn ordered_prefers_the_first_healthy_endpoint() {
+        let failover = failover(FailoverConfig::ordered(vec![
+            "https://eu.example.com",
+            "https://us.example.com",
+        ]));
+        assert_eq!(failover.select(), 0);
+
+        failover.record(0, true);
+        assert_eq!(failover.select(), 1);
+        assert!(!failover.health()[0].healthy);
+
+        failover.record(1, true);
+        // both failed, so the one that failed first is tried again
+        assert_eq!(failover.select(), 0);
+        assert!(!failover.healthy(0));
+    }
+
+    #[test]
+    fn returns_to_primary_after_cool_down() {
+        let failover = failover(FailoverConfig {
+            cool_down: Duration::from_millis(20),
+            ..FailoverConfig::ordered(vec!["https://eu.example.com", "https://us.example.com"])
+        });
+        failover.record(0, true);
+        assert_eq!(failover.select(), 1);
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(failover.select(), 0);
+        assert_eq!(failover.health()[0].consecutive_failures, 1);
+    }
+
+    #[test]
+    fn weighted_skips_unhealthy_and_zero_weight_endpoints() {
+        let failover = failover(FailoverConfig::weighted(vec![
+            ("https://a.example.com", 3),
+            ("https://b.example.com", 1),
+            ("https://c.example.com", 0),
+        ]));
+        failover.record(0, true);
+
+        for _ in 0..20 {
+            assert_eq!(failover.select(), 1);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_endpoints() {
+        assert!(Failover::new(FailoverConfig::ordered(Vec::<String>::new())).is_err());
+        assert!(Failover::new(FailoverConfig::ordered(vec!["ftp://example.com"])).is_err());
+        assert!(Failover::new(FailoverConfig::weighted(vec![("https://example.com", 0)])).is_err());
+    }
+}
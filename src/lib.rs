@@ -1,8 +1,30 @@
 // public modules
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod batcher;
+pub mod builder;
+pub mod buffered;
+pub mod capture;
+pub mod circuit_breaker;
 pub mod client;
+pub mod compression;
+pub mod config;
 pub mod errors;
+pub mod event;
+pub mod failover;
 pub mod message;
+pub mod middleware;
+pub mod mirror;
+pub mod rate_limit;
+pub mod redaction;
 pub mod retry;
+pub mod ruddermessage;
+pub mod sampling;
+pub mod spool;
+pub mod stats;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod tracking_plan;
+pub mod transport;
 // private modules
-mod ruddermessage;
 mod utils;
@@ -1,68 +1,496 @@
 use std::error::Error;
-use clap::{App, AppSettings, Arg, SubCommand};
+use chrono::{DateTime, Utc};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use log::debug;
-use rudderanalytics::client::RudderAnalytics;
-use rudderanalytics::message::Message;
-use std::io;
+use rudderanalytics::batcher::Batcher;
+use rudderanalytics::client::{self, RudderAnalytics};
+use rudderanalytics::config::ClientConfig;
+use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::message::{
+    Alias, BatchMessage, Group, Identify, Message, Page, Screen, Track,
+};
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::process;
 
 fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
-    let matches = App::new("Rudderanalytics")
+    let app = App::new("Rudderanalytics")
         .version(env!("CARGO_PKG_VERSION"))
         .about("Sends analytics events to RudderStack")
         .setting(AppSettings::ColoredHelp)
         .arg(
             Arg::with_name("write-key")
-                .help("Write key to send message with")
+                .help("Write key to send message with [default: $RUDDER_WRITE_KEY]")
                 .takes_value(true)
                 .short('w')
-                .long("write-key")
-                .required(true),
+                .long("write-key"),
         )
         .arg(
             Arg::with_name("data-plane-url")
-                .help("Base url to send to your data")
+                .help("Base url to send to your data [default: $RUDDER_DATA_PLANE_URL]")
                 .takes_value(true)
                 .short('d')
-                .long("data-plane-url")
-                .required(true),
+                .long("data-plane-url"),
         )
-        .subcommand(SubCommand::with_name("identify").about("Send an identify event"))
-        .subcommand(SubCommand::with_name("track").about("Send a track event"))
-        .subcommand(SubCommand::with_name("page").about("Send a page event"))
-        .subcommand(SubCommand::with_name("screen").about("Send a screen event"))
-        .subcommand(SubCommand::with_name("group").about("Send a group event"))
-        .subcommand(SubCommand::with_name("alias").about("Send an alias event"))
-        .setting(AppSettings::ArgRequiredElseHelp)
-        .get_matches();
-
-    let write_key = matches.value_of("write-key").unwrap().to_owned();
-    let data_plane_url = matches.value_of("data-plane-url").unwrap().to_owned();
+        .arg(
+            Arg::with_name("config")
+                .help("JSON or TOML config file with the client settings")
+                .takes_value(true)
+                .short('c')
+                .long("config"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .help("Print the payloads that would be sent instead of sending them")
+                .long("dry-run")
+                .global(true),
+        )
+        .subcommand(
+            event_command("identify", "Send an identify event").arg(pairs("trait", "User trait")),
+        )
+        .subcommand(
+            event_command("track", "Send a track event")
+                .arg(flag("event", "Name of the event"))
+                .arg(pairs("property", "Event property")),
+        )
+        .subcommand(
+            event_command("page", "Send a page event")
+                .arg(flag("name", "Name of the page"))
+                .arg(pairs("property", "Page property")),
+        )
+        .subcommand(
+            event_command("screen", "Send a screen event")
+                .arg(flag("name", "Name of the screen"))
+                .arg(pairs("property", "Screen property")),
+        )
+        .subcommand(
+            event_command("group", "Send a group event")
+                .arg(flag("group-id", "Group the user is associated with"))
+                .arg(pairs("trait", "Group trait")),
+        )
+        .subcommand(
+            event_command("alias", "Send an alias event")
+                .arg(flag("previous-id", "Previous id of the user"))
+                .arg(pairs("trait", "User trait")),
+        )
+        .subcommand(
+            SubCommand::with_name("batch")
+                .alias("replay")
+                .about("Send newline-delimited JSON events in batches")
+                .arg(
+                    Arg::with_name("file")
+                        .help("File with one event per line, each with a `type` field [default: stdin]")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("batch-size")
+                        .help("Maximum number of events per batch request")
+                        .takes_value(true)
+                        .long("batch-size")
+                        .default_value("100"),
+                ),
+        )
+        .setting(AppSettings::ArgRequiredElseHelp);
+    let matches = app.clone().get_matches();
+
+    // flags take precedence over the environment, which takes precedence
+    // over the config file
+    let mut config = ClientConfig::default();
+    if let Some(path) = matches.value_of("config") {
+        config = ClientConfig::from_file(path)?;
+    }
+    config = config.merge(ClientConfig::from_env()).merge(ClientConfig {
+        write_key: matches.value_of("write-key").map(str::to_owned),
+        data_plane_url: matches.value_of("data-plane-url").map(str::to_owned),
+        ..Default::default()
+    });
+
+    // nothing is sent on a dry run, so the credentials are not needed
+    let dry_run = matches.is_present("dry-run");
+    if dry_run {
+        config.write_key.get_or_insert_with(|| String::from("dry-run"));
+        config
+            .data_plane_url
+            .get_or_insert_with(|| String::from("http://localhost"));
+    }
 
     debug!("Supplied CLI args:-");
-    debug!("write-key: {}", write_key);
-    debug!("data-plane-url: {}", data_plane_url);
-
-    let rudderanalytics = RudderAnalytics::load(write_key, data_plane_url);
-
-    fn format() -> String {
-        let mut cmd_ln_inp = String::new();
-        io::stdin()
-            .read_line(&mut cmd_ln_inp)
-            .expect("Failed To read Input");
-        cmd_ln_inp.to_string()
-    }
-
-    let message = match matches.subcommand_name() {
-        Some("identify") => Message::Identify(serde_json::from_str(&format())?),
-        Some("track") => Message::Track(serde_json::from_str(&format())?),
-        Some("page") => Message::Page(serde_json::from_str(&format())?),
-        Some("screen") => Message::Screen(serde_json::from_str(&format())?),
-        Some("group") => Message::Group(serde_json::from_str(&format())?),
-        Some("alias") => Message::Alias(serde_json::from_str(&format())?),
-        Some(_) => panic!("unknown message type"),
-        None => panic!("subcommand is required"),
+    debug!("write-key: {:?}", config.write_key);
+    debug!("data-plane-url: {:?}", config.data_plane_url);
+
+    let rudderanalytics = config.builder()?.build()?;
+
+    if let Some(batch_matches) = matches.subcommand_matches("batch") {
+        let input: Box<dyn BufRead> = match batch_matches.value_of("file") {
+            Some("-") | None => Box::new(BufReader::new(io::stdin())),
+            Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        };
+        let batch_size = batch_matches.value_of("batch-size").unwrap().parse()?;
+
+        let summary = replay(&rudderanalytics, input, batch_size, dry_run)?;
+        eprintln!(
+            "sent {} events in {} batches, {} failed",
+            summary.sent, summary.batches, summary.failed
+        );
+        if summary.failed > 0 {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let message = match matches.subcommand() {
+        Some((name, event_matches)) if has_event_flags(&app, name, event_matches) => {
+            message_from_flags(name, event_matches)?
+        }
+        Some((name, _)) => message_from_stdin(name)?,
+        None => return Err("a subcommand is required".into()),
     };
 
+    if dry_run {
+        return Ok(print_payloads(&rudderanalytics, &message)?);
+    }
     Ok(rudderanalytics.send(&message)?)
 }
+
+// Reads one event per line, batches them and sends each batch. Lines that
+// cannot be parsed, validated or batched are reported and skipped.
+fn replay(
+    analytics: &RudderAnalytics,
+    input: Box<dyn BufRead>,
+    batch_size: usize,
+    dry_run: bool,
+) -> io::Result<Replay<'_>> {
+    let mut replay = Replay {
+        analytics,
+        batch_size: batch_size.max(1),
+        dry_run,
+        batcher: Batcher::new(None),
+        lines: Vec::new(),
+        sent: 0,
+        failed: 0,
+        batches: 0,
+    };
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        let line_number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let event: BatchMessage = match serde_json::from_str(&line) {
+            Ok(event) => event,
+            Err(err) => {
+                replay.fail(line_number, &err);
+                continue;
+            }
+        };
+        if let Err(err) = client::validate(&Message::from(event.clone())) {
+            replay.fail(line_number, &err);
+            continue;
+        }
+        replay.push(line_number, event);
+    }
+    replay.flush();
+
+    Ok(replay)
+}
+
+struct Replay<'a> {
+    analytics: &'a RudderAnalytics,
+    batch_size: usize,
+    dry_run: bool,
+    batcher: Batcher,
+    // line numbers of the events in the current batch
+    lines: Vec<usize>,
+    sent: usize,
+    failed: usize,
+    batches: usize,
+}
+
+impl Replay<'_> {
+    fn push(&mut self, line_number: usize, event: BatchMessage) {
+        let result = match self.batcher.push(event) {
+            Ok(Some(event)) => {
+                self.flush();
+                self.batcher.push(event)
+            }
+            result => result,
+        };
+
+        match result {
+            Ok(_) => {
+                self.lines.push(line_number);
+                if self.lines.len() >= self.batch_size {
+                    self.flush();
+                }
+            }
+            Err(err) => self.fail(line_number, &err),
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.lines.is_empty() {
+            return;
+        }
+
+        let msg = std::mem::replace(&mut self.batcher, Batcher::new(None)).into_message();
+        let lines = std::mem::take(&mut self.lines);
+        let result = if self.dry_run {
+            print_payloads(self.analytics, &msg)
+        } else {
+            self.analytics.send(&msg)
+        };
+        match result {
+            Ok(()) => {
+                self.sent += lines.len();
+                self.batches += 1;
+            }
+            Err(err) => {
+                eprintln!(
+                    "lines {}-{}: batch of {} events failed: {}",
+                    lines[0],
+                    lines[lines.len() - 1],
+                    lines.len(),
+                    describe(&err)
+                );
+                self.failed += lines.len();
+            }
+        }
+    }
+
+    fn fail(&mut self, line_number: usize, err: &dyn Error) {
+        eprintln!("line {}: {}", line_number, err);
+        self.failed += 1;
+    }
+}
+
+fn describe(err: &AnalyticsError) -> String {
+    match err.response_body() {
+        Some(body) if !body.is_empty() => format!("{}: {}", err, body),
+        _ => err.to_string(),
+    }
+}
+
+fn print_payloads(analytics: &RudderAnalytics, msg: &Message) -> Result<(), AnalyticsError> {
+    for payload in analytics.prepare(msg)? {
+        println!("{}", serde_json::to_string_pretty(&payload)?);
+    }
+    Ok(())
+}
+
+// Whether any of the subcommand's own flags were given. Global flags such as
+// --dry-run are propagated to the subcommand's matches, so `args_present`
+// cannot be used.
+fn has_event_flags(app: &App, name: &str, matches: &ArgMatches) -> bool {
+    match app.find_subcommand(name) {
+        Some(subcommand) => subcommand
+            .get_arguments()
+            .any(|arg| matches.try_contains_id(arg.get_id()).unwrap_or(false)),
+        None => false,
+    }
+}
+
+// The flags shared by every event subcommand. Without any of them, the event
+// is read as JSON from stdin.
+fn event_command(name: &'static str, about: &'static str) -> App<'static> {
+    SubCommand::with_name(name)
+        .about(about)
+        .after_help(
+            "Values of repeated flags are PATH=VALUE pairs, where PATH may be dotted \
+             (app.version=1.2) and PATH:=JSON sets a JSON value (count:=3).\n\
+             Without any flags, the event is read as a JSON object from stdin.",
+        )
+        .arg(flag("user-id", "User id of the event"))
+        .arg(flag("anonymous-id", "Anonymous id of the event"))
+        .arg(flag("message-id", "Message id used for deduplication"))
+        .arg(flag("timestamp", "Original timestamp, in RFC 3339 format"))
+        .arg(pairs("context", "Context value"))
+        .arg(pairs("integration", "Integrations value"))
+}
+
+fn flag(name: &'static str, help: &'static str) -> Arg<'static> {
+    Arg::with_name(name).help(help).takes_value(true).long(name)
+}
+
+fn pairs(name: &'static str, help: &'static str) -> Arg<'static> {
+    flag(name, help)
+        .value_name("PATH=VALUE")
+        .multiple_occurrences(true)
+        .number_of_values(1)
+}
+
+fn message_from_stdin(name: &str) -> Result<Message, Box<dyn Error>> {
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+
+    Ok(match name {
+        "identify" => Message::Identify(serde_json::from_str(&line)?),
+        "track" => Message::Track(serde_json::from_str(&line)?),
+        "page" => Message::Page(serde_json::from_str(&line)?),
+        "screen" => Message::Screen(serde_json::from_str(&line)?),
+        "group" => Message::Group(serde_json::from_str(&line)?),
+        "alias" => Message::Alias(serde_json::from_str(&line)?),
+        other => return Err(format!("unknown message type {}", other).into()),
+    })
+}
+
+fn message_from_flags(name: &str, args: &ArgMatches) -> Result<Message, Box<dyn Error>> {
+    let user_id = args.value_of("user-id").map(str::to_owned);
+    let anonymous_id = args.value_of("anonymous-id").map(str::to_owned);
+    let message_id = args.value_of("message-id").map(str::to_owned);
+    let original_timestamp = match args.value_of("timestamp") {
+        Some(timestamp) => Some(DateTime::parse_from_rfc3339(timestamp)?.with_timezone(&Utc)),
+        None => None,
+    };
+    let context = pairs_of(args, "context")?;
+    let integrations = pairs_of(args, "integration")?;
+
+    Ok(match name {
+        "identify" => Message::Identify(Identify {
+            user_id,
+            anonymous_id,
+            traits: pairs_of(args, "trait")?,
+            message_id,
+            original_timestamp,
+            context,
+            integrations,
+        }),
+        "track" => Message::Track(Track {
+            user_id,
+            anonymous_id,
+            event: required(args, "event")?,
+            properties: pairs_of(args, "property")?,
+            message_id,
+            original_timestamp,
+            context,
+            integrations,
+        }),
+        "page" => Message::Page(Page {
+            user_id,
+            anonymous_id,
+            name: required(args, "name")?,
+            properties: pairs_of(args, "property")?,
+            message_id,
+            original_timestamp,
+            context,
+            integrations,
+        }),
+        "screen" => Message::Screen(Screen {
+            user_id,
+            anonymous_id,
+            name: required(args, "name")?,
+            properties: pairs_of(args, "property")?,
+            message_id,
+            original_timestamp,
+            context,
+            integrations,
+        }),
+        "group" => Message::Group(Group {
+            user_id,
+            anonymous_id,
+            group_id: required(args, "group-id")?,
+            traits: pairs_of(args, "trait")?,
+            message_id,
+            original_timestamp,
+            context,
+            integrations,
+        }),
+        "alias" => Message::Alias(Alias {
+            user_id: user_id.ok_or("--user-id is required")?,
+            previous_id: required(args, "previous-id")?,
+            traits: pairs_of(args, "trait")?,
+            message_id,
+            original_timestamp,
+            context,
+            integrations,
+        }),
+        other => return Err(format!("unknown message type {}", other).into()),
+    })
+}
+
+fn required(args: &ArgMatches, name: &str) -> Result<String, String> {
+    args.value_of(name)
+        .map(str::to_owned)
+        .ok_or_else(|| format!("--{} is required", name))
+}
+
+fn pairs_of(args: &ArgMatches, name: &str) -> Result<Option<Value>, String> {
+    match args.values_of(name) {
+        Some(values) => parse_pairs(values).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Parses PATH=VALUE pairs into a JSON object. Dotted paths build nested
+// objects, and PATH:=VALUE parses the value as JSON instead of a string.
+fn parse_pairs<'a>(pairs: impl IntoIterator<Item = &'a str>) -> Result<Value, String> {
+    let mut object = Value::Object(Map::new());
+    for pair in pairs {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("expected PATH=VALUE, got `{}`", pair))?;
+        let (path, value) = match key.strip_suffix(':') {
+            Some(path) => (
+                path,
+                serde_json::from_str(value)
+                    .map_err(|err| format!("invalid JSON value for `{}`: {}", path, err))?,
+            ),
+            None => (key, Value::String(value.to_owned())),
+        };
+        insert_path(&mut object, path, value)?;
+    }
+    Ok(object)
+}
+
+fn insert_path(object: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    let mut current = object;
+    let mut segments = path.split('.').peekable();
+    while let Some(segment) = segments.next() {
+        if segment.is_empty() {
+            return Err(format!("invalid path `{}`", path));
+        }
+        let map = current
+            .as_object_mut()
+            .ok_or_else(|| format!("`{}` conflicts with an earlier value", path))?;
+        if segments.peek().is_none() {
+            map.insert(segment.to_owned(), value);
+            return Ok(());
+        }
+        current = map
+            .entry(segment)
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_dotted_pairs_into_nested_objects() {
+        let value = parse_pairs(vec!["app.version=1.2", "app.name=cli", "plan=pro"]).unwrap();
+        assert_eq!(
+            value,
+            json!({ "app": { "version": "1.2", "name": "cli" }, "plan": "pro" })
+        );
+    }
+
+    #[test]
+    fn parses_json_values() {
+        let value = parse_pairs(vec!["count:=3", "tags:=[\"a\"]", "url=a=b"]).unwrap();
+        assert_eq!(value, json!({ "count": 3, "tags": ["a"], "url": "a=b" }));
+    }
+
+    #[test]
+    fn rejects_malformed_pairs() {
+        assert!(parse_pairs(vec!["plan"]).is_err());
+        assert!(parse_pairs(vec!["app..version=1"]).is_err());
+        assert!(parse_pairs(vec!["count:=three"]).is_err());
+        assert!(parse_pairs(vec!["app=x", "app.version=1"]).is_err());
+    }
+}
@@ -30,6 +30,11 @@ pub struct Identify {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub traits: Option<Value>,
 
+    /// The unique id of this message, used by RudderStack to deduplicate
+    /// events. Generated automatically when not set.
+    #[serde(rename = "messageId", default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+
     /// The timestamp associated with this message.
     #[serde(rename="originalTimestamp", skip_serializing_if = "Option::is_none")]
     pub original_timestamp: Option<DateTime<Utc>>,
@@ -61,6 +66,11 @@ pub struct Track {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub properties: Option<Value>,
 
+    /// The unique id of this message, used by RudderStack to deduplicate
+    /// events. Generated automatically when not set.
+    #[serde(rename = "messageId", default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+
     /// The timestamp associated with this message.
     #[serde(rename="originalTimestamp", skip_serializing_if = "Option::is_none")]
     pub original_timestamp: Option<DateTime<Utc>>,
@@ -92,6 +102,11 @@ pub struct Page {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub properties: Option<Value>,
 
+    /// The unique id of this message, used by RudderStack to deduplicate
+    /// events. Generated automatically when not set.
+    #[serde(rename = "messageId", default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+
     /// The timestamp associated with this message.
     #[serde(rename="originalTimestamp", skip_serializing_if = "Option::is_none")]
     pub original_timestamp: Option<DateTime<Utc>>,
@@ -123,6 +138,11 @@ pub struct Screen {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub properties: Option<Value>,
 
+    /// The unique id of this message, used by RudderStack to deduplicate
+    /// events. Generated automatically when not set.
+    #[serde(rename = "messageId", default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+
     /// The timestamp associated with this message.
     #[serde(rename="originalTimestamp", skip_serializing_if = "Option::is_none")]
     pub original_timestamp: Option<DateTime<Utc>>,
@@ -155,6 +175,11 @@ pub struct Group {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub traits: Option<Value>,
 
+    /// The unique id of this message, used by RudderStack to deduplicate
+    /// events. Generated automatically when not set.
+    #[serde(rename = "messageId", default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+
     /// The timestamp associated with this message.
     #[serde(rename="originalTimestamp", skip_serializing_if = "Option::is_none")]
     pub original_timestamp: Option<DateTime<Utc>>,
@@ -183,6 +208,11 @@ pub struct Alias {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub traits: Option<Value>,
 
+    /// The unique id of this message, used by RudderStack to deduplicate
+    /// events. Generated automatically when not set.
+    #[serde(rename = "messageId", default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+
     /// The timestamp associated with this message.
     #[serde(rename="originalTimestamp", skip_serializing_if = "Option::is_none")]
     pub original_timestamp: Option<DateTime<Utc>>,
@@ -232,3 +262,16 @@ pub enum BatchMessage {
     #[serde(rename = "alias")]
     Alias(Alias),
 }
+
+impl From<BatchMessage> for Message {
+    fn from(msg: BatchMessage) -> Message {
+        match msg {
+            BatchMessage::Identify(a_) => Message::Identify(a_),
+            BatchMessage::Track(a_) => Message::Track(a_),
+            BatchMessage::Page(a_) => Message::Page(a_),
+            BatchMessage::Screen(a_) => Message::Screen(a_),
+            BatchMessage::Group(a_) => Message::Group(a_),
+            BatchMessage::Alias(a_) => Message::Alias(a_),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! User-registered middleware that runs on every outgoing event.
+//!
+//! Middleware runs after a message has been validated and converted to a
+//! `Ruddermessage`, and before it is posted. Each middleware receives the
+//! output of the previous one and can modify, drop or split the message.
+
+use crate::ruddermessage::Ruddermessage;
+
+/// A step in the outgoing event pipeline.
+///
+/// Return the message (possibly modified) to pass it on, an empty `Vec` to
+/// drop it, or several messages to split it. Each returned message is passed
+/// to the next middleware in the chain and then sent on its own. Batches are
+/// passed as a single `Ruddermessage::Batch`.
+///
+/// Closures of type `Fn(Ruddermessage) -> Vec<Ruddermessage>` implement this
+/// trait.
+pub trait Middleware: Send + Sync {
+    fn process(&self, msg: Ruddermessage) -> Vec<Ruddermessage>;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(Ruddermessage) -> Vec<Ruddermessage> + Send + Sync,
+{
+    fn process(&self, msg: Ruddermessage) -> Vec<Ruddermessage> {
+        self(msg)
+    }
+}
+
+/// Run the message through each middleware in order.
+pub(crate) fn run_chain(chain: &[Box<dyn Middleware>], msg: Ruddermessage) -> Vec<Ruddermessage> {
+    chain.iter().fold(vec![msg], |messages, middleware| {
+        messages
+            .into_iter()
+            .flat_map(|msg| middleware.process(msg))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ruddermessage::{Identify, Track};
+    use serde_json::json;
+
+    fn track(event: &str) -> Ruddermessage {
+        Ruddermessage::Track(Track {
+            event: event.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn event_names(messages: &[Ruddermessage]) -> Vec<&str> {
+        messages
+            .iter()
+            .map(|msg| match msg {
+                Ruddermessage::Track(a_) => a_.event.as_str(),
+                Ruddermessage::Identify(_) => "identify",
+                other => panic!("unexpected message {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn runs_middleware_in_order() {
+        let chain: Vec<Box<dyn Middleware>> = vec![
+            Box::new(|msg| match msg {
+                Ruddermessage::Track(mut a_) => {
+                    a_.event.push_str(" first");
+                    vec![Ruddermessage::Track(a_)]
+                }
+                other => vec![other],
+            }),
+            Box::new(|msg| match msg {
+                Ruddermessage::Track(mut a_) => {
+                    a_.event.push_str(" second");
+                    a_.properties = Some(json!({ "enriched": true }));
+                    vec![Ruddermessage::Track(a_)]
+                }
+                other => vec![other],
+            }),
+        ];
+
+        let messages = run_chain(&chain, track("Event"));
+
+        assert_eq!(event_names(&messages), vec!["Event first second"]);
+    }
+
+    #[test]
+    fn dropped_messages_skip_later_middleware() {
+        let chain: Vec<Box<dyn Middleware>> = vec![
+            Box::new(|_| Vec::new()),
+            Box::new(|_: Ruddermessage| -> Vec<Ruddermessage> {
+                panic!("dropped message reached the next middleware")
+            }),
+        ];
+
+        assert!(run_chain(&chain, track("Heartbeat")).is_empty());
+    }
+
+    #[test]
+    fn split_messages_all_reach_later_middleware() {
+        let chain: Vec<Box<dyn Middleware>> = vec![
+            Box::new(|msg| vec![msg, Ruddermessage::Identify(Identify::default())]),
+            Box::new(|msg| match msg {
+                Ruddermessage::Identify(_) => vec![msg.clone(), msg],
+                other => vec![other],
+            }),
+        ];
+
+        let messages = run_chain(&chain, track("Signed Up"));
+
+        assert_eq!(
+            event_names(&messages),
+            vec!["Signed Up", "identify", "identify"]
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Mirroring events to additional data planes, e.g. during a migration.
+//!
+//! A client with mirror destinations delivers each payload to its own data
+//! plane first, then to every destination, each with its own write key and
+//! retry state. A destination with a `sample_rate` below 1.0 shadows only
+//! that fraction of payloads. Mirror deliveries never change the result of
+//! `send`, are not spooled and do not count towards the client's stats;
+//! their outcomes are counted per destination, see
+//! `RudderAnalytics::mirror_stats`.
+//!
+//! Mirrors are delivered one after another on the sending thread, so every
+//! destination adds its latency to `send`. To bound that, mirrors are not
+//! retried unless the destination's `retry_config` says so, and each
+//! destination has its own circuit breaker: once it opens, deliveries to the destination
+//! fail without a request until it lets a probe through. Mirrors do not
+//! depend on the client's own circuit breaker, so a destination keeps
+//! receiving events while the client's data plane is down.
+
+use crate::builder::validate_data_plane_url;
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
+use crate::errors::Error as AnalyticsError;
+use crate::retry::RetryConfig;
+use std::sync::Mutex;
+
+/// A data plane that receives a copy of the client's events.
+#[derive(Debug, Clone)]
+pub struct Destination {
+    /// Identifies the destination in `DestinationStats`.
+    pub name: String,
+    pub write_key: String,
+    pub data_plane_url: String,
+    /// Fraction of payloads to send to this destination, from 0.0 to 1.0.
+    pub sample_rate: f64,
+    /// The circuit breaker of this destination.
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Retries of deliveries to this destination. Defaults to none, since
+    /// every retry delays `send`.
+    pub retry_config: RetryConfig,
+}
+
+impl Destination {
+    /// A destination that receives every payload.
+    pub fn mirror(name: &str, write_key: &str, data_plane_url: &str) -> Self {
+        Self::shadow(name, write_key, data_plane_url, 1.0)
+    }
+
+    /// A destination that receives a `sample_rate` fraction of payloads.
+    pub fn shadow(name: &str, write_key: &str, data_plane_url: &str, sample_rate: f64) -> Self {
+        Self {
+            name: name.to_string(),
+            write_key: write_key.to_string(),
+            data_plane_url: data_plane_url.to_string(),
+            sample_rate,
+            circuit_breaker: CircuitBreakerConfig::default(),
+            retry_config: RetryConfig::disabled(),
+        }
+    }
+}
+
+/// Delivery outcomes for a mirror destination since the client was created.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DestinationStats {
+    pub name: String,
+    /// Events accepted by the destination.
+    pub events_delivered: u64,
+    /// Events that could not be delivered, after any retries.
+    pub events_failed: u64,
+    /// Events left out by the sample rate.
+    pub events_skipped: u64,
+    /// The error of the most recent failed delivery.
+    pub last_error: Option<String>,
+}
+
+pub(crate) struct Mirror {
+    pub(crate) write_key: String,
+    pub(crate) data_plane_url: String,
+    sample_rate: f64,
+    pub(crate) circuit_breaker: CircuitBreaker,
+    pub(crate) retry_config: RetryConfig,
+    stats: Mutex<DestinationStats>,
+}
+
+impl Mirror {
+    pub(crate) fn new(destination: Destination) -> Result<Self, AnalyticsError> {
+        if !(0.0..=1.0).contains(&destination.sample_rate) {
+            return Err(AnalyticsError::InvalidConfig(format!(
+                "sample rate of mirror {:?} must be between 0 and 1",
+                destination.name
+            )));
+        }
+        Ok(Self {
+            write_key: destination.write_key,
+            data_plane_url: validate_data_plane_url(&destination.data_plane_url)?,
+            sample_rate: destination.sample_rate,
+            circuit_breaker: CircuitBreaker::new(destination.circuit_breaker),
+            retry_config: destination.retry_config,
+            stats: Mutex::new(DestinationStats {
+                name: destination.name,
+                ..Default::default()
+            }),
+        })
+    }
+
+    pub(crate) fn name(&self) -> String {
+        self.stats.lock().unwrap().name.clone()
+    }
+
+    /// Whether the next payload goes to this destination. Skipped payloads
+    /// are counted.
+    pub(crate) fn sample(&self, events: usize) -> bool {
+        if self.sample_rate >= 1.0 || rand::random::<f64>() < self.sample_rate {
+            return true;
+        }
+        self.stats.lock().unwrap().events_skipped += events as u64;
+        false
+    }
+
+    pub(crate) fn record(&self, events: usize, result: &Result<(), AnalyticsError>) {
+        let mut stats = self.stats.lock().unwrap();
+        match result {
+            Ok(()) => stats.events_delivered += events as u64,
+            Err(err) => {
+                stats.events_failed += events as u64;
+                stats.last_error = Some(err.to_string());
+            }
+        }
+    }
+
+    pub(crate) fn stats(&self) -> DestinationStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_rate_bounds() {
+        let mirror = Mirror::new(Destination::shadow(
+            "new",
+            "write-key",
+            "https://new.example.com",
+            0.0,
+        ))
+        .unwrap();
+        assert!(!mirror.sample(2));
+        assert_eq!(mirror.stats().events_skipped, 2);
+
+        let mirror = Mirror::new(Destination::mirror(
+            "new",
+            "write-key",
+            "https://new.example.com",
+        ))
+        .unwrap();
+        assert!(mirror.sample(1));
+
+        assert!(Mirror::new(Destination::shadow(
+            "new",
+            "write-key",
+            "https://new.example.com",
+            1.5,
+        ))
+        .is_err());
+    }
+}
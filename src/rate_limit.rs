@@ -0,0 +1,195 @@
+//! Token bucket rate limiting of outgoing events.
+
+use crate::errors::Error as AnalyticsError;
+use crate::message::Message;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+/// Limits how many events the client sends.
+///
+/// The bucket holds up to `burst` tokens and refills at `events_per_second`.
+/// Each event left after sampling takes one token; a batch takes one per
+/// member. Events sent when the bucket is empty fail with
+/// `Error::RateLimited`, and a batch with more members than `burst` fails
+/// validation since it could never be sent. Middleware that turns a message
+/// into more than `burst` events makes it fail with `Error::RateLimited`.
+///
+/// `BufferedRudderAnalytics` takes tokens when a batch is flushed and drops
+/// the events of the batch that are over the limit.
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub events_per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimitConfig {
+    pub fn new(events_per_second: f64, burst: u32) -> Self {
+        Self {
+            events_per_second,
+            burst,
+        }
+    }
+}
+
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: RateLimitConfig) -> Result<Self, AnalyticsError> {
+        if config.events_per_second.is_nan() || config.events_per_second <= 0.0 {
+            return Err(AnalyticsError::InvalidConfig(format!(
+                "rate limit of {} events per second must be above zero",
+                config.events_per_second
+            )));
+        }
+        if config.burst == 0 {
+            return Err(AnalyticsError::InvalidConfig(String::from(
+                "rate limit burst must be above zero",
+            )));
+        }
+
+        let bucket = Bucket {
+            tokens: f64::from(config.burst),
+            refilled_at: Instant::now(),
+        };
+        Ok(Self {
+            config,
+            bucket: Mutex::new(bucket),
+        })
+    }
+
+    /// Reject a message with more events than the bucket holds, which would
+    /// be rate limited forever. No tokens are taken.
+    pub(crate) fn check(&self, msg: &Message) -> Result<(), AnalyticsError> {
+        let count = message_events(msg);
+        if count > self.config.burst as usize {
+            return Err(AnalyticsError::Validation {
+                field: String::from("batch"),
+                message: format!(
+                    "{} events exceed the rate limit burst of {}",
+                    count, self.config.burst
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Take a token for each of `count` events.
+    pub(crate) fn acquire(&self, count: usize) -> Result<(), AnalyticsError> {
+        if self.try_acquire(count) {
+            Ok(())
+        } else {
+            Err(AnalyticsError::RateLimited)
+        }
+    }
+
+    /// Take a token for each of up to `count` events, as many as the bucket
+    /// holds, and return how many were taken.
+    pub(crate) fn acquire_up_to(&self, count: usize) -> usize {
+        let mut bucket = self.refill();
+        let taken = (bucket.tokens as usize).min(count);
+        bucket.tokens -= taken as f64;
+        taken
+    }
+
+    /// Take `count` tokens if they are all available.
+    fn try_acquire(&self, count: usize) -> bool {
+        let mut bucket = self.refill();
+        let count = count as f64;
+        if bucket.tokens >= count {
+            bucket.tokens -= count;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn refill(&self) -> MutexGuard<'_, Bucket> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.config.events_per_second)
+            .min(f64::from(self.config.burst));
+        bucket.refilled_at = now;
+        bucket
+    }
+}
+
+/// The number of events in a message, counting each member of a batch.
+pub(crate) fn message_events(msg: &Message) -> usize {
+    match msg {
+        Message::Batch(b_) => b_.batch.len(),
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn limiter(events_per_second: f64, burst: u32) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig::new(events_per_second, burst)).unwrap()
+    }
+
+    #[test]
+    fn allows_bursts_up_to_capacity() {
+        let limiter = limiter(1.0, 3);
+
+        assert!(limiter.try_acquire(2));
+        assert!(limiter.try_acquire(1));
+        assert!(!limiter.try_acquire(1));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let limiter = limiter(100.0, 1);
+
+        assert!(limiter.try_acquire(1));
+        assert!(!limiter.try_acquire(1));
+        thread::sleep(Duration::from_millis(30));
+        assert!(limiter.try_acquire(1));
+    }
+
+    #[test]
+    fn rejects_requests_larger_than_the_bucket() {
+        let limiter = limiter(1000.0, 5);
+
+        assert!(matches!(
+            limiter.acquire(6),
+            Err(AnalyticsError::RateLimited)
+        ));
+        assert!(limiter.acquire(5).is_ok());
+        assert!(matches!(
+            limiter.acquire(1),
+            Err(AnalyticsError::RateLimited)
+        ));
+    }
+
+    #[test]
+    fn takes_the_tokens_left() {
+        let limiter = limiter(1.0, 3);
+
+        assert_eq!(limiter.acquire_up_to(2), 2);
+        assert_eq!(limiter.acquire_up_to(2), 1);
+        assert_eq!(limiter.acquire_up_to(2), 0);
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        for (events_per_second, burst) in [(0.0, 1), (-1.0, 1), (f64::NAN, 1), (1.0, 0)] {
+            assert!(matches!(
+                RateLimiter::new(RateLimitConfig::new(events_per_second, burst)),
+                Err(AnalyticsError::InvalidConfig(_))
+            ));
+        }
+    }
+}
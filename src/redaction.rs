@@ -0,0 +1,590 @@
+//! Redaction of personal data before events leave the process.
+//!
+//! Field rules apply an action to the values at a path such as
+//! `traits.email` or `properties.address.phone`, and detectors apply an
+//! action to every string in `traits`, `properties` and `context` that
+//! matches a regular expression.
+
+use crate::errors::Error as AnalyticsError;
+use crate::ruddermessage::{BatchMessage, Ruddermessage};
+use regex::Regex;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Replacement for masked values.
+pub const MASK: &str = "****";
+
+const ROOTS: [&str; 3] = ["traits", "properties", "context"];
+
+/// What to do with a value that matches a rule or detector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedactionAction {
+    /// Remove the field.
+    Drop,
+    /// Replace the value with `****`.
+    Mask,
+    /// Replace the value with the hex SHA-256 hash of the salt followed by
+    /// the value, so it can still be joined on without being readable.
+    Hash,
+}
+
+/// Applies an action to the values at a dotted field path.
+///
+/// The first segment is `traits`, `properties` or `context`. A `*` segment
+/// matches every key of an object, and arrays are searched element by
+/// element, so `properties.items.email` applies to the `email` of every
+/// item. `Mask` and `Hash` apply to every scalar inside the matched value.
+#[derive(Debug, Clone)]
+pub struct FieldRule {
+    pub path: String,
+    pub action: RedactionAction,
+}
+
+impl FieldRule {
+    pub fn new(path: impl Into<String>, action: RedactionAction) -> Self {
+        Self {
+            path: path.into(),
+            action,
+        }
+    }
+}
+
+/// Applies an action to string values that match a pattern.
+///
+/// `Mask` and `Hash` replace only the matching part of the string; `Drop`
+/// removes the whole field.
+#[derive(Debug, Clone)]
+pub struct Detector {
+    pub pattern: Regex,
+    pub action: RedactionAction,
+}
+
+impl Detector {
+    pub fn new(pattern: Regex, action: RedactionAction) -> Self {
+        Self { pattern, action }
+    }
+
+    /// Detect email addresses.
+    pub fn email(action: RedactionAction) -> Self {
+        Self::new(
+            Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap(),
+            action,
+        )
+    }
+
+    /// Detect phone numbers written with separators, such as
+    /// `+1 555-123-4567` or `(555) 123 4567`.
+    pub fn phone(action: RedactionAction) -> Self {
+        Self::new(
+            Regex::new(r"(?:\+\d{1,3}[\s.-]?)?\(?\d{2,4}\)?[\s.-]\d{3,4}[\s.-]\d{3,4}\b").unwrap(),
+            action,
+        )
+    }
+
+    /// Detect IPv4 addresses and uncompressed IPv6 addresses.
+    pub fn ip_address(action: RedactionAction) -> Self {
+        Self::new(
+            Regex::new(concat!(
+                r"\b(?:(?:25[0-5]|2[0-4]\d|1?\d?\d)\.){3}(?:25[0-5]|2[0-4]\d|1?\d?\d)\b",
+                r"|\b(?:[0-9A-Fa-f]{1,4}:){7}[0-9A-Fa-f]{1,4}\b",
+            ))
+            .unwrap(),
+            action,
+        )
+    }
+}
+
+/// Configuration for redacting `traits`, `properties` and `context` before
+/// events are sent. Field rules are applied before detectors.
+#[derive(Debug, Clone, Default)]
+pub struct RedactionConfig {
+    pub rules: Vec<FieldRule>,
+    pub detectors: Vec<Detector>,
+    /// Prepended to values before they are hashed.
+    pub salt: String,
+}
+
+impl RedactionConfig {
+    /// Hash emails, phone numbers and IP addresses wherever they appear.
+    pub fn hash_pii(salt: impl Into<String>) -> Self {
+        Self {
+            rules: Vec::new(),
+            detectors: vec![
+                Detector::email(RedactionAction::Hash),
+                Detector::phone(RedactionAction::Hash),
+                Detector::ip_address(RedactionAction::Hash),
+            ],
+            salt: salt.into(),
+        }
+    }
+}
+
+struct CompiledRule {
+    root: String,
+    segments: Vec<String>,
+    action: RedactionAction,
+}
+
+pub(crate) struct Redactor {
+    rules: Vec<CompiledRule>,
+    detectors: Vec<Detector>,
+    salt: String,
+}
+
+impl Redactor {
+    pub(crate) fn new(config: RedactionConfig) -> Result<Redactor, AnalyticsError> {
+        let mut rules = Vec::new();
+        for rule in config.rules {
+            let mut segments = rule.path.split('.').map(String::from);
+            let root = segments.next().unwrap_or_default();
+            let segments: Vec<String> = segments.collect();
+            if !ROOTS.contains(&root.as_str()) || segments.iter().any(String::is_empty) {
+                return Err(AnalyticsError::InvalidConfig(format!(
+                    "redaction path {:?} must start with traits, properties or context",
+                    rule.path
+                )));
+            }
+            rules.push(CompiledRule {
+                root,
+                segments,
+                action: rule.action,
+            });
+        }
+
+        Ok(Redactor {
+            rules,
+            detectors: config.detectors,
+            salt: config.salt,
+        })
+    }
+
+    pub(crate) fn redact(&self, msg: &mut Ruddermessage) {
+        match msg {
+            Ruddermessage::Identify(a_) => {
+                self.redact_root("traits", &mut a_.traits);
+                self.redact_root("context", &mut a_.context);
+            }
+            Ruddermessage::Track(a_) => {
+                self.redact_root("properties", &mut a_.properties);
+                self.redact_root("context", &mut a_.context);
+            }
+            Ruddermessage::Page(a_) => {
+                self.redact_root("properties", &mut a_.properties);
+                self.redact_root("context", &mut a_.context);
+            }
+            Ruddermessage::Screen(a_) => {
+                self.redact_root("properties", &mut a_.properties);
+                self.redact_root("context", &mut a_.context);
+            }
+            Ruddermessage::Group(a_) => {
+                self.redact_root("traits", &mut a_.traits);
+                self.redact_root("context", &mut a_.context);
+            }
+            Ruddermessage::Alias(a_) => {
+                self.redact_root("traits", &mut a_.traits);
+                self.redact_root("context", &mut a_.context);
+            }
+            Ruddermessage::Batch(b_) => {
+                self.redact_root("context", &mut b_.context);
+                for member in &mut b_.batch {
+                    self.redact_batch_message(member);
+                }
+            }
+        }
+    }
+
+    fn redact_batch_message(&self, msg: &mut BatchMessage) {
+        match msg {
+            BatchMessage::Identify(a_) => {
+                self.redact_root("traits", &mut a_.traits);
+                self.redact_root("context", &mut a_.context);
+            }
+            BatchMessage::Track(a_) => {
+                self.redact_root("properties", &mut a_.properties);
+                self.redact_root("context", &mut a_.context);
+            }
+            BatchMessage::Page(a_) => {
+                self.redact_root("properties", &mut a_.properties);
+                self.redact_root("context", &mut a_.context);
+            }
+            BatchMessage::Screen(a_) => {
+                self.redact_root("properties", &mut a_.properties);
+                self.redact_root("context", &mut a_.context);
+            }
+            BatchMessage::Group(a_) => {
+                self.redact_root("traits", &mut a_.traits);
+                self.redact_root("context", &mut a_.context);
+            }
+            BatchMessage::Alias(a_) => {
+                self.redact_root("traits", &mut a_.traits);
+                self.redact_root("context", &mut a_.context);
+            }
+        }
+    }
+
+    fn redact_root(&self, root: &str, field: &mut Option<Value>) {
+        for rule in self.rules.iter().filter(|rule| rule.root == root) {
+            if rule.segments.is_empty() {
+                match rule.action {
+                    RedactionAction::Drop => *field = None,
+                    action => {
+                        if let Some(value) = field {
+                            self.redact_value(value, action);
+                        }
+                    }
+                }
+            } else if let Some(value) = field {
+                self.apply_rule(value, &rule.segments, rule.action);
+            }
+        }
+
+        for detector in &self.detectors {
+            match field {
+                Some(value)
+                    if detector.action == RedactionAction::Drop
+                        && matches_string(value, &detector.pattern) =>
+                {
+                    *field = None
+                }
+                Some(value) => self.apply_detector(value, detector),
+                None => {}
+            }
+        }
+    }
+
+    fn apply_rule(&self, value: &mut Value, segments: &[String], action: RedactionAction) {
+        match value {
+            Value::Array(items) => {
+                for item in items {
+                    self.apply_rule(item, segments, action);
+                }
+            }
+            Value::Object(map) => {
+                let (segment, rest) = match segments.split_first() {
+                    Some(split) => split,
+                    None => return,
+                };
+                let keys: Vec<String> = map
+                    .keys()
+                    .filter(|key| segment == "*" || *key == segment)
+                    .cloned()
+                    .collect();
+                for key in keys {
+                    if !rest.is_empty() {
+                        if let Some(child) = map.get_mut(&key) {
+                            self.apply_rule(child, rest, action);
+                        }
+                    } else if action == RedactionAction::Drop {
+                        map.remove(&key);
+                    } else if let Some(child) = map.get_mut(&key) {
+                        self.redact_value(child, action);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn apply_detector(&self, value: &mut Value, detector: &Detector) {
+        match value {
+            Value::String(text) => {
+                if let Some(redacted) = self.redact_matches(text, detector) {
+                    *text = redacted;
+                }
+            }
+            Value::Array(items) => {
+                if detector.action == RedactionAction::Drop {
+                    items.retain(|item| !matches_string(item, &detector.pattern));
+                }
+                for item in items {
+                    self.apply_detector(item, detector);
+                }
+            }
+            Value::Object(map) => {
+                if detector.action == RedactionAction::Drop {
+                    map.retain(|_, item| !matches_string(item, &detector.pattern));
+                }
+                for (_, item) in map.iter_mut() {
+                    self.apply_detector(item, detector);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn redact_matches(&self, text: &str, detector: &Detector) -> Option<String> {
+        if !detector.pattern.is_match(text) {
+            return None;
+        }
+        match detector.action {
+            RedactionAction::Drop => None,
+            RedactionAction::Mask => Some(detector.pattern.replace_all(text, MASK).into_owned()),
+            RedactionAction::Hash => Some(
+                detector
+                    .pattern
+                    .replace_all(text, |captures: &regex::Captures| self.hash(&captures[0]))
+                    .into_owned(),
+            ),
+        }
+    }
+
+    // Mask or hash every scalar inside the value
+    fn redact_value(&self, value: &mut Value, action: RedactionAction) {
+        match value {
+            Value::Array(items) => {
+                for item in items {
+                    self.redact_value(item, action);
+                }
+            }
+            Value::Object(map) => {
+                for (_, item) in map.iter_mut() {
+                    self.redact_value(item, action);
+                }
+            }
+            Value::Null => {}
+            Value::String(text) => *text = self.redacted(text, action),
+            scalar => {
+                let text = scalar.to_string();
+                *scalar = Value::String(self.redacted(&text, action));
+            }
+        }
+    }
+
+    fn redacted(&self, text: &str, action: RedactionAction) -> String {
+        match action {
+            RedactionAction::Hash => self.hash(text),
+            _ => String::from(MASK),
+        }
+    }
+
+    fn hash(&self, text: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update(text.as_bytes());
+        hex::encode(hasher.finalize())
+    }
+}
+
+fn matches_string(value: &Value, pattern: &Regex) -> bool {
+    value
+        .as_str()
+        .map(|text| pattern.is_match(text))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ruddermessage::{Batch, Identify, Track};
+    use serde_json::json;
+
+    fn redactor(rules: Vec<FieldRule>, detectors: Vec<Detector>) -> Redactor {
+        Redactor::new(RedactionConfig {
+            rules,
+            detectors,
+            salt: String::from("pepper"),
+        })
+        .unwrap()
+    }
+
+    fn sha256(text: &str) -> String {
+        hex::encode(Sha256::digest(format!("pepper{}", text).as_bytes()))
+    }
+
+    fn redact_track(redactor: &Redactor, properties: Value) -> Value {
+        let mut msg = Ruddermessage::Track(Track {
+            properties: Some(properties),
+            ..Default::default()
+        });
+        redactor.redact(&mut msg);
+        match msg {
+            Ruddermessage::Track(a_) => a_.properties.unwrap_or(Value::Null),
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn field_rules_apply_to_nested_objects() {
+        let redactor = redactor(
+            vec![
+                FieldRule::new("properties.user.email", RedactionAction::Hash),
+                FieldRule::new("properties.user.phone", RedactionAction::Mask),
+                FieldRule::new("properties.user.ssn", RedactionAction::Drop),
+            ],
+            Vec::new(),
+        );
+
+        let properties = redact_track(
+            &redactor,
+            json!({
+                "user": {
+                    "email": "jane@example.com",
+                    "phone": 5551234567u64,
+                    "ssn": "123-45-6789",
+                    "plan": "pro"
+                },
+                "email": "unchanged@example.com"
+            }),
+        );
+
+        assert_eq!(
+            properties,
+            json!({
+                "user": {
+                    "email": sha256("jane@example.com"),
+                    "phone": MASK,
+                    "plan": "pro"
+                },
+                "email": "unchanged@example.com"
+            })
+        );
+    }
+
+    #[test]
+    fn field_rules_apply_to_every_array_element() {
+        let redactor = redactor(
+            vec![
+                FieldRule::new("properties.contacts.email", RedactionAction::Hash),
+                FieldRule::new("properties.tags", RedactionAction::Mask),
+                FieldRule::new("properties.addresses.*", RedactionAction::Drop),
+            ],
+            Vec::new(),
+        );
+
+        let properties = redact_track(
+            &redactor,
+            json!({
+                "contacts": [
+                    { "email": "a@example.com", "name": "A" },
+                    { "email": "b@example.com" },
+                    { "name": "C" }
+                ],
+                "tags": ["vip", ["nested"], null],
+                "addresses": [{ "street": "Main St", "city": "Springfield" }]
+            }),
+        );
+
+        assert_eq!(
+            properties,
+            json!({
+                "contacts": [
+                    { "email": sha256("a@example.com"), "name": "A" },
+                    { "email": sha256("b@example.com") },
+                    { "name": "C" }
+                ],
+                "tags": [MASK, [MASK], null],
+                "addresses": [{}]
+            })
+        );
+    }
+
+    #[test]
+    fn detectors_redact_matches_anywhere() {
+        let redactor = redactor(
+            Vec::new(),
+            vec![
+                Detector::email(RedactionAction::Hash),
+                Detector::phone(RedactionAction::Mask),
+                Detector::ip_address(RedactionAction::Drop),
+            ],
+        );
+
+        let mut msg = Ruddermessage::Identify(Identify {
+            traits: Some(json!({
+                "contact": {
+                    "emails": ["jane@example.com", "not an email"],
+                    "note": "call +1 555-123-4567 after 5"
+                },
+                "age": 42
+            })),
+            context: Some(json!({
+                "ip": "192.168.0.1",
+                "library": { "name": "RudderStack Rust SDK", "version": "1.1.4" }
+            })),
+            ..Default::default()
+        });
+        redactor.redact(&mut msg);
+
+        let (traits, context) = match msg {
+            Ruddermessage::Identify(a_) => (a_.traits.unwrap(), a_.context.unwrap()),
+            other => panic!("unexpected message {:?}", other),
+        };
+        assert_eq!(
+            traits,
+            json!({
+                "contact": {
+                    "emails": [sha256("jane@example.com"), "not an email"],
+                    "note": "call **** after 5"
+                },
+                "age": 42
+            })
+        );
+        assert_eq!(
+            context,
+            json!({ "library": { "name": "RudderStack Rust SDK", "version": "1.1.4" } })
+        );
+    }
+
+    #[test]
+    fn redacts_every_batch_member() {
+        let redactor = redactor(
+            vec![FieldRule::new("traits.email", RedactionAction::Drop)],
+            vec![Detector::email(RedactionAction::Mask)],
+        );
+
+        let mut msg = Ruddermessage::Batch(Batch {
+            batch: vec![
+                BatchMessage::Identify(Identify {
+                    traits: Some(json!({ "email": "a@example.com", "name": "A" })),
+                    ..Default::default()
+                }),
+                BatchMessage::Track(Track {
+                    properties: Some(json!({ "referrer": "b@example.com" })),
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        });
+        redactor.redact(&mut msg);
+
+        let batch = match msg {
+            Ruddermessage::Batch(b_) => b_.batch,
+            other => panic!("unexpected message {:?}", other),
+        };
+        match &batch[0] {
+            BatchMessage::Identify(a_) => assert_eq!(a_.traits, Some(json!({ "name": "A" }))),
+            other => panic!("unexpected batch member {:?}", other),
+        }
+        match &batch[1] {
+            BatchMessage::Track(a_) => {
+                assert_eq!(a_.properties, Some(json!({ "referrer": MASK })))
+            }
+            other => panic!("unexpected batch member {:?}", other),
+        }
+    }
+
+    #[test]
+    fn whole_root_rules() {
+        let redactor = redactor(
+            vec![FieldRule::new("properties", RedactionAction::Drop)],
+            Vec::new(),
+        );
+
+        assert_eq!(redact_track(&redactor, json!({ "a": 1 })), Value::Null);
+    }
+
+    #[test]
+    fn rejects_paths_outside_known_roots() {
+        for path in ["user.email", "traits..email", ""] {
+            let result = Redactor::new(RedactionConfig {
+                rules: vec![FieldRule::new(path, RedactionAction::Drop)],
+                ..Default::default()
+            });
+            assert!(
+                matches!(result, Err(AnalyticsError::InvalidConfig(_))),
+                "expected {:?} to be rejected",
+                path
+            );
+        }
+    }
+}
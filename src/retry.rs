@@ -1,9 +1,12 @@
 //! Retry configuration and helpers for transient delivery failures.
 
+use crate::errors::Error as AnalyticsError;
+use crate::transport::TransportError;
+use log::debug;
 use rand::Rng;
 use reqwest::header::{HeaderMap, RETRY_AFTER};
 use reqwest::StatusCode;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 /// Configuration for retrying transient delivery failures.
 #[derive(Debug, Clone)]
@@ -18,6 +21,13 @@ pub struct RetryConfig {
     pub max_backoff_delay: Duration,
     /// Jitter ratio added to the selected delay. `0.2` means up to 20%.
     pub jitter_ratio: f64,
+    /// Total time a delivery may take, including retries. Delivery stops
+    /// with `Error::DeadlineExceeded` when the next retry would start after
+    /// it. `None` bounds deliveries only by `max_retries`.
+    pub deadline: Option<Duration>,
+    /// Longest `Retry-After` delay to honor. Longer delays are shortened to
+    /// this. `None` honors any delay.
+    pub max_retry_after: Option<Duration>,
 }
 
 impl Default for RetryConfig {
@@ -28,6 +38,8 @@ impl Default for RetryConfig {
             base_delay: Duration::from_millis(100),
             max_backoff_delay: Duration::from_secs(30),
             jitter_ratio: 0.2,
+            deadline: None,
+            max_retry_after: None,
         }
     }
 }
@@ -41,16 +53,167 @@ impl RetryConfig {
             base_delay: Duration::from_millis(100),
             max_backoff_delay: Duration::from_secs(30),
             jitter_ratio: 0.0,
+            deadline: None,
+            max_retry_after: None,
         }
     }
 }
 
-pub(crate) fn is_status_retryable(status: StatusCode) -> bool {
-    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+/// Retry bookkeeping for a single delivery, shared by the blocking and async
+/// clients so both make the same decisions.
+pub(crate) struct RetryState<'a> {
+    config: &'a RetryConfig,
+    retries: u32,
+    transient_failure: bool,
+    skip_backoff: bool,
+    started: Instant,
 }
 
-pub(crate) fn is_error_retryable(err: &reqwest::Error) -> bool {
-    err.is_connect() || err.is_timeout()
+impl<'a> RetryState<'a> {
+    pub(crate) fn new(config: &'a RetryConfig) -> Self {
+        Self {
+            config,
+            retries: 0,
+            transient_failure: false,
+            skip_backoff: false,
+            started: Instant::now(),
+        }
+    }
+
+    // Whether the final failure was one that could succeed later, such as a
+    // 5xx or a connection error, as opposed to a rejected request.
+    pub(crate) fn failed_transiently(&self) -> bool {
+        self.transient_failure
+    }
+
+    // Make the next retry go out right away instead of after the backoff,
+    // e.g. because it goes to another endpoint. The deadline is checked
+    // against the delay that is actually waited.
+    pub(crate) fn skip_backoff(&mut self) {
+        self.skip_backoff = true;
+    }
+
+    fn next_delay(&mut self, headers: Option<&HeaderMap>) -> Duration {
+        if std::mem::take(&mut self.skip_backoff) {
+            return Duration::from_secs(0);
+        }
+        retry_delay(self.config, self.retries + 1, headers)
+    }
+
+    // Whether a retry after `delay` would start after the deadline.
+    fn past_deadline(&self, delay: Duration) -> bool {
+        match self.config.deadline {
+            Some(deadline) => self.started.elapsed().saturating_add(delay) > deadline,
+            None => false,
+        }
+    }
+
+    // The error for a delivery stopped by an open circuit breaker, which is
+    // as transient as the failures that opened it.
+    pub(crate) fn circuit_open(&mut self) -> AnalyticsError {
+        self.transient_failure = true;
+        AnalyticsError::CircuitOpen
+    }
+
+    // Returns the delay before the next attempt after a non-success status,
+    // or the final error when the status should not be retried.
+    pub(crate) fn retry_status(
+        &mut self,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: String,
+    ) -> Result<Duration, AnalyticsError> {
+        let attempt = self.retries + 1;
+        let retryable = self.config.enabled && is_status_retryable(status);
+
+        if !retryable {
+            self.transient_failure = is_status_retryable(status);
+            debug!(
+                "request rejected with status {} after {} attempt(s): {}",
+                status, attempt, body
+            );
+            return Err(AnalyticsError::Rejected {
+                status: status.as_u16(),
+                attempts: attempt,
+                body,
+            });
+        }
+        if self.retries >= self.config.max_retries {
+            self.transient_failure = true;
+            return Err(AnalyticsError::RetriesExhausted {
+                attempts: attempt,
+                status: Some(status.as_u16()),
+                body: Some(body),
+                source: None,
+            });
+        }
+
+        let delay = self.next_delay(Some(headers));
+        if self.past_deadline(delay) {
+            self.transient_failure = true;
+            return Err(AnalyticsError::DeadlineExceeded {
+                attempts: attempt,
+                status: Some(status.as_u16()),
+                body: Some(body),
+                source: None,
+            });
+        }
+
+        self.retries += 1;
+        debug!(
+            "retrying request after status {} in {:?} (attempt {} of {})",
+            status,
+            delay,
+            attempt,
+            self.config.max_retries + 1
+        );
+        Ok(delay)
+    }
+
+    // Returns the delay before the next attempt after a transport error, or
+    // the error itself when it should not be retried.
+    pub(crate) fn retry_error(&mut self, err: TransportError) -> Result<Duration, AnalyticsError> {
+        let attempt = self.retries + 1;
+        let can_retry =
+            self.config.enabled && err.is_retryable() && self.retries < self.config.max_retries;
+
+        if !can_retry {
+            self.transient_failure = err.is_retryable();
+            if self.config.enabled && self.transient_failure {
+                return Err(AnalyticsError::RetriesExhausted {
+                    attempts: attempt,
+                    status: None,
+                    body: None,
+                    source: Some(err),
+                });
+            }
+            return Err(AnalyticsError::SendRequestError(err));
+        }
+
+        let delay = self.next_delay(None);
+        if self.past_deadline(delay) {
+            self.transient_failure = true;
+            return Err(AnalyticsError::DeadlineExceeded {
+                attempts: attempt,
+                status: None,
+                body: None,
+                source: Some(err),
+            });
+        }
+
+        self.retries += 1;
+        debug!(
+            "retrying request after transport error in {:?} (attempt {} of {})",
+            delay,
+            attempt,
+            self.config.max_retries + 1
+        );
+        Ok(delay)
+    }
+}
+
+pub(crate) fn is_status_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
 }
 
 pub(crate) fn retry_delay(
@@ -59,14 +222,22 @@ pub(crate) fn retry_delay(
     headers: Option<&HeaderMap>,
 ) -> Duration {
     let backoff_delay = exponential_backoff(config, retry_number);
-    let retry_after_delay = headers
+    let mut retry_after_delay = headers
         .and_then(parse_retry_after)
         .unwrap_or_else(|| Duration::from_secs(0));
+    if let Some(max_retry_after) = config.max_retry_after {
+        retry_after_delay = retry_after_delay.min(max_retry_after);
+    }
 
-    add_jitter(
+    let delay = add_jitter(
         std::cmp::max(backoff_delay, retry_after_delay),
         config.jitter_ratio,
-    )
+    );
+    match config.max_retry_after {
+        // jitter must not push a Retry-After delay past the cap
+        Some(max_retry_after) if retry_after_delay > backoff_delay => delay.min(max_retry_after),
+        _ => delay,
+    }
 }
 
 fn exponential_backoff(config: &RetryConfig, retry_number: u32) -> Duration {
@@ -228,6 +399,70 @@ mod tests {
         );
     }
 
+    #[test]
+    fn retry_after_is_capped_by_max_retry_after() {
+        let config = RetryConfig {
+            base_delay: Duration::from_millis(100),
+            jitter_ratio: 0.0,
+            max_retry_after: Some(Duration::from_secs(2)),
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "3600".parse().unwrap());
+
+        assert_eq!(
+            retry_delay(&config, 1, Some(&headers)),
+            Duration::from_secs(2)
+        );
+    }
+
+    #[test]
+    fn jitter_does_not_exceed_max_retry_after() {
+        let config = RetryConfig {
+            base_delay: Duration::from_millis(100),
+            jitter_ratio: 0.5,
+            max_retry_after: Some(Duration::from_secs(2)),
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "3600".parse().unwrap());
+
+        for _ in 0..20 {
+            let delay = retry_delay(&config, 1, Some(&headers));
+            assert!(delay <= Duration::from_secs(2), "delay {:?}", delay);
+            assert!(delay >= Duration::from_millis(100), "delay {:?}", delay);
+        }
+    }
+
+    #[test]
+    fn gives_up_when_retry_would_cross_deadline() {
+        let config = RetryConfig {
+            base_delay: Duration::from_secs(1),
+            jitter_ratio: 0.0,
+            deadline: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        let mut retry = RetryState::new(&config);
+
+        let err = retry
+            .retry_status(
+                StatusCode::SERVICE_UNAVAILABLE,
+                &HeaderMap::new(),
+                String::new(),
+            )
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            AnalyticsError::DeadlineExceeded {
+                attempts: 1,
+                status: Some(503),
+                ..
+            }
+        ));
+        assert!(retry.failed_transiently());
+    }
+
     #[test]
     fn jitter_does_not_overflow_large_durations() {
         assert_eq!(add_jitter(max_duration(), 1.0), max_duration());
@@ -1,3 +1,6 @@
+//! The payloads sent to the RudderStack HTTP API, as produced from a
+//! `message::Message` and passed through any middleware.
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -29,6 +32,10 @@ pub struct Identify {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub traits: Option<Value>,
 
+    /// The unique id of this message, stable across retries.
+    #[serde(rename = "messageId")]
+    pub message_id: String,
+
     /// The originalTimestamp associated with this message.
     #[serde(rename = "originalTimestamp")]
     pub original_timestamp: Option<DateTime<Utc>>,
@@ -71,6 +78,10 @@ pub struct Track {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub properties: Option<Value>,
 
+    /// The unique id of this message, stable across retries.
+    #[serde(rename = "messageId")]
+    pub message_id: String,
+
     /// The originalTimestamp associated with this message.
     #[serde(rename = "originalTimestamp")]
     pub original_timestamp: Option<DateTime<Utc>>,
@@ -113,6 +124,10 @@ pub struct Page {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub properties: Option<Value>,
 
+    /// The unique id of this message, stable across retries.
+    #[serde(rename = "messageId")]
+    pub message_id: String,
+
     /// The originalTimestamp associated with this message.
     #[serde(rename = "originalTimestamp")]
     pub original_timestamp: Option<DateTime<Utc>>,
@@ -155,6 +170,10 @@ pub struct Screen {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub properties: Option<Value>,
 
+    /// The unique id of this message, stable across retries.
+    #[serde(rename = "messageId")]
+    pub message_id: String,
+
     /// The originalTimestamp associated with this message.
     #[serde(rename = "originalTimestamp")]
     pub original_timestamp: Option<DateTime<Utc>>,
@@ -198,6 +217,10 @@ pub struct Group {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub traits: Option<Value>,
 
+    /// The unique id of this message, stable across retries.
+    #[serde(rename = "messageId")]
+    pub message_id: String,
+
     /// The originalTimestamp associated with this message.
     #[serde(rename = "originalTimestamp")]
     pub original_timestamp: Option<DateTime<Utc>>,
@@ -237,6 +260,10 @@ pub struct Alias {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub traits: Option<Value>,
 
+    /// The unique id of this message, stable across retries.
+    #[serde(rename = "messageId")]
+    pub message_id: String,
+
     /// The originalTimestamp associated with this message.
     #[serde(rename = "originalTimestamp")]
     pub original_timestamp: Option<DateTime<Utc>>,
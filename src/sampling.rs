@@ -0,0 +1,275 @@
+//! Client-side sampling of high-volume track events.
+//!
+//! A `Sampler` is a middleware that keeps a fraction of the track events with
+//! a given name and drops the rest. Kept events record the rate they were
+//! sampled at under the `sampleRate` key of their context, so downstream
+//! counts can be re-weighted.
+
+use crate::errors::Error as AnalyticsError;
+use crate::middleware::Middleware;
+use crate::ruddermessage::{BatchMessage, Ruddermessage};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Context key under which the sample rate of a kept event is recorded.
+pub const SAMPLE_RATE_CONTEXT_KEY: &str = "sampleRate";
+
+/// How events are chosen to be kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplingStrategy {
+    /// Keep each event independently with probability `rate`.
+    Random,
+    /// Keep all or none of a user's events, based on a hash of the user id,
+    /// or the anonymous id when there is no user id. A user kept at one rate
+    /// is also kept at every higher rate.
+    PerUser,
+}
+
+/// The fraction of events to keep and how to choose them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplingRule {
+    /// Fraction of events to keep, from 0.0 to 1.0.
+    pub rate: f64,
+    pub strategy: SamplingStrategy,
+}
+
+impl SamplingRule {
+    /// Keep each event with probability `rate`.
+    pub fn random(rate: f64) -> Self {
+        Self {
+            rate,
+            strategy: SamplingStrategy::Random,
+        }
+    }
+
+    /// Keep the events of a `rate` fraction of users.
+    pub fn per_user(rate: f64) -> Self {
+        Self {
+            rate,
+            strategy: SamplingStrategy::PerUser,
+        }
+    }
+}
+
+/// Sampling rules for track events, by event name.
+///
+/// Events without a rule of their own use `default_rule`, and are always
+/// kept when it is `None`. Events other than track are never sampled.
+#[derive(Debug, Clone, Default)]
+pub struct SamplingConfig {
+    pub events: HashMap<String, SamplingRule>,
+    pub default_rule: Option<SamplingRule>,
+}
+
+impl SamplingConfig {
+    /// Add a rule for track events named `event`.
+    pub fn event(mut self, event: impl Into<String>, rule: SamplingRule) -> Self {
+        self.events.insert(event.into(), rule);
+        self
+    }
+}
+
+/// A middleware that samples track events according to a `SamplingConfig`.
+pub struct Sampler {
+    config: SamplingConfig,
+}
+
+impl Sampler {
+    /// Check that every rate is between 0.0 and 1.0 and build the sampler.
+    pub fn new(config: SamplingConfig) -> Result<Sampler, AnalyticsError> {
+        let rules = config.events.values().chain(config.default_rule.iter());
+        for rule in rules {
+            if !(0.0..=1.0).contains(&rule.rate) {
+                return Err(AnalyticsError::InvalidConfig(format!(
+                    "sample rate {} must be between 0.0 and 1.0",
+                    rule.rate
+                )));
+            }
+        }
+        Ok(Sampler { config })
+    }
+
+    fn rule(&self, event: &str) -> Option<&SamplingRule> {
+        self.config
+            .events
+            .get(event)
+            .or(self.config.default_rule.as_ref())
+    }
+
+    // Return whether the event is kept, recording the rate in its context
+    fn sample(
+        &self,
+        event: &str,
+        user_id: &Option<String>,
+        anonymous_id: &Option<String>,
+        context: &mut Option<Value>,
+    ) -> bool {
+        let rule = match self.rule(event) {
+            Some(rule) => rule,
+            None => return true,
+        };
+        let position = match (rule.strategy, user_id.as_ref().or(anonymous_id.as_ref())) {
+            (SamplingStrategy::PerUser, Some(id)) => hash_position(id),
+            _ => rand::random::<f64>(),
+        };
+        if position >= rule.rate {
+            return false;
+        }
+
+        if let Value::Object(context) = context.get_or_insert_with(|| json!({})) {
+            context.insert(SAMPLE_RATE_CONTEXT_KEY.to_string(), json!(rule.rate));
+        }
+        true
+    }
+}
+
+impl Middleware for Sampler {
+    fn process(&self, msg: Ruddermessage) -> Vec<Ruddermessage> {
+        match msg {
+            Ruddermessage::Track(mut a_) => {
+                if self.sample(&a_.event, &a_.user_id, &a_.anonymous_id, &mut a_.context) {
+                    vec![Ruddermessage::Track(a_)]
+                } else {
+                    Vec::new()
+                }
+            }
+            Ruddermessage::Batch(mut b_) => {
+                b_.batch.retain_mut(|member| match member {
+                    BatchMessage::Track(a_) => {
+                        self.sample(&a_.event, &a_.user_id, &a_.anonymous_id, &mut a_.context)
+                    }
+                    _ => true,
+                });
+                if b_.batch.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![Ruddermessage::Batch(b_)]
+                }
+            }
+            other => vec![other],
+        }
+    }
+}
+
+// Map an id to a stable position in [0, 1)
+fn hash_position(id: &str) -> f64 {
+    let digest = Sha256::digest(id.as_bytes());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ruddermessage::{Batch, Identify, Track};
+
+    fn track(event: &str, user_id: &str) -> Track {
+        Track {
+            user_id: Some(user_id.to_string()),
+            event: event.to_string(),
+            context: Some(json!({})),
+            ..Default::default()
+        }
+    }
+
+    fn kept(sampler: &Sampler, event: &str, user_id: &str) -> bool {
+        !sampler
+            .process(Ruddermessage::Track(track(event, user_id)))
+            .is_empty()
+    }
+
+    #[test]
+    fn per_user_sampling_is_deterministic() {
+        let sampler =
+            Sampler::new(SamplingConfig::default().event("Heartbeat", SamplingRule::per_user(0.5)))
+                .unwrap();
+
+        let users: Vec<String> = (0..200).map(|i| format!("user-{}", i)).collect();
+        let first: Vec<bool> = users
+            .iter()
+            .map(|u| kept(&sampler, "Heartbeat", u))
+            .collect();
+        let second: Vec<bool> = users
+            .iter()
+            .map(|u| kept(&sampler, "Heartbeat", u))
+            .collect();
+
+        assert_eq!(first, second);
+        let kept_count = first.iter().filter(|kept| **kept).count();
+        assert!(kept_count > 60 && kept_count < 140, "{}", kept_count);
+    }
+
+    #[test]
+    fn users_kept_at_a_low_rate_are_kept_at_higher_rates() {
+        let sampler = Sampler::new(
+            SamplingConfig::default()
+                .event("Heartbeat", SamplingRule::per_user(0.2))
+                .event("Scroll", SamplingRule::per_user(0.6)),
+        )
+        .unwrap();
+
+        for i in 0..200 {
+            let user = format!("user-{}", i);
+            if kept(&sampler, "Heartbeat", &user) {
+                assert!(kept(&sampler, "Scroll", &user), "{}", user);
+            }
+        }
+    }
+
+    #[test]
+    fn records_sample_rate_and_keeps_unsampled_events() {
+        let sampler = Sampler::new(SamplingConfig {
+            events: HashMap::new(),
+            default_rule: Some(SamplingRule::random(1.0)),
+        })
+        .unwrap();
+
+        let messages = sampler.process(Ruddermessage::Batch(Batch {
+            batch: vec![
+                BatchMessage::Track(track("Clicked", "user-1")),
+                BatchMessage::Identify(Identify::default()),
+            ],
+            ..Default::default()
+        }));
+
+        let batch = match &messages[..] {
+            [Ruddermessage::Batch(b_)] => &b_.batch,
+            other => panic!("expected one batch, got {:?}", other),
+        };
+        match &batch[0] {
+            BatchMessage::Track(a_) => assert_eq!(a_.context, Some(json!({ "sampleRate": 1.0 }))),
+            other => panic!("unexpected batch member {:?}", other),
+        }
+        match &batch[1] {
+            BatchMessage::Identify(a_) => assert_eq!(a_.context, None),
+            other => panic!("unexpected batch member {:?}", other),
+        }
+    }
+
+    #[test]
+    fn zero_rate_drops_every_event() {
+        let sampler =
+            Sampler::new(SamplingConfig::default().event("Heartbeat", SamplingRule::random(0.0)))
+                .unwrap();
+
+        assert!(!kept(&sampler, "Heartbeat", "user-1"));
+        assert!(kept(&sampler, "Purchase", "user-1"));
+        let messages = sampler.process(Ruddermessage::Batch(Batch {
+            batch: vec![BatchMessage::Track(track("Heartbeat", "user-1"))],
+            ..Default::default()
+        }));
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn rejects_rates_outside_unit_interval() {
+        for rate in [-0.1, 1.5, f64::NAN] {
+            let result = Sampler::new(
+                SamplingConfig::default().event("Heartbeat", SamplingRule::random(rate)),
+            );
+            assert!(matches!(result, Err(AnalyticsError::InvalidConfig(_))));
+        }
+    }
+}
@@ -0,0 +1,619 @@
+//! Durable on-disk spool for events that could not be delivered.
+//!
+//! When `send` gives up on an event after a transient failure (a 429, a 5xx,
+//! a connection error or a timeout), the serialized payload is appended to a
+//! segment file in the spool directory. Spooled events are delivered again by
+//! `RudderAnalytics::replay_spool`, either on startup or periodically through
+//! a `SpoolReplayer`.
+//!
+//! Segments are append-only newline delimited JSON files named after an
+//! increasing sequence number. When the spool grows past `max_bytes`, the
+//! oldest segments are deleted first. Each append is synced to disk unless
+//! `SpoolConfig::sync` is turned off.
+//!
+//! While a segment is replayed, the number of its records already handled
+//! is kept in an offset file next to it, so a replay that is interrupted,
+//! by a transient failure or by the process dying, resumes after the last
+//! delivered record instead of sending the segment again.
+//!
+//! Replayed payloads get a fresh `sentAt`, on a batch and on each of its
+//! members, so that the time spent in the spool is not mistaken for client
+//! clock skew.
+
+use crate::client::RudderAnalytics;
+use crate::errors::Error as AnalyticsError;
+use chrono::{DateTime, Utc};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const SEGMENT_EXTENSION: &str = "spool";
+const OFFSET_EXTENSION: &str = "offset";
+
+/// Configuration for the on-disk spool.
+#[derive(Debug, Clone)]
+pub struct SpoolConfig {
+    /// Directory holding the spool segments. Created if it does not exist.
+    pub dir: PathBuf,
+    /// Maximum total size of all segments. The oldest segments are evicted
+    /// once the spool grows past this size.
+    pub max_bytes: u64,
+    /// Size at which the segment being appended to is closed and a new one
+    /// is started.
+    pub segment_bytes: u64,
+    /// Whether appends and replay offsets are synced to disk before they
+    /// return. Turning this off makes spooling faster, but events spooled
+    /// shortly before a crash or power loss may be lost or delivered twice.
+    pub sync: bool,
+}
+
+impl SpoolConfig {
+    /// Spool into `dir` with a 64 MB size limit and 1 MB segments, syncing
+    /// every append.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes: 64 * 1024 * 1024,
+            segment_bytes: 1024 * 1024,
+            sync: true,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SpoolRecord {
+    path: String,
+    payload: Value,
+}
+
+struct SpoolState {
+    active: Option<u64>,
+    next_seq: u64,
+}
+
+/// An append-only, size-bounded store of undelivered payloads.
+pub struct Spool {
+    config: SpoolConfig,
+    state: Mutex<SpoolState>,
+    replaying: Mutex<()>,
+}
+
+impl Spool {
+    /// Open the spool directory, creating it if needed.
+    pub fn open(config: SpoolConfig) -> Result<Spool, AnalyticsError> {
+        fs::create_dir_all(&config.dir)?;
+        let next_seq = list_segments(&config.dir)?
+            .last()
+            .map(|(seq, _)| seq + 1)
+            .unwrap_or(0);
+
+        Ok(Spool {
+            config,
+            state: Mutex::new(SpoolState {
+                active: None,
+                next_seq,
+            }),
+            replaying: Mutex::new(()),
+        })
+    }
+
+    /// Total size in bytes of all spooled segments.
+    pub fn size(&self) -> Result<u64, AnalyticsError> {
+        Ok(list_segments(&self.config.dir)?
+            .iter()
+            .map(|(_, path)| file_size(path))
+            .sum())
+    }
+
+    /// Number of payloads waiting in the spool.
+    pub fn len(&self) -> Result<usize, AnalyticsError> {
+        let mut count = 0;
+        for (_, path) in list_segments(&self.config.dir)? {
+            count += read_segment(&path)?
+                .len()
+                .saturating_sub(read_offset(&path)?);
+        }
+        Ok(count)
+    }
+
+    /// Whether the spool holds no payloads.
+    pub fn is_empty(&self) -> Result<bool, AnalyticsError> {
+        Ok(self.len()? == 0)
+    }
+
+    pub(crate) fn append<T: Serialize>(
+        &self,
+        path: &str,
+        payload: &T,
+    ) -> Result<(), AnalyticsError> {
+        let record = SpoolRecord {
+            path: path.to_string(),
+            payload: serde_json::to_value(payload)?,
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+
+        let mut state = self.state.lock().unwrap();
+        let segment = match state.active {
+            Some(seq)
+                if file_size(&self.segment_path(seq)) + line.len() as u64
+                    <= self.config.segment_bytes =>
+            {
+                seq
+            }
+            _ => {
+                let seq = state.next_seq;
+                state.next_seq += 1;
+                state.active = Some(seq);
+                seq
+            }
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.segment_path(segment))?;
+        file.write_all(&line)?;
+        file.flush()?;
+        if self.config.sync {
+            file.sync_data()?;
+        }
+
+        self.evict(&mut state)
+    }
+
+    // Deliver spooled payloads oldest first. Delivery stops at the first
+    // transient failure, leaving that payload and everything after it in the
+    // spool. Payloads the data plane rejects outright are dropped. The offset
+    // of a segment is saved after every payload it handles.
+    pub(crate) fn replay<F>(&self, mut deliver: F) -> Result<usize, AnalyticsError>
+    where
+        F: FnMut(&str, &Value) -> Result<(), ReplayError>,
+    {
+        let _replaying = match self.replaying.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => return Ok(0),
+        };
+
+        // Close the active segment so that new failures go to a fresh segment
+        // while the existing ones are being replayed.
+        let segments = {
+            let mut state = self.state.lock().unwrap();
+            state.active = None;
+            list_segments(&self.config.dir)?
+        };
+
+        let mut delivered = 0;
+        for (_, segment) in segments {
+            let records = match read_segment(&segment) {
+                Ok(records) => records,
+                // evicted while we were replaying
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            let offset = read_offset(&segment)?;
+            for (index, record) in records.iter().enumerate().skip(offset) {
+                let mut payload = record.payload.clone();
+                restamp_sent_at(&mut payload, Utc::now());
+                match deliver(&record.path, &payload) {
+                    Ok(()) => delivered += 1,
+                    Err(ReplayError::Transient(err)) => {
+                        debug!("stopping spool replay after transient failure: {:?}", err);
+                        return Ok(delivered);
+                    }
+                    Err(ReplayError::Rejected(err)) => {
+                        warn!(
+                            "dropping spooled event rejected by the data plane: {:?}",
+                            err
+                        );
+                    }
+                }
+                write_offset(&segment, index + 1, self.config.sync)?;
+            }
+
+            remove_segment(&segment)?;
+        }
+
+        Ok(delivered)
+    }
+
+    fn evict(&self, state: &mut SpoolState) -> Result<(), AnalyticsError> {
+        let segments = list_segments(&self.config.dir)?;
+        let mut total: u64 = segments.iter().map(|(_, path)| file_size(path)).sum();
+
+        for (seq, path) in segments {
+            if total <= self.config.max_bytes {
+                break;
+            }
+            warn!(
+                "spool exceeds {} bytes, evicting {:?}",
+                self.config.max_bytes, path
+            );
+            total = total.saturating_sub(file_size(&path));
+            remove_segment(&path)?;
+            if state.active == Some(seq) {
+                state.active = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn segment_path(&self, seq: u64) -> PathBuf {
+        self.config
+            .dir
+            .join(format!("{:020}.{}", seq, SEGMENT_EXTENSION))
+    }
+}
+
+pub(crate) enum ReplayError {
+    Transient(AnalyticsError),
+    Rejected(AnalyticsError),
+}
+
+/// Periodically replays the spool of a shared client on a background thread.
+///
+/// The first replay runs as soon as the replayer starts, so events spooled
+/// by a previous run are delivered on startup. Dropping the replayer stops
+/// the thread.
+pub struct SpoolReplayer {
+    stop: Option<mpsc::Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl SpoolReplayer {
+    /// Start replaying `analytics`' spool every `interval`.
+    pub fn start(analytics: Arc<RudderAnalytics>, interval: Duration) -> SpoolReplayer {
+        let (stop, stopped) = mpsc::channel();
+        let worker = thread::Builder::new()
+            .name("rudderanalytics-spool".to_string())
+            .spawn(move || loop {
+                match analytics.replay_spool() {
+                    Ok(delivered) if delivered > 0 => {
+                        debug!("replayed {} spooled events", delivered)
+                    }
+                    Ok(_) => {}
+                    Err(err) => error!("failed to replay spool: {:?}", err),
+                }
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
+            })
+            .expect("failed to spawn rudderanalytics spool thread");
+
+        SpoolReplayer {
+            stop: Some(stop),
+            worker: Some(worker),
+        }
+    }
+
+    /// Stop the replay thread, waiting for an in-flight replay to finish.
+    pub fn stop(mut self) {
+        self.stop_worker();
+    }
+
+    fn stop_worker(&mut self) {
+        // dropping the sender wakes the worker up
+        self.stop.take();
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                error!("rudderanalytics spool thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for SpoolReplayer {
+    fn drop(&mut self) {
+        self.stop_worker();
+    }
+}
+
+// Set sentAt on the payload and, for a batch, on each of its members.
+fn restamp_sent_at(payload: &mut Value, sent_at: DateTime<Utc>) {
+    let sent_at = Value::from(sent_at.to_rfc3339());
+    if let Some(members) = payload.get_mut("batch").and_then(Value::as_array_mut) {
+        for member in members.iter_mut().filter_map(Value::as_object_mut) {
+            member.insert("sentAt".to_string(), sent_at.clone());
+        }
+    }
+    if let Some(payload) = payload.as_object_mut() {
+        payload.insert("sentAt".to_string(), sent_at);
+    }
+}
+
+fn list_segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+        if let Some(seq) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+        {
+            segments.push((seq, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+fn read_segment(path: &Path) -> io::Result<Vec<SpoolRecord>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // a torn write from a crash leaves a partial last line behind
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(err) => warn!("skipping corrupt spool record in {:?}: {}", path, err),
+        }
+    }
+    Ok(records)
+}
+
+// The number of records at the start of a segment that were already
+// replayed.
+fn read_offset(segment: &Path) -> io::Result<usize> {
+    let path = segment.with_extension(OFFSET_EXTENSION);
+    match fs::read_to_string(&path) {
+        Ok(offset) => Ok(offset.trim().parse().unwrap_or_else(|_| {
+            warn!("ignoring corrupt spool offset in {:?}", path);
+            0
+        })),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+fn write_offset(segment: &Path, offset: usize, sync: bool) -> io::Result<()> {
+    let tmp_path = segment.with_extension("tmp");
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(offset.to_string().as_bytes())?;
+        if sync {
+            file.sync_data()?;
+        }
+    }
+    fs::rename(&tmp_path, segment.with_extension(OFFSET_EXTENSION))
+}
+
+// The offset goes first: a crash in between then only means the segment is
+// replayed again, while a leftover offset could skip records of a new
+// segment with the same sequence number.
+fn remove_segment(path: &Path) -> io::Result<()> {
+    for path in [path.with_extension(OFFSET_EXTENSION), path.to_path_buf()] {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn spool(dir: &Path, max_bytes: u64, segment_bytes: u64) -> Spool {
+        Spool::open(SpoolConfig {
+            max_bytes,
+            segment_bytes,
+            ..SpoolConfig::new(dir)
+        })
+        .unwrap()
+    }
+
+    fn payload(i: usize) -> Value {
+        json!({ "type": "track", "event": format!("event-{}", i) })
+    }
+
+    #[test]
+    fn replays_in_order_and_empties_spool() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(dir.path(), 1024 * 1024, 128);
+        for i in 0..5 {
+            spool.append("/v1/track", &payload(i)).unwrap();
+        }
+        assert!(list_segments(dir.path()).unwrap().len() > 1);
+
+        let mut seen = Vec::new();
+        let delivered = spool
+            .replay(|path, payload| {
+                assert_eq!(path, "/v1/track");
+                seen.push(payload["event"].as_str().unwrap().to_string());
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(delivered, 5);
+        assert_eq!(
+            seen,
+            vec!["event-0", "event-1", "event-2", "event-3", "event-4"]
+        );
+        assert!(spool.is_empty().unwrap());
+    }
+
+    #[test]
+    fn stops_at_transient_failure_and_keeps_remaining() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(dir.path(), 1024 * 1024, 1024 * 1024);
+        for i in 0..3 {
+            spool.append("/v1/track", &payload(i)).unwrap();
+        }
+
+        let mut calls = 0;
+        let delivered = spool
+            .replay(|_, _| {
+                calls += 1;
+                if calls == 2 {
+                    Err(ReplayError::Transient(AnalyticsError::QueueClosed))
+                } else {
+                    Ok(())
+                }
+            })
+            .unwrap();
+
+        assert_eq!(delivered, 1);
+        assert_eq!(spool.len().unwrap(), 2);
+
+        let mut seen = Vec::new();
+        spool
+            .replay(|_, payload| {
+                seen.push(payload["event"].as_str().unwrap().to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(seen, vec!["event-1", "event-2"]);
+    }
+
+    #[test]
+    fn resumes_after_an_interrupted_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let interrupted = spool(dir.path(), 1024 * 1024, 1024 * 1024);
+        for i in 0..3 {
+            interrupted.append("/v1/track", &payload(i)).unwrap();
+        }
+
+        // the process dies while delivering the second payload
+        let mut calls = 0;
+        let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            interrupted.replay(|_, _| {
+                calls += 1;
+                if calls == 2 {
+                    panic!("crash");
+                }
+                Ok(())
+            })
+        }));
+        assert!(crashed.is_err());
+
+        let reopened = spool(dir.path(), 1024 * 1024, 1024 * 1024);
+        assert_eq!(reopened.len().unwrap(), 2);
+        let mut seen = Vec::new();
+        reopened
+            .replay(|_, payload| {
+                seen.push(payload["event"].as_str().unwrap().to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(seen, vec!["event-1", "event-2"]);
+        assert!(fs::read_dir(dir.path()).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn drops_rejected_payloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(dir.path(), 1024 * 1024, 1024 * 1024);
+        spool.append("/v1/track", &payload(0)).unwrap();
+
+        let delivered = spool
+            .replay(|_, _| Err(ReplayError::Rejected(AnalyticsError::QueueClosed)))
+            .unwrap();
+
+        assert_eq!(delivered, 0);
+        assert!(spool.is_empty().unwrap());
+    }
+
+    #[test]
+    fn evicts_oldest_segments_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let record_size = serde_json::to_vec(&SpoolRecord {
+            path: "/v1/track".to_string(),
+            payload: payload(0),
+        })
+        .unwrap()
+        .len() as u64
+            + 1;
+        let spool = spool(dir.path(), record_size * 3, record_size);
+        for i in 0..5 {
+            spool.append("/v1/track", &payload(i)).unwrap();
+        }
+
+        assert!(spool.size().unwrap() <= record_size * 3);
+        let mut seen = Vec::new();
+        spool
+            .replay(|_, payload| {
+                seen.push(payload["event"].as_str().unwrap().to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(seen, vec!["event-2", "event-3", "event-4"]);
+    }
+
+    #[test]
+    fn replay_restamps_sent_at() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(dir.path(), 1024 * 1024, 1024 * 1024);
+        let spooled_at = Utc::now();
+        spool
+            .append(
+                "/v1/batch",
+                &json!({
+                    "type": "batch",
+                    "sentAt": spooled_at,
+                    "batch": [{ "type": "track", "sentAt": spooled_at }],
+                }),
+            )
+            .unwrap();
+        thread::sleep(Duration::from_millis(10));
+
+        let mut sent_at = Vec::new();
+        spool
+            .replay(|_, payload| {
+                sent_at.push(payload["sentAt"].clone());
+                sent_at.push(payload["batch"][0]["sentAt"].clone());
+                Ok(())
+            })
+            .unwrap();
+
+        for value in sent_at {
+            let value: DateTime<Utc> = serde_json::from_value(value).unwrap();
+            assert!(value > spooled_at);
+        }
+    }
+
+    #[test]
+    fn continues_sequence_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        spool(dir.path(), 1024 * 1024, 1024 * 1024)
+            .append("/v1/track", &payload(0))
+            .unwrap();
+        spool(dir.path(), 1024 * 1024, 1024 * 1024)
+            .append("/v1/track", &payload(1))
+            .unwrap();
+
+        assert_eq!(list_segments(dir.path()).unwrap().len(), 2);
+        let reopened = spool(dir.path(), 1024 * 1024, 1024 * 1024);
+        let mut seen = Vec::new();
+        reopened
+            .replay(|_, payload| {
+                seen.push(payload["event"].as_str().unwrap().to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(seen, vec!["event-0", "event-1"]);
+    }
+}
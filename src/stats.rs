@@ -0,0 +1,303 @@
+//! Delivery statistics and observer hooks.
+//!
+//! Every client keeps counters of its delivery attempts, retries, bytes sent
+//! and request latency, available as a `StatsSnapshot`. To feed these into
+//! another metrics system, register a `DeliveryObserver`, which is called as
+//! each delivery event happens. With the `metrics` feature, `MetricsObserver`
+//! reports them through the `metrics` crate.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram buckets, in milliseconds.
+pub const LATENCY_BUCKETS_MS: [u64; 10] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 10000];
+
+/// Something that happened while delivering events.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeliveryEvent<'a> {
+    /// A request was made. `status` is `None` when no response was received.
+    Attempt {
+        path: &'a str,
+        status: Option<u16>,
+        latency: Duration,
+        bytes: usize,
+    },
+    /// A failed request will be retried after `delay`. `status` is `None`
+    /// for transport errors such as timeouts.
+    Retry {
+        path: &'a str,
+        status: Option<u16>,
+        delay: Duration,
+    },
+    /// Events were accepted by the data plane.
+    Delivered { path: &'a str, events: usize },
+    /// Events could not be delivered, after any retries.
+    Failed { path: &'a str, events: usize },
+    /// Events were dropped before delivery, e.g. by sampling or middleware.
+    Dropped { events: usize },
+    /// Events were handed to the fallback sink because the circuit breaker
+    /// was open.
+    Diverted { path: &'a str, events: usize },
+    /// Events were handed to the capture sink instead of the data plane.
+    Captured { path: &'a str, events: usize },
+}
+
+/// Receives delivery events as they happen.
+///
+/// Observers are called on the thread doing the delivery, so they should
+/// return quickly. Closures of type `Fn(&DeliveryEvent)` implement this
+/// trait.
+pub trait DeliveryObserver: Send + Sync {
+    fn observe(&self, event: &DeliveryEvent);
+}
+
+impl<F> DeliveryObserver for F
+where
+    F: Fn(&DeliveryEvent) + Send + Sync,
+{
+    fn observe(&self, event: &DeliveryEvent) {
+        self(event)
+    }
+}
+
+/// Request latencies, bucketed by `LATENCY_BUCKETS_MS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyHistogram {
+    /// Number of requests per bucket. The last entry counts requests slower
+    /// than the largest bound.
+    pub counts: Vec<u64>,
+    /// Total number of requests recorded.
+    pub count: u64,
+    /// Sum of all recorded latencies.
+    pub sum: Duration,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; LATENCY_BUCKETS_MS.len() + 1],
+            count: 0,
+            sum: Duration::from_secs(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Mean latency, or `None` before any request has been made.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let nanos = self.sum.as_nanos() / u128::from(self.count);
+        Some(
+            Duration::from_secs((nanos / 1_000_000_000) as u64)
+                + Duration::from_nanos((nanos % 1_000_000_000) as u64),
+        )
+    }
+
+    fn record(&mut self, latency: Duration) {
+        let millis = latency.as_millis();
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| millis <= u128::from(*bound))
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum += latency;
+    }
+}
+
+/// Delivery counters since the client was created.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatsSnapshot {
+    /// HTTP requests made, including retries.
+    pub attempts: u64,
+    /// Retries, by the status code of the response that was retried.
+    pub retries_by_status: BTreeMap<u16, u64>,
+    /// Retries after transport errors such as timeouts.
+    pub retries_on_error: u64,
+    /// Request body bytes sent, after compression.
+    pub bytes_sent: u64,
+    /// Events accepted by the data plane.
+    pub events_delivered: u64,
+    /// Events that could not be delivered.
+    pub events_failed: u64,
+    /// Events dropped before delivery.
+    pub events_dropped: u64,
+    /// Events handed to the fallback sink while the circuit breaker was open.
+    pub events_diverted: u64,
+    /// Events handed to the capture sink, which never reach the data plane.
+    pub events_captured: u64,
+    pub latency: LatencyHistogram,
+}
+
+impl StatsSnapshot {
+    /// Total number of retries.
+    pub fn retries(&self) -> u64 {
+        self.retries_by_status.values().sum::<u64>() + self.retries_on_error
+    }
+}
+
+/// Counters behind `StatsSnapshot`, shared between a client and its worker
+/// threads.
+#[derive(Default)]
+pub(crate) struct DeliveryStats {
+    snapshot: Mutex<StatsSnapshot>,
+}
+
+impl DeliveryStats {
+    pub(crate) fn snapshot(&self) -> StatsSnapshot {
+        self.snapshot.lock().unwrap().clone()
+    }
+
+    pub(crate) fn record(&self, event: &DeliveryEvent) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        match event {
+            DeliveryEvent::Attempt { latency, bytes, .. } => {
+                snapshot.attempts += 1;
+                snapshot.bytes_sent += *bytes as u64;
+                snapshot.latency.record(*latency);
+            }
+            DeliveryEvent::Retry {
+                status: Some(status),
+                ..
+            } => *snapshot.retries_by_status.entry(*status).or_insert(0) += 1,
+            DeliveryEvent::Retry { status: None, .. } => snapshot.retries_on_error += 1,
+            DeliveryEvent::Delivered { events, .. } => snapshot.events_delivered += *events as u64,
+            DeliveryEvent::Failed { events, .. } => snapshot.events_failed += *events as u64,
+            DeliveryEvent::Dropped { events } => snapshot.events_dropped += *events as u64,
+            DeliveryEvent::Diverted { events, .. } => snapshot.events_diverted += *events as u64,
+            DeliveryEvent::Captured { events, .. } => snapshot.events_captured += *events as u64,
+        }
+    }
+}
+
+/// Reports delivery events through the `metrics` crate.
+///
+/// Metrics are named `rudderanalytics_*`: the `attempts_total`,
+/// `retries_total`, `bytes_sent_total`, `events_delivered_total`,
+/// `events_failed_total`, `events_dropped_total`, `events_diverted_total`
+/// and `events_captured_total` counters, and the
+/// `request_duration_seconds` histogram. Requests are labelled with their
+/// `path` and `status`.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default)]
+pub struct MetricsObserver;
+
+#[cfg(feature = "metrics")]
+impl DeliveryObserver for MetricsObserver {
+    fn observe(&self, event: &DeliveryEvent) {
+        match event {
+            DeliveryEvent::Attempt {
+                path,
+                status,
+                latency,
+                bytes,
+            } => {
+                let status = status_label(*status);
+                metrics::counter!("rudderanalytics_attempts_total", "path" => path.to_string(), "status" => status.clone())
+                    .increment(1);
+                metrics::counter!("rudderanalytics_bytes_sent_total", "path" => path.to_string())
+                    .increment(*bytes as u64);
+                metrics::histogram!("rudderanalytics_request_duration_seconds", "path" => path.to_string(), "status" => status)
+                    .record(latency.as_secs_f64());
+            }
+            DeliveryEvent::Retry { path, status, .. } => {
+                metrics::counter!("rudderanalytics_retries_total", "path" => path.to_string(), "status" => status_label(*status))
+                    .increment(1);
+            }
+            DeliveryEvent::Delivered { path, events } => {
+                metrics::counter!("rudderanalytics_events_delivered_total", "path" => path.to_string())
+                    .increment(*events as u64);
+            }
+            DeliveryEvent::Failed { path, events } => {
+                metrics::counter!("rudderanalytics_events_failed_total", "path" => path.to_string())
+                    .increment(*events as u64);
+            }
+            DeliveryEvent::Dropped { events } => {
+                metrics::counter!("rudderanalytics_events_dropped_total").increment(*events as u64);
+            }
+            DeliveryEvent::Diverted { path, events } => {
+                metrics::counter!("rudderanalytics_events_diverted_total", "path" => path.to_string())
+                    .increment(*events as u64);
+            }
+            DeliveryEvent::Captured { path, events } => {
+                metrics::counter!("rudderanalytics_events_captured_total", "path" => path.to_string())
+                    .increment(*events as u64);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn status_label(status: Option<u16>) -> String {
+    status
+        .map(|status| status.to_string())
+        .unwrap_or_else(|| String::from("error"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_counters_and_latency_buckets() {
+        let stats = DeliveryStats::default();
+        for (status, millis) in [(Some(503), 3), (None, 40), (Some(200), 20_000)] {
+            stats.record(&DeliveryEvent::Attempt {
+                path: "/v1/track",
+                status,
+                latency: Duration::from_millis(millis),
+                bytes: 100,
+            });
+        }
+        for status in [Some(503), None] {
+            stats.record(&DeliveryEvent::Retry {
+                path: "/v1/track",
+                status,
+                delay: Duration::from_millis(100),
+            });
+        }
+        stats.record(&DeliveryEvent::Delivered {
+            path: "/v1/batch",
+            events: 5,
+        });
+        stats.record(&DeliveryEvent::Dropped { events: 2 });
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.attempts, 3);
+        assert_eq!(snapshot.bytes_sent, 300);
+        assert_eq!(snapshot.retries_by_status.get(&503), Some(&1));
+        assert_eq!(snapshot.retries_on_error, 1);
+        assert_eq!(snapshot.retries(), 2);
+        assert_eq!(snapshot.events_delivered, 5);
+        assert_eq!(snapshot.events_dropped, 2);
+        assert_eq!(snapshot.latency.count, 3);
+        assert_eq!(snapshot.latency.counts[0], 1);
+        assert_eq!(snapshot.latency.counts[3], 1);
+        assert_eq!(snapshot.latency.counts[LATENCY_BUCKETS_MS.len()], 1);
+        assert_eq!(
+            snapshot.latency.mean(),
+            Some(Duration::from_millis(20_043) / 3)
+        );
+    }
+
+    #[test]
+    fn mean_does_not_truncate_large_counts() {
+        let count = u64::from(u32::MAX) + 2;
+        let latency = LatencyHistogram {
+            count,
+            sum: Duration::from_millis(count),
+            ..Default::default()
+        };
+        assert_eq!(latency.mean(), Some(Duration::from_millis(1)));
+
+        let latency = LatencyHistogram {
+            count,
+            sum: Duration::from_secs(count * 3),
+            ..Default::default()
+        };
+        assert_eq!(latency.mean(), Some(Duration::from_secs(3)));
+    }
+}
@@ -0,0 +1,430 @@
+//! A mock RudderStack data plane for tests, enabled by the `test-util`
+//! feature.
+//!
+//! `MockDataPlane` listens on a local port and answers every request with the
+//! next scripted `MockResponse`, or `200 OK` once the script runs out. It
+//! records every request along with the `Ruddermessage` decoded from its
+//! body, so tests can assert on exactly what a client sent.
+//!
+//! ```no_run
+//! use rudderanalytics::client::RudderAnalytics;
+//! use rudderanalytics::message::{Message, Track};
+//! use rudderanalytics::test_util::{MockDataPlane, MockResponse};
+//!
+//! let data_plane = MockDataPlane::start();
+//! data_plane.enqueue(MockResponse::status(503).retry_after("1"));
+//!
+//! let rudder_analytics = RudderAnalytics::load("WRITE_KEY".to_string(), data_plane.url());
+//! rudder_analytics
+//!     .send(&Message::Track(Track {
+//!         user_id: Some("user-1".to_string()),
+//!         event: "Signup".to_string(),
+//!         ..Default::default()
+//!     }))
+//!     .unwrap();
+//!
+//! data_plane.assert_request_count(2);
+//! data_plane.assert_track_event("Signup");
+//! ```
+
+use crate::ruddermessage::{Batch, BatchMessage, Ruddermessage};
+use flate2::read::GzDecoder;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A scripted response of a `MockDataPlane`.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// Time to wait before responding.
+    pub delay: Duration,
+    /// Close the connection without responding.
+    pub drop_connection: bool,
+}
+
+impl MockResponse {
+    /// A response with `status` and an empty body.
+    pub fn status(status: u16) -> MockResponse {
+        MockResponse {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+            delay: Duration::from_secs(0),
+            drop_connection: false,
+        }
+    }
+
+    pub fn ok() -> MockResponse {
+        Self::status(200)
+    }
+
+    /// Close the connection after reading the request, without a response.
+    pub fn drop_connection() -> MockResponse {
+        MockResponse {
+            drop_connection: true,
+            ..Self::ok()
+        }
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> MockResponse {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Add a `Retry-After` header, in seconds or as an HTTP date.
+    pub fn retry_after(self, value: impl Into<String>) -> MockResponse {
+        self.header("Retry-After", value)
+    }
+
+    pub fn body(mut self, body: impl Into<String>) -> MockResponse {
+        self.body = body.into();
+        self
+    }
+
+    /// Wait `delay` before responding, e.g. to trigger client timeouts.
+    pub fn delay(mut self, delay: Duration) -> MockResponse {
+        self.delay = delay;
+        self
+    }
+}
+
+/// A request received by a `MockDataPlane`.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lower case.
+    pub headers: Vec<(String, String)>,
+    /// The body, gunzipped if it was sent with `Content-Encoding: gzip`.
+    pub body: Vec<u8>,
+    /// The payload decoded from the body, if it matches the path.
+    pub message: Option<Ruddermessage>,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_ascii_lowercase();
+        self.headers
+            .iter()
+            .find(|(header_name, _)| *header_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Default)]
+struct State {
+    script: VecDeque<MockResponse>,
+    requests: Vec<RecordedRequest>,
+}
+
+/// A local HTTP server standing in for the RudderStack data plane.
+///
+/// The server stops when the `MockDataPlane` is dropped.
+pub struct MockDataPlane {
+    url: String,
+    state: Arc<Mutex<State>>,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockDataPlane {
+    /// Start a server on a free local port.
+    pub fn start() -> MockDataPlane {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind mock data plane");
+        listener
+            .set_nonblocking(true)
+            .expect("failed to configure mock data plane");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let thread_state = Arc::clone(&state);
+        let thread_shutdown = Arc::clone(&shutdown);
+        let handle = thread::Builder::new()
+            .name("rudderanalytics-mock-data-plane".to_string())
+            .spawn(move || serve(listener, thread_state, thread_shutdown))
+            .expect("failed to spawn mock data plane");
+
+        MockDataPlane {
+            url,
+            state,
+            shutdown,
+            handle: Some(handle),
+        }
+    }
+
+    /// The data plane url to give to a client.
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Answer the next request with `response`. Scripted responses are used
+    /// in the order they were enqueued.
+    pub fn enqueue(&self, response: MockResponse) {
+        self.state.lock().unwrap().script.push_back(response);
+    }
+
+    /// Every request received so far.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub fn request_count(&self) -> usize {
+        self.state.lock().unwrap().requests.len()
+    }
+
+    /// The payloads received on `path`, e.g. `/v1/batch`.
+    pub fn messages(&self, path: &str) -> Vec<Ruddermessage> {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter(|request| request.path == path)
+            .filter_map(|request| request.message.clone())
+            .collect()
+    }
+
+    /// Every event received on any endpoint, with batches split into their
+    /// members.
+    pub fn events(&self) -> Vec<BatchMessage> {
+        let state = self.state.lock().unwrap();
+        let mut events = Vec::new();
+        for message in state
+            .requests
+            .iter()
+            .filter_map(|request| request.message.as_ref())
+        {
+            match message.clone() {
+                Ruddermessage::Identify(a_) => events.push(BatchMessage::Identify(a_)),
+                Ruddermessage::Track(a_) => events.push(BatchMessage::Track(a_)),
+                Ruddermessage::Page(a_) => events.push(BatchMessage::Page(a_)),
+                Ruddermessage::Screen(a_) => events.push(BatchMessage::Screen(a_)),
+                Ruddermessage::Group(a_) => events.push(BatchMessage::Group(a_)),
+                Ruddermessage::Alias(a_) => events.push(BatchMessage::Alias(a_)),
+                Ruddermessage::Batch(a_) => events.extend(a_.batch),
+            }
+        }
+        events
+    }
+
+    /// Block until at least `count` requests have been received, or
+    /// `timeout` has passed. Returns whether the requests arrived.
+    pub fn wait_for_requests(&self, count: usize, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.request_count() < count {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    /// Panic unless exactly `count` requests have been received.
+    pub fn assert_request_count(&self, count: usize) {
+        let actual = self.request_count();
+        assert_eq!(
+            actual, count,
+            "expected {} requests to the mock data plane, got {}",
+            count, actual
+        );
+    }
+
+    /// Panic unless a track event named `event` has been received, and
+    /// return it.
+    pub fn assert_track_event(&self, event: &str) -> crate::ruddermessage::Track {
+        let events = self.events();
+        events
+            .iter()
+            .find_map(|received| match received {
+                BatchMessage::Track(track) if track.event == event => Some(track.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| {
+                panic!(
+                    "expected a track event named {:?}, received {:?}",
+                    event, events
+                )
+            })
+    }
+}
+
+impl Drop for MockDataPlane {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve(listener: TcpListener, state: Arc<Mutex<State>>, shutdown: Arc<AtomicBool>) {
+    while !shutdown.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let _ = handle_connection(stream, &state);
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(5));
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+// Serve a single request; every response closes the connection.
+fn handle_connection(mut stream: TcpStream, state: &Mutex<State>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let request = match read_request(&mut stream)? {
+        Some(request) => request,
+        None => return Ok(()),
+    };
+
+    let response = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(request);
+        state.script.pop_front().unwrap_or_else(MockResponse::ok)
+    };
+
+    if response.delay > Duration::from_secs(0) {
+        thread::sleep(response.delay);
+    }
+    if response.drop_connection {
+        return Ok(());
+    }
+
+    let mut raw = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason(response.status),
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        raw.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    raw.push_str("\r\n");
+    raw.push_str(&response.body);
+    stream.write_all(raw.as_bytes())?;
+    stream.flush()
+}
+
+fn read_request(stream: &mut TcpStream) -> io::Result<Option<RecordedRequest>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+    let header_end = loop {
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position;
+        }
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        })
+        .collect();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(header_name, _)| header_name == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let content_length: usize = header("content-length")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = buffer[header_end + 4..].to_vec();
+    while body.len() < content_length {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+
+    if header("content-encoding") == Some("gzip") {
+        let mut decoded = Vec::new();
+        GzDecoder::new(&body[..]).read_to_end(&mut decoded)?;
+        body = decoded;
+    }
+    let message = decode_message(&path, &body);
+
+    Ok(Some(RecordedRequest {
+        method,
+        path,
+        headers,
+        body,
+        message,
+    }))
+}
+
+// The payload types cannot be told apart by their fields alone, so decode
+// them by endpoint.
+fn decode_message(path: &str, body: &[u8]) -> Option<Ruddermessage> {
+    match path {
+        "/v1/identify" => serde_json::from_slice(body)
+            .ok()
+            .map(Ruddermessage::Identify),
+        "/v1/track" => serde_json::from_slice(body).ok().map(Ruddermessage::Track),
+        "/v1/page" => serde_json::from_slice(body).ok().map(Ruddermessage::Page),
+        "/v1/screen" => serde_json::from_slice(body).ok().map(Ruddermessage::Screen),
+        "/v1/group" => serde_json::from_slice(body).ok().map(Ruddermessage::Group),
+        "/v1/alias" => serde_json::from_slice(body).ok().map(Ruddermessage::Alias),
+        "/v1/batch" => decode_batch(body).map(Ruddermessage::Batch),
+        _ => None,
+    }
+}
+
+// Batch members keep their `type` field, which the internally tagged
+// `BatchMessage` would consume before decoding the member itself.
+fn decode_batch(body: &[u8]) -> Option<Batch> {
+    let mut value: Value = serde_json::from_slice(body).ok()?;
+    let members = match value
+        .as_object_mut()?
+        .insert("batch".to_string(), Value::Array(Vec::new()))
+    {
+        Some(Value::Array(members)) => members,
+        _ => return None,
+    };
+    let mut batch: Batch = serde_json::from_value(value).ok()?;
+    for member in members {
+        let event = match member.get("type")?.as_str()? {
+            "identify" => BatchMessage::Identify(serde_json::from_value(member).ok()?),
+            "track" => BatchMessage::Track(serde_json::from_value(member).ok()?),
+            "page" => BatchMessage::Page(serde_json::from_value(member).ok()?),
+            "screen" => BatchMessage::Screen(serde_json::from_value(member).ok()?),
+            "group" => BatchMessage::Group(serde_json::from_value(member).ok()?),
+            "alias" => BatchMessage::Alias(serde_json::from_value(member).ok()?),
+            _ => return None,
+        };
+        batch.batch.push(event);
+    }
+    Some(batch)
+}
+
+fn reason(status: u16) -> &'static str {
+    reqwest::StatusCode::from_u16(status)
+        .ok()
+        .and_then(|status| status.canonical_reason())
+        .unwrap_or("Unknown")
+}
@@ -0,0 +1,442 @@
+//! Validation of events against a tracking plan before they are sent.
+//!
+//! A `Validator` checks `Track` properties and `Identify` traits. What
+//! happens to an event with violations depends on the `ValidationMode`.
+//! With the `tracking-plan` feature, `TrackingPlan` validates events against
+//! the JSON Schemas in a local tracking plan file.
+
+use crate::errors::Error as AnalyticsError;
+use crate::message::{BatchMessage, Message};
+use log::warn;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+#[cfg(feature = "tracking-plan")]
+use jsonschema::JSONSchema;
+#[cfg(feature = "tracking-plan")]
+use serde::Deserialize;
+#[cfg(feature = "tracking-plan")]
+use std::collections::HashMap;
+#[cfg(feature = "tracking-plan")]
+use std::path::Path;
+
+/// Context key under which violations are recorded in `Annotate` mode.
+pub const VIOLATIONS_CONTEXT_KEY: &str = "trackingPlanViolations";
+
+/// A single way in which an event does not match the tracking plan.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    /// JSON pointer to the offending value, e.g. `/properties/revenue`.
+    pub field: String,
+    /// Description of the violation.
+    pub message: String,
+}
+
+/// What to do with an event that has violations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationMode {
+    /// Fail `send` or `enqueue` with an error; the event is not sent.
+    Reject,
+    /// Log the violations and send the event unchanged.
+    Warn,
+    /// Send the event with its violations listed under the
+    /// `trackingPlanViolations` key of its context.
+    Annotate,
+}
+
+/// Checks events against a tracking plan.
+///
+/// Batch members are checked one at a time. Events other than track and
+/// identify are not validated.
+pub trait Validator: Send + Sync {
+    /// Check the properties of a track event.
+    fn validate_track(&self, _event: &str, _properties: Option<&Value>) -> Vec<Violation> {
+        Vec::new()
+    }
+
+    /// Check the traits of an identify event.
+    fn validate_identify(&self, _traits: Option<&Value>) -> Vec<Violation> {
+        Vec::new()
+    }
+}
+
+/// A validator together with the mode it is applied in.
+pub(crate) struct PlanValidation {
+    validator: Box<dyn Validator>,
+    mode: ValidationMode,
+}
+
+impl PlanValidation {
+    pub(crate) fn new(validator: Box<dyn Validator>, mode: ValidationMode) -> Self {
+        Self { validator, mode }
+    }
+
+    /// Check the message. Returns an annotated copy when violations were
+    /// found in `Annotate` mode, and `None` when the message is sent as is.
+    pub(crate) fn apply(&self, msg: &Message) -> Result<Option<Message>, AnalyticsError> {
+        let violations: Vec<Vec<Violation>> = match msg {
+            Message::Batch(b_) => b_
+                .batch
+                .iter()
+                .map(|event| self.batch_message_violations(event))
+                .collect(),
+            other => vec![self.message_violations(other)],
+        };
+        if violations.iter().all(Vec::is_empty) {
+            return Ok(None);
+        }
+
+        match self.mode {
+            ValidationMode::Reject => {
+                let field = violations
+                    .iter()
+                    .flatten()
+                    .next()
+                    .map(|violation| violation.field.clone())
+                    .unwrap_or_default();
+                Err(AnalyticsError::Validation {
+                    field,
+                    message: format!(
+                        "Tracking plan violation: {}",
+                        describe(violations.iter().flatten())
+                    ),
+                })
+            }
+            ValidationMode::Warn => {
+                warn!(
+                    "sending event with tracking plan violations: {}",
+                    describe(violations.iter().flatten())
+                );
+                Ok(None)
+            }
+            ValidationMode::Annotate => {
+                let mut annotated = msg.clone();
+                match &mut annotated {
+                    Message::Batch(b_) => {
+                        for (event, violations) in b_.batch.iter_mut().zip(violations) {
+                            if let Some(context) = batch_message_context(event) {
+                                annotate(context, violations);
+                            }
+                        }
+                    }
+                    other => {
+                        if let Some(context) = message_context(other) {
+                            annotate(context, violations.into_iter().flatten().collect());
+                        }
+                    }
+                }
+                Ok(Some(annotated))
+            }
+        }
+    }
+
+    fn message_violations(&self, msg: &Message) -> Vec<Violation> {
+        match msg {
+            Message::Track(a_) => self
+                .validator
+                .validate_track(&a_.event, a_.properties.as_ref()),
+            Message::Identify(a_) => self.validator.validate_identify(a_.traits.as_ref()),
+            _ => Vec::new(),
+        }
+    }
+
+    fn batch_message_violations(&self, msg: &BatchMessage) -> Vec<Violation> {
+        match msg {
+            BatchMessage::Track(a_) => self
+                .validator
+                .validate_track(&a_.event, a_.properties.as_ref()),
+            BatchMessage::Identify(a_) => self.validator.validate_identify(a_.traits.as_ref()),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn message_context(msg: &mut Message) -> Option<&mut Option<Value>> {
+    match msg {
+        Message::Track(a_) => Some(&mut a_.context),
+        Message::Identify(a_) => Some(&mut a_.context),
+        _ => None,
+    }
+}
+
+fn batch_message_context(msg: &mut BatchMessage) -> Option<&mut Option<Value>> {
+    match msg {
+        BatchMessage::Track(a_) => Some(&mut a_.context),
+        BatchMessage::Identify(a_) => Some(&mut a_.context),
+        _ => None,
+    }
+}
+
+fn annotate(context: &mut Option<Value>, violations: Vec<Violation>) {
+    if violations.is_empty() {
+        return;
+    }
+    match context.get_or_insert_with(|| json!({})) {
+        Value::Object(map) => {
+            map.insert(VIOLATIONS_CONTEXT_KEY.to_string(), json!(violations));
+        }
+        _ => warn!("cannot annotate tracking plan violations, context is not an object"),
+    }
+}
+
+fn describe<'a>(violations: impl Iterator<Item = &'a Violation>) -> String {
+    violations
+        .map(|violation| format!("{}: {}", violation.field, violation.message))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// A tracking plan with a JSON Schema for the properties of each planned
+/// event and, optionally, for identify traits.
+///
+/// The plan file is a JSON object of the form:
+///
+/// ```json
+/// {
+///   "events": {
+///     "Order Completed": {
+///       "type": "object",
+///       "properties": { "revenue": { "type": "number" } },
+///       "required": ["revenue"]
+///     }
+///   },
+///   "identify": { "type": "object" },
+///   "allowUnplannedEvents": false
+/// }
+/// ```
+///
+/// Track events missing from `events` are violations unless
+/// `allowUnplannedEvents` is `true`, which is the default.
+#[cfg(feature = "tracking-plan")]
+pub struct TrackingPlan {
+    events: HashMap<String, JSONSchema>,
+    identify: Option<JSONSchema>,
+    allow_unplanned_events: bool,
+}
+
+#[cfg(feature = "tracking-plan")]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrackingPlanFile {
+    #[serde(default)]
+    events: HashMap<String, Value>,
+    identify: Option<Value>,
+    #[serde(default = "allow_unplanned_events_default")]
+    allow_unplanned_events: bool,
+}
+
+#[cfg(feature = "tracking-plan")]
+fn allow_unplanned_events_default() -> bool {
+    true
+}
+
+#[cfg(feature = "tracking-plan")]
+impl TrackingPlan {
+    /// Load a tracking plan from a JSON file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<TrackingPlan, AnalyticsError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            AnalyticsError::InvalidConfig(format!(
+                "cannot read tracking plan {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        let plan: Value = serde_json::from_str(&contents).map_err(|err| {
+            AnalyticsError::InvalidConfig(format!(
+                "tracking plan {} is not valid JSON: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        Self::from_value(&plan)
+    }
+
+    /// Build a tracking plan from its JSON representation.
+    pub fn from_value(plan: &Value) -> Result<TrackingPlan, AnalyticsError> {
+        let plan: TrackingPlanFile = serde_json::from_value(plan.clone()).map_err(|err| {
+            AnalyticsError::InvalidConfig(format!("invalid tracking plan: {}", err))
+        })?;
+
+        let mut events = HashMap::new();
+        for (event, schema) in &plan.events {
+            events.insert(event.clone(), compile_schema(schema, event)?);
+        }
+        let identify = match &plan.identify {
+            Some(schema) => Some(compile_schema(schema, "identify")?),
+            None => None,
+        };
+
+        Ok(TrackingPlan {
+            events,
+            identify,
+            allow_unplanned_events: plan.allow_unplanned_events,
+        })
+    }
+}
+
+#[cfg(feature = "tracking-plan")]
+impl Validator for TrackingPlan {
+    fn validate_track(&self, event: &str, properties: Option<&Value>) -> Vec<Violation> {
+        match self.events.get(event) {
+            Some(schema) => schema_violations(schema, properties, "/properties"),
+            None if self.allow_unplanned_events => Vec::new(),
+            None => vec![Violation {
+                field: String::from("/event"),
+                message: format!("{:?} is not in the tracking plan", event),
+            }],
+        }
+    }
+
+    fn validate_identify(&self, traits: Option<&Value>) -> Vec<Violation> {
+        match &self.identify {
+            Some(schema) => schema_violations(schema, traits, "/traits"),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "tracking-plan")]
+fn compile_schema(schema: &Value, name: &str) -> Result<JSONSchema, AnalyticsError> {
+    JSONSchema::compile(schema).map_err(|err| {
+        AnalyticsError::InvalidConfig(format!(
+            "invalid tracking plan schema for {:?}: {}",
+            name, err
+        ))
+    })
+}
+
+// Missing properties or traits are validated as an empty object, so that
+// required fields are still reported.
+#[cfg(feature = "tracking-plan")]
+fn schema_violations(schema: &JSONSchema, value: Option<&Value>, prefix: &str) -> Vec<Violation> {
+    let empty = json!({});
+    let value = value.unwrap_or(&empty);
+    let violations = match schema.validate(value) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors
+            .map(|err| Violation {
+                field: format!("{}{}", prefix, err.instance_path),
+                message: err.to_string(),
+            })
+            .collect(),
+    };
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Batch, Identify, Page, Track};
+
+    struct RequireRevenue;
+
+    impl Validator for RequireRevenue {
+        fn validate_track(&self, _event: &str, properties: Option<&Value>) -> Vec<Violation> {
+            match properties.and_then(|properties| properties.get("revenue")) {
+                Some(_) => Vec::new(),
+                None => vec![Violation {
+                    field: String::from("/properties/revenue"),
+                    message: String::from("revenue is required"),
+                }],
+            }
+        }
+    }
+
+    fn track(properties: Value) -> Track {
+        Track {
+            user_id: Some(String::from("user-1")),
+            event: String::from("Order Completed"),
+            properties: Some(properties),
+            ..Default::default()
+        }
+    }
+
+    fn validation(mode: ValidationMode) -> PlanValidation {
+        PlanValidation::new(Box::new(RequireRevenue), mode)
+    }
+
+    #[test]
+    fn valid_events_are_sent_unchanged() {
+        for mode in [
+            ValidationMode::Reject,
+            ValidationMode::Warn,
+            ValidationMode::Annotate,
+        ] {
+            let msg = Message::Track(track(json!({ "revenue": 10 })));
+            assert_eq!(validation(mode).apply(&msg).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn reject_mode_fails_with_violations() {
+        let msg = Message::Track(track(json!({})));
+        match validation(ValidationMode::Reject).apply(&msg) {
+            Err(AnalyticsError::Validation { field, message }) => {
+                assert_eq!(field, "/properties/revenue");
+                assert!(message.contains("/properties/revenue"), "{}", message)
+            }
+            other => panic!("expected rejection, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn warn_mode_sends_event_unchanged() {
+        let msg = Message::Track(track(json!({})));
+        assert_eq!(validation(ValidationMode::Warn).apply(&msg).unwrap(), None);
+    }
+
+    #[test]
+    fn annotate_mode_records_violations_per_batch_member() {
+        let msg = Message::Batch(Batch {
+            batch: vec![
+                BatchMessage::Track(track(json!({ "revenue": 10 }))),
+                BatchMessage::Track(Track {
+                    context: Some(json!({ "app": "shop" })),
+                    ..track(json!({}))
+                }),
+                BatchMessage::Identify(Identify::default()),
+            ],
+            ..Default::default()
+        });
+
+        let annotated = validation(ValidationMode::Annotate).apply(&msg).unwrap();
+        let batch = match annotated {
+            Some(Message::Batch(b_)) => b_.batch,
+            other => panic!("expected an annotated batch, got {:?}", other),
+        };
+        let contexts: Vec<Option<Value>> = batch
+            .into_iter()
+            .map(|event| match event {
+                BatchMessage::Track(a_) => a_.context,
+                BatchMessage::Identify(a_) => a_.context,
+                other => panic!("unexpected batch member {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(contexts[0], None);
+        assert_eq!(
+            contexts[1],
+            Some(json!({
+                "app": "shop",
+                "trackingPlanViolations": [
+                    { "field": "/properties/revenue", "message": "revenue is required" }
+                ]
+            }))
+        );
+        assert_eq!(contexts[2], None);
+    }
+
+    #[test]
+    fn other_event_types_are_not_validated() {
+        let msg = Message::Page(Page {
+            user_id: Some(String::from("user-1")),
+            name: String::from("Home"),
+            ..Default::default()
+        });
+        assert_eq!(
+            validation(ValidationMode::Reject).apply(&msg).unwrap(),
+            None
+        );
+    }
+}
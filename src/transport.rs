@@ -0,0 +1,167 @@
+//! The HTTP layer between `RudderAnalytics` and the data plane.
+//!
+//! The client builds each request (url, headers and encoded body) and hands
+//! it to a `Transport`, which returns the response status, headers and body.
+//! Retries, stats and spooling all happen in the client, so a transport only
+//! has to make a single request. `reqwest::blocking::Client` is the default
+//! transport; implement the trait to use another HTTP stack or a mock.
+//! Closures of type `Fn(&TransportRequest) -> Result<TransportResponse,
+//! TransportError>` implement it too.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::StatusCode;
+use std::error::Error as StdError;
+use std::fmt;
+
+/// A request to the data plane.
+#[derive(Debug, Clone)]
+pub struct TransportRequest<'a> {
+    /// The full url, i.e. the data plane url followed by `path`.
+    pub url: String,
+    /// The API path, e.g. `/v1/batch`.
+    pub path: &'a str,
+    /// Authorization, content type and content encoding headers.
+    pub headers: &'a HeaderMap,
+    /// The JSON body, gzipped when the content encoding header says so.
+    pub body: &'a [u8],
+}
+
+/// The data plane's response to a request.
+#[derive(Debug, Clone)]
+pub struct TransportResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl TransportResponse {
+    /// A response with `status` and no headers or body.
+    pub fn new(status: StatusCode) -> TransportResponse {
+        TransportResponse {
+            status,
+            headers: HeaderMap::new(),
+            body: String::new(),
+        }
+    }
+}
+
+/// Why a request failed without a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportErrorKind {
+    /// The connection could not be established.
+    Connect,
+    /// The request timed out.
+    Timeout,
+    /// Any other failure. These are not retried.
+    Other,
+}
+
+/// A request that failed without a response from the data plane.
+#[derive(Debug)]
+pub struct TransportError {
+    kind: TransportErrorKind,
+    source: Box<dyn StdError + Send + Sync>,
+}
+
+impl TransportError {
+    pub fn new(
+        kind: TransportErrorKind,
+        source: impl Into<Box<dyn StdError + Send + Sync>>,
+    ) -> TransportError {
+        TransportError {
+            kind,
+            source: source.into(),
+        }
+    }
+
+    pub fn kind(&self) -> TransportErrorKind {
+        self.kind
+    }
+
+    /// Whether the request may succeed if it is made again.
+    pub fn is_retryable(&self) -> bool {
+        match self.kind {
+            TransportErrorKind::Connect | TransportErrorKind::Timeout => true,
+            TransportErrorKind::Other => false,
+        }
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            TransportErrorKind::Connect => write!(f, "connection failed: {}", self.source),
+            TransportErrorKind::Timeout => write!(f, "request timed out: {}", self.source),
+            TransportErrorKind::Other => write!(f, "request failed: {}", self.source),
+        }
+    }
+}
+
+impl StdError for TransportError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+impl From<reqwest::Error> for TransportError {
+    fn from(err: reqwest::Error) -> TransportError {
+        let kind = if err.is_connect() {
+            TransportErrorKind::Connect
+        } else if err.is_timeout() {
+            TransportErrorKind::Timeout
+        } else {
+            TransportErrorKind::Other
+        };
+        TransportError::new(kind, err)
+    }
+}
+
+/// Makes a single request to the data plane.
+pub trait Transport: Send + Sync {
+    fn send(&self, request: &TransportRequest) -> Result<TransportResponse, TransportError>;
+}
+
+impl<F> Transport for F
+where
+    F: Fn(&TransportRequest) -> Result<TransportResponse, TransportError> + Send + Sync,
+{
+    fn send(&self, request: &TransportRequest) -> Result<TransportResponse, TransportError> {
+        self(request)
+    }
+}
+
+impl Transport for reqwest::blocking::Client {
+    fn send(&self, request: &TransportRequest) -> Result<TransportResponse, TransportError> {
+        let response = self
+            .post(&request.url)
+            .headers(request.headers.clone())
+            .body(request.body.to_vec())
+            .send()?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().unwrap_or_default();
+        Ok(TransportResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+// Headers sent with every request: basic auth with the write key as the
+// user name, and the JSON content type.
+pub(crate) fn request_headers(write_key: &str, gzipped: bool) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let credentials = STANDARD.encode(format!("{}:", write_key));
+    if let Ok(mut value) = HeaderValue::from_str(&format!("Basic {}", credentials)) {
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if gzipped {
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    }
+    headers
+}
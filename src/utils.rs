@@ -12,6 +12,7 @@ use crate::ruddermessage::{
 };
 use serde_json::{json, Value};
 use chrono::prelude::*;
+use uuid::Uuid;
 
 // constants and reserved keywords
 const NAME: &str = "RudderStack Rust SDK";
@@ -20,7 +21,7 @@ static RESERVED_KEYS : [&str;1] = ["library"];
 const CHANNEL :&str = "server";
 
 // function to merge two objects
-fn merge(a: &mut Value, b: Value) {
+pub(crate) fn merge(a: &mut Value, b: Value) {
     match (a, b) {
         (a @ &mut Value::Object(_), Value::Object(b)) => {
             let a = a.as_object_mut().unwrap();
@@ -46,6 +47,11 @@ pub fn check_reserved_keywords_conflict(context: Value)->bool{
     result
 }
 
+// use the caller supplied message id or generate a new one
+fn message_id(message_id: &Option<String>) -> String {
+    message_id.clone().unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
 // Build and return static context fields
 fn get_default_context()->Value{
     let default_context = json!({
@@ -62,28 +68,23 @@ pub fn parse_identify(msg:&Identify)-> Ruddermessage{
     let mut modified_context = get_default_context();
     merge(&mut modified_context, msg.context.clone().unwrap_or(json!({})));
 
-    let original_timestamp;
     let sent_at = Utc::now();
-    if msg.original_timestamp==Option::None {
-        original_timestamp = Some(sent_at);
-    }else {
-        original_timestamp = msg.original_timestamp;
-    }
+    let original_timestamp = msg.original_timestamp.or(Some(sent_at));
     
-    let new_message = Ruddermessage::Identify(
+    Ruddermessage::Identify(
         Rudderidentify {
             user_id: msg.user_id.clone(),
             anonymous_id: msg.anonymous_id.clone(),
             traits: msg.traits.clone(),
-            original_timestamp: original_timestamp,
+            message_id: message_id(&msg.message_id),
+            original_timestamp,
             sent_at: Some(sent_at),
             integrations: msg.integrations.clone(),
             context: Some(modified_context),
             r#type: String::from("identify"),
             channel: CHANNEL.to_string()
         } 
-    );
-    new_message
+    )
 }
 
 // modify track payload to rudder format
@@ -91,29 +92,24 @@ pub fn parse_track(msg:&Track)-> Ruddermessage{
     let mut modified_context = get_default_context();
     merge(&mut modified_context, msg.context.clone().unwrap_or(json!({})));
 
-    let original_timestamp;
     let sent_at = Utc::now();
-    if msg.original_timestamp==Option::None {
-        original_timestamp = Some(sent_at);
-    }else {
-        original_timestamp = msg.original_timestamp;
-    }
+    let original_timestamp = msg.original_timestamp.or(Some(sent_at));
 
-    let new_message= Ruddermessage::Track(
+    Ruddermessage::Track(
         Ruddertrack {
             user_id: msg.user_id.clone(),
             anonymous_id: msg.anonymous_id.clone(),
             event: msg.event.clone(),
             properties: msg.properties.clone(),
-            original_timestamp: original_timestamp,
+            message_id: message_id(&msg.message_id),
+            original_timestamp,
             sent_at: Some(sent_at),
             integrations: msg.integrations.clone(),
             context: Some(modified_context),
             r#type: String::from("track"),
             channel: CHANNEL.to_string()
         }
-    );
-    new_message
+    )
 }
 
 // modify page payload to rudder format
@@ -121,29 +117,24 @@ pub fn parse_page(msg:&Page)-> Ruddermessage{
     let mut modified_context = get_default_context();
     merge(&mut modified_context, msg.context.clone().unwrap_or(json!({})));
 
-    let original_timestamp;
     let sent_at = Utc::now();
-    if msg.original_timestamp==Option::None {
-        original_timestamp = Some(sent_at);
-    }else {
-        original_timestamp = msg.original_timestamp;
-    }
+    let original_timestamp = msg.original_timestamp.or(Some(sent_at));
 
-    let new_message= Ruddermessage::Page(
+    Ruddermessage::Page(
         Rudderpage {
             user_id: msg.user_id.clone(),
             anonymous_id: msg.anonymous_id.clone(),
             name: msg.name.clone(),
             properties: msg.properties.clone(),
-            original_timestamp: original_timestamp,
+            message_id: message_id(&msg.message_id),
+            original_timestamp,
             sent_at: Some(sent_at),
             integrations: msg.integrations.clone(),
             context: Some(modified_context),
             r#type: String::from("page"),
             channel: CHANNEL.to_string()
         }
-    );
-    new_message
+    )
 }
 
 // modify screen payload to rudder format
@@ -151,29 +142,24 @@ pub fn parse_screen(msg:&Screen)-> Ruddermessage{
     let mut modified_context = get_default_context();
     merge(&mut modified_context, msg.context.clone().unwrap_or(json!({})));
 
-    let original_timestamp;
     let sent_at = Utc::now();
-    if msg.original_timestamp==Option::None {
-        original_timestamp = Some(sent_at);
-    }else {
-        original_timestamp = msg.original_timestamp;
-    }
+    let original_timestamp = msg.original_timestamp.or(Some(sent_at));
 
-    let new_message= Ruddermessage::Screen(
+    Ruddermessage::Screen(
         Rudderscreen {
             user_id: msg.user_id.clone(),
             anonymous_id: msg.anonymous_id.clone(),
             name: msg.name.clone(),
             properties: msg.properties.clone(),
-            original_timestamp: original_timestamp,
+            message_id: message_id(&msg.message_id),
+            original_timestamp,
             sent_at: Some(sent_at),
             integrations: msg.integrations.clone(),
             context: Some(modified_context),
             r#type: String::from("screen"),
             channel: CHANNEL.to_string()
         }
-    );
-    new_message
+    )
 }
 
 // modify group payload to rudder format
@@ -181,29 +167,24 @@ pub fn parse_group(msg:&Group)-> Ruddermessage{
     let mut modified_context = get_default_context();
     merge(&mut modified_context, msg.context.clone().unwrap_or(json!({})));
 
-    let original_timestamp;
     let sent_at = Utc::now();
-    if msg.original_timestamp==Option::None {
-        original_timestamp = Some(sent_at);
-    }else {
-        original_timestamp = msg.original_timestamp;
-    }
+    let original_timestamp = msg.original_timestamp.or(Some(sent_at));
 
-    let new_message= Ruddermessage::Group(
+    Ruddermessage::Group(
         Ruddergroup {
             user_id: msg.user_id.clone(),
             anonymous_id: msg.anonymous_id.clone(),
             group_id: msg.group_id.clone(),
             traits: msg.traits.clone(),
-            original_timestamp: original_timestamp,
+            message_id: message_id(&msg.message_id),
+            original_timestamp,
             sent_at: Some(sent_at),
             integrations: msg.integrations.clone(),
             context: Some(modified_context),
             r#type: String::from("group"),
             channel: CHANNEL.to_string()
         }
-    );
-    new_message
+    )
 }
 
 // modify alias payload to rudder format
@@ -211,182 +192,302 @@ pub fn parse_alias(msg:&Alias)-> Ruddermessage{
     let mut modified_context = get_default_context();
     merge(&mut modified_context, msg.context.clone().unwrap_or(json!({})));
 
-    let original_timestamp;
     let sent_at = Utc::now();
-    if msg.original_timestamp==Option::None {
-        original_timestamp = Some(sent_at);
-    }else {
-        original_timestamp = msg.original_timestamp;
-    }
+    let original_timestamp = msg.original_timestamp.or(Some(sent_at));
 
-    let new_message= Ruddermessage::Alias(
+    Ruddermessage::Alias(
         Rudderalias {
             user_id: msg.user_id.clone(),
             previous_id: msg.previous_id.clone(),
             traits: msg.traits.clone(),
-            original_timestamp: original_timestamp,
+            message_id: message_id(&msg.message_id),
+            original_timestamp,
             sent_at: Some(sent_at),
             integrations: msg.integrations.clone(),
             context: Some(modified_context),
             r#type: String::from("alias"),
             channel: CHANNEL.to_string()
         }
-    );
-    new_message
+    )
 }
 
 // modify batch payload to rudder format
 pub fn parse_batch(msg:&Batch)-> Ruddermessage{
-    let default_context = get_default_context();
     let batch_context = msg.context.clone().unwrap_or(json!({}));
 
-    let original_timestamp;
     let sent_at = Utc::now();
-    if msg.original_timestamp==Option::None {
-        original_timestamp = Some(sent_at);
-    }else {
-        original_timestamp = msg.original_timestamp;
-    }
+    let original_timestamp = msg.original_timestamp.or(Some(sent_at));
 
-    let mut batch:Vec<Rudderbatchmessage> = Vec::new();
+    let batch:Vec<Rudderbatchmessage> = msg.batch.iter()
+        .map(|i| parse_batch_message(i, &batch_context, original_timestamp, sent_at))
+        .collect();
 
-    for i in &msg.batch {
-        match i {
-            BatchMessage::Identify(a_) =>{
-                let mut event_context: Value = a_.context.clone().unwrap_or(json!({}));
-                let mut final_context: Value = batch_context.clone();
-                merge(&mut final_context, event_context.clone());
-                merge(&mut final_context, default_context.clone());
+    Ruddermessage::Batch(
+        Rudderbatch {
+            batch,
+            integrations: msg.integrations.clone(),
+            context: Some(batch_context),
+            r#type: String::from("batch"),
+            original_timestamp,
+            sent_at: Some(sent_at),
+        }
+    )
+}
 
-                batch.push(Rudderbatchmessage::Identify(Rudderidentify 
-                {
+// modify a batch member to rudder format, layering the batch and default context.
+// Members keep their own originalTimestamp and only fall back to the batch one
+// when it is not set. sentAt is the same for the batch and all of its members:
+// RudderStack compares it with the time the request was received to correct
+// every originalTimestamp in the batch for client clock skew.
+pub fn parse_batch_message(msg:&BatchMessage, batch_context:&Value, batch_timestamp:Option<DateTime<Utc>>, sent_at:DateTime<Utc>)-> Rudderbatchmessage{
+    let default_context = get_default_context();
+
+    match msg {
+        BatchMessage::Identify(a_) =>{
+            let event_context: Value = a_.context.clone().unwrap_or(json!({}));
+            let mut final_context: Value = batch_context.clone();
+            merge(&mut final_context, event_context.clone());
+            merge(&mut final_context, default_context.clone());
+
+            Rudderbatchmessage::Identify(Rudderidentify 
+            {
+                user_id: a_.user_id.clone(),
+                anonymous_id: a_.anonymous_id.clone(),
+                traits: a_.traits.clone(),
+                message_id: message_id(&a_.message_id),
+                original_timestamp: a_.original_timestamp.or(batch_timestamp),
+                sent_at: Some(sent_at),
+                integrations: a_.integrations.clone(),
+                context: Some(final_context),
+                r#type: String::from("identify"),
+                channel: CHANNEL.to_string()
+            })
+        },           
+        BatchMessage::Track(a_) =>{
+            let event_context: Value = a_.context.clone().unwrap_or(json!({}));
+            let mut final_context: Value = batch_context.clone();
+            merge(&mut final_context, event_context.clone());
+            merge(&mut final_context, default_context.clone());
+
+            Rudderbatchmessage::Track(
+                Ruddertrack {
                     user_id: a_.user_id.clone(),
                     anonymous_id: a_.anonymous_id.clone(),
+                    event: a_.event.clone(),
+                    properties: a_.properties.clone(),
+                    message_id: message_id(&a_.message_id),
+                    original_timestamp: a_.original_timestamp.or(batch_timestamp),
+                    sent_at: Some(sent_at),
+                    integrations: a_.integrations.clone(),
+                    context: Some(final_context),
+                    r#type: String::from("track"),
+                    channel: CHANNEL.to_string()
+                }
+            )
+        },           
+        BatchMessage::Page(a_) =>{
+            let event_context: Value = a_.context.clone().unwrap_or(json!({}));
+            let mut final_context: Value = batch_context.clone();
+            merge(&mut final_context, event_context.clone());
+            merge(&mut final_context, default_context.clone());
+
+            Rudderbatchmessage::Page(
+                Rudderpage {
+                    user_id: a_.user_id.clone(),
+                    anonymous_id: a_.anonymous_id.clone(),
+                    name: a_.name.clone(),
+                    properties: a_.properties.clone(),
+                    message_id: message_id(&a_.message_id),
+                    original_timestamp: a_.original_timestamp.or(batch_timestamp),
+                    sent_at: Some(sent_at),
+                    integrations: a_.integrations.clone(),
+                    context: Some(final_context),
+                    r#type: String::from("page"),
+                    channel: CHANNEL.to_string()
+                }
+            )
+        },           
+        BatchMessage::Screen(a_) =>{
+            let event_context: Value = a_.context.clone().unwrap_or(json!({}));
+            let mut final_context: Value = batch_context.clone();
+            merge(&mut final_context, event_context.clone());
+            merge(&mut final_context, default_context.clone());
+
+            Rudderbatchmessage::Screen(
+                Rudderscreen {
+                    user_id: a_.user_id.clone(),
+                    anonymous_id: a_.anonymous_id.clone(),
+                    name: a_.name.clone(),
+                    properties: a_.properties.clone(),
+                    message_id: message_id(&a_.message_id),
+                    original_timestamp: a_.original_timestamp.or(batch_timestamp),
+                    sent_at: Some(sent_at),
+                    integrations: a_.integrations.clone(),
+                    context: Some(final_context),
+                    r#type: String::from("screen"),
+                    channel: CHANNEL.to_string()
+                }
+            )
+        },           
+        BatchMessage::Group(a_) =>{
+            let event_context: Value = a_.context.clone().unwrap_or(json!({}));
+            let mut final_context: Value = batch_context.clone();
+            merge(&mut final_context, event_context.clone());
+            merge(&mut final_context, default_context.clone());
+
+            Rudderbatchmessage::Group(
+                Ruddergroup {
+                    user_id: a_.user_id.clone(),
+                    anonymous_id: a_.anonymous_id.clone(),
+                    group_id: a_.group_id.clone(),
                     traits: a_.traits.clone(),
-                    original_timestamp: original_timestamp,
+                    message_id: message_id(&a_.message_id),
+                    original_timestamp: a_.original_timestamp.or(batch_timestamp),
                     sent_at: Some(sent_at),
                     integrations: a_.integrations.clone(),
                     context: Some(final_context),
-                    r#type: String::from("identify"),
+                    r#type: String::from("group"),
                     channel: CHANNEL.to_string()
-                }));
-            },           
-            BatchMessage::Track(a_) =>{
-                let mut event_context: Value = a_.context.clone().unwrap_or(json!({}));
-                let mut final_context: Value = batch_context.clone();
-                merge(&mut final_context, event_context.clone());
-                merge(&mut final_context, default_context.clone());
-
-                batch.push(Rudderbatchmessage::Track(
-                    Ruddertrack {
-                        user_id: a_.user_id.clone(),
-                        anonymous_id: a_.anonymous_id.clone(),
-                        event: a_.event.clone(),
-                        properties: a_.properties.clone(),
-                        original_timestamp: original_timestamp,
-                        sent_at: Some(sent_at),
-                        integrations: a_.integrations.clone(),
-                        context: Some(final_context),
-                        r#type: String::from("track"),
-                        channel: CHANNEL.to_string()
-                    }
-                ));
-            },           
-            BatchMessage::Page(a_) =>{
-                let mut event_context: Value = a_.context.clone().unwrap_or(json!({}));
-                let mut final_context: Value = batch_context.clone();
-                merge(&mut final_context, event_context.clone());
-                merge(&mut final_context, default_context.clone());
-
-                batch.push(Rudderbatchmessage::Page(
-                    Rudderpage {
-                        user_id: a_.user_id.clone(),
-                        anonymous_id: a_.anonymous_id.clone(),
-                        name: a_.name.clone(),
-                        properties: a_.properties.clone(),
-                        original_timestamp: original_timestamp,
-                        sent_at: Some(sent_at),
-                        integrations: a_.integrations.clone(),
-                        context: Some(final_context),
-                        r#type: String::from("page"),
-                        channel: CHANNEL.to_string()
-                    }
-                ));
-            },           
-            BatchMessage::Screen(a_) =>{
-                let mut event_context: Value = a_.context.clone().unwrap_or(json!({}));
-                let mut final_context: Value = batch_context.clone();
-                merge(&mut final_context, event_context.clone());
-                merge(&mut final_context, default_context.clone());
-
-                batch.push(Rudderbatchmessage::Screen(
-                    Rudderscreen {
-                        user_id: a_.user_id.clone(),
-                        anonymous_id: a_.anonymous_id.clone(),
-                        name: a_.name.clone(),
-                        properties: a_.properties.clone(),
-                        original_timestamp: original_timestamp,
-                        sent_at: Some(sent_at),
-                        integrations: a_.integrations.clone(),
-                        context: Some(final_context),
-                        r#type: String::from("screen"),
-                        channel: CHANNEL.to_string()
-                    }
-                ));
-            },           
-            BatchMessage::Group(a_) =>{
-                let mut event_context: Value = a_.context.clone().unwrap_or(json!({}));
-                let mut final_context: Value = batch_context.clone();
-                merge(&mut final_context, event_context.clone());
-                merge(&mut final_context, default_context.clone());
-
-                batch.push(Rudderbatchmessage::Group(
-                    Ruddergroup {
-                        user_id: a_.user_id.clone(),
-                        anonymous_id: a_.anonymous_id.clone(),
-                        group_id: a_.group_id.clone(),
-                        traits: a_.traits.clone(),
-                        original_timestamp: original_timestamp,
-                        sent_at: Some(sent_at),
-                        integrations: a_.integrations.clone(),
-                        context: Some(final_context),
-                        r#type: String::from("group"),
-                        channel: CHANNEL.to_string()
-                    }
-                ));
-            },           
-            BatchMessage::Alias(a_) =>{
-                let mut event_context: Value = a_.context.clone().unwrap_or(json!({}));
-                let mut final_context: Value = batch_context.clone();
-                merge(&mut final_context, event_context.clone());
-                merge(&mut final_context, default_context.clone());
-
-                batch.push(Rudderbatchmessage::Alias(
-                    Rudderalias {
-                        user_id: a_.user_id.clone(),
-                        previous_id: a_.previous_id.clone(),
-                        traits: a_.traits.clone(),
-                        original_timestamp: original_timestamp,
-                        sent_at: Some(sent_at),
-                        integrations: a_.integrations.clone(),
-                        context: Some(final_context),
-                        r#type: String::from("alias"),
-                        channel: CHANNEL.to_string()
-                    }
-                ));
-            },           
+                }
+            )
+        },           
+        BatchMessage::Alias(a_) =>{
+            let event_context: Value = a_.context.clone().unwrap_or(json!({}));
+            let mut final_context: Value = batch_context.clone();
+            merge(&mut final_context, event_context.clone());
+            merge(&mut final_context, default_context.clone());
+
+            Rudderbatchmessage::Alias(
+                Rudderalias {
+                    user_id: a_.user_id.clone(),
+                    previous_id: a_.previous_id.clone(),
+                    traits: a_.traits.clone(),
+                    message_id: message_id(&a_.message_id),
+                    original_timestamp: a_.original_timestamp.or(batch_timestamp),
+                    sent_at: Some(sent_at),
+                    integrations: a_.integrations.clone(),
+                    context: Some(final_context),
+                    r#type: String::from("alias"),
+                    channel: CHANNEL.to_string()
+                }
+            )
+        },           
+    }
+}
+
+// layer the client level default context underneath every event's context,
+// so keys set on the event win over the defaults
+pub fn apply_default_context(msg: &mut Ruddermessage, default_context: &Value) {
+    match msg {
+        Ruddermessage::Identify(a_) => layer_context(&mut a_.context, default_context),
+        Ruddermessage::Track(a_) => layer_context(&mut a_.context, default_context),
+        Ruddermessage::Page(a_) => layer_context(&mut a_.context, default_context),
+        Ruddermessage::Screen(a_) => layer_context(&mut a_.context, default_context),
+        Ruddermessage::Group(a_) => layer_context(&mut a_.context, default_context),
+        Ruddermessage::Alias(a_) => layer_context(&mut a_.context, default_context),
+        Ruddermessage::Batch(b_) => {
+            for member in &mut b_.batch {
+                match member {
+                    Rudderbatchmessage::Identify(a_) => layer_context(&mut a_.context, default_context),
+                    Rudderbatchmessage::Track(a_) => layer_context(&mut a_.context, default_context),
+                    Rudderbatchmessage::Page(a_) => layer_context(&mut a_.context, default_context),
+                    Rudderbatchmessage::Screen(a_) => layer_context(&mut a_.context, default_context),
+                    Rudderbatchmessage::Group(a_) => layer_context(&mut a_.context, default_context),
+                    Rudderbatchmessage::Alias(a_) => layer_context(&mut a_.context, default_context),
+                }
+            }
         }
     }
+}
 
-    let new_message= Ruddermessage::Batch(
-        Rudderbatch {
-            batch: batch,
-            integrations: msg.integrations.clone(),
-            context: Some(batch_context),
-            r#type: String::from("batch"),
-            original_timestamp: original_timestamp,
-            sent_at: Some(sent_at),
+fn layer_context(context: &mut Option<Value>, default_context: &Value) {
+    let mut layered = default_context.clone();
+    merge(&mut layered, context.take().unwrap_or_else(|| json!({})));
+    *context = Some(layered);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    type Timestamps = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+    fn track_at(user_id: &str, original_timestamp: Option<DateTime<Utc>>) -> BatchMessage {
+        BatchMessage::Track(Track {
+            user_id: Some(user_id.to_string()),
+            event: "Batched".to_string(),
+            original_timestamp,
+            ..Default::default()
+        })
+    }
+
+    fn member_timestamps(msg: &Ruddermessage) -> Vec<Timestamps> {
+        match msg {
+            Ruddermessage::Batch(b_) => b_
+                .batch
+                .iter()
+                .map(|member| match member {
+                    Rudderbatchmessage::Track(a_) => (a_.original_timestamp, a_.sent_at),
+                    other => panic!("unexpected batch member {:?}", other),
+                })
+                .collect(),
+            other => panic!("expected a batch, got {:?}", other),
         }
-    );
-    new_message
+    }
+
+    #[test]
+    fn batch_members_keep_their_own_original_timestamp() {
+        let batch_timestamp = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let first = batch_timestamp - Duration::seconds(60);
+        let second = batch_timestamp - Duration::seconds(30);
+
+        let msg = parse_batch(&Batch {
+            batch: vec![track_at("a", Some(first)), track_at("b", Some(second))],
+            original_timestamp: Some(batch_timestamp),
+            ..Default::default()
+        });
+
+        let timestamps = member_timestamps(&msg);
+        assert_eq!(timestamps[0].0, Some(first));
+        assert_eq!(timestamps[1].0, Some(second));
+    }
+
+    #[test]
+    fn batch_members_fall_back_to_batch_timestamp() {
+        let batch_timestamp = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let own = batch_timestamp - Duration::seconds(60);
+
+        let msg = parse_batch(&Batch {
+            batch: vec![track_at("a", None), track_at("b", Some(own))],
+            original_timestamp: Some(batch_timestamp),
+            ..Default::default()
+        });
+
+        let timestamps = member_timestamps(&msg);
+        assert_eq!(timestamps[0].0, Some(batch_timestamp));
+        assert_eq!(timestamps[1].0, Some(own));
+    }
+
+    #[test]
+    fn batch_members_share_the_batch_sent_at() {
+        let before = Utc::now();
+        let msg = parse_batch(&Batch {
+            batch: vec![
+                track_at("a", Some(before - Duration::seconds(60))),
+                track_at("b", None),
+            ],
+            ..Default::default()
+        });
+
+        let batch_sent_at = match &msg {
+            Ruddermessage::Batch(b_) => b_.sent_at.unwrap(),
+            other => panic!("expected a batch, got {:?}", other),
+        };
+        assert!(batch_sent_at >= before);
+        for (original_timestamp, sent_at) in member_timestamps(&msg) {
+            assert_eq!(sent_at, Some(batch_sent_at));
+            assert!(original_timestamp.unwrap() <= batch_sent_at);
+        }
+    }
 }
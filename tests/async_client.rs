@@ -0,0 +1,136 @@
+#![cfg(feature = "async")]
+
+use rudderanalytics::async_client::AsyncRudderAnalytics;
+use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::message::{Message, Track};
+use rudderanalytics::redaction::{FieldRule, RedactionAction, RedactionConfig, MASK};
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::test_util::{MockDataPlane, MockResponse};
+use std::time::{Duration, Instant};
+
+fn async_analytics_with_retries(server_url: String, max_retries: u32) -> AsyncRudderAnalytics {
+    AsyncRudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        server_url,
+        RetryConfig {
+            max_retries,
+            base_delay: Duration::from_millis(0),
+            max_backoff_delay: Duration::from_millis(0),
+            jitter_ratio: 0.0,
+            ..Default::default()
+        },
+    )
+}
+
+fn track_message() -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: "Async Event".to_string(),
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn sends_track_event() {
+    let data_plane = MockDataPlane::start();
+    let analytics = async_analytics_with_retries(data_plane.url(), 3);
+
+    let result = analytics.send(&track_message()).await;
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0];
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.path, "/v1/track");
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["event"], "Async Event");
+    assert_eq!(body["context"]["library"]["name"], "RudderStack Rust SDK");
+}
+
+#[tokio::test]
+async fn redacts_personal_data() {
+    let data_plane = MockDataPlane::start();
+    let analytics = async_analytics_with_retries(data_plane.url(), 0)
+        .with_redaction(RedactionConfig {
+            rules: vec![FieldRule::new("properties.email", RedactionAction::Mask)],
+            ..RedactionConfig::hash_pii("salt")
+        })
+        .unwrap();
+
+    let result = analytics
+        .send(&Message::Track(Track {
+            user_id: Some("user-1".to_string()),
+            event: "Async Event".to_string(),
+            properties: Some(serde_json::json!({
+                "email": "jane@example.com",
+                "note": "call 555-123-4567",
+            })),
+            ..Default::default()
+        }))
+        .await;
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    let body = String::from_utf8_lossy(&requests[0].body);
+    assert!(!body.contains("jane@example.com"));
+    assert!(!body.contains("555-123-4567"));
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["properties"]["email"], MASK);
+}
+
+#[tokio::test]
+async fn retries_429_with_retry_after() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(429).retry_after("1"));
+    let analytics = async_analytics_with_retries(data_plane.url(), 3);
+
+    let start = Instant::now();
+    let result = analytics.send(&track_message()).await;
+    let elapsed = start.elapsed();
+    let request_count = data_plane.request_count();
+
+    assert!(result.is_ok());
+    assert_eq!(request_count, 2);
+    assert!(
+        elapsed >= Duration::from_secs(1),
+        "expected Retry-After delay to be honored, elapsed {:?}",
+        elapsed
+    );
+}
+
+#[tokio::test]
+async fn returns_error_after_retry_budget_is_exhausted() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503));
+    data_plane.enqueue(MockResponse::status(503));
+    let analytics = async_analytics_with_retries(data_plane.url(), 1);
+
+    let result = analytics.send(&track_message()).await;
+    let request_count = data_plane.request_count();
+
+    match result {
+        Err(AnalyticsError::RetriesExhausted {
+            attempts, status, ..
+        }) => {
+            assert_eq!(attempts, 2);
+            assert_eq!(status, Some(503));
+        }
+        other => panic!("expected retries exhausted error, got {:?}", other),
+    }
+    assert_eq!(request_count, 2);
+}
+
+#[tokio::test]
+async fn rejects_message_without_user_or_anonymous_id() {
+    let analytics = async_analytics_with_retries("http://127.0.0.1:1".to_string(), 0);
+
+    let result = analytics
+        .send(&Message::Track(Track {
+            event: "No User".to_string(),
+            ..Default::default()
+        }))
+        .await;
+
+    assert!(matches!(result, Err(AnalyticsError::Validation { .. })));
+}
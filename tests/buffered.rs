@@ -0,0 +1,200 @@
+use rudderanalytics::buffered::{BufferConfig, BufferedRudderAnalytics};
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::message::{Batch, BatchMessage, Message, Track};
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::test_util::MockDataPlane;
+use serde_json::{json, Value};
+use std::thread;
+use std::time::Duration;
+
+fn buffered_analytics(server_url: String, config: BufferConfig) -> BufferedRudderAnalytics {
+    let analytics = RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        server_url,
+        RetryConfig::disabled(),
+    );
+    BufferedRudderAnalytics::new(analytics, config)
+}
+
+fn track(user_id: &str) -> Track {
+    Track {
+        user_id: Some(user_id.to_string()),
+        event: "Buffered Event".to_string(),
+        ..Default::default()
+    }
+}
+
+fn batch_body(body: &[u8]) -> Vec<Value> {
+    let body: Value = serde_json::from_slice(body).unwrap();
+    assert_eq!(body["type"], "batch");
+    body["batch"].as_array().unwrap().clone()
+}
+
+#[test]
+fn flushes_when_batch_size_is_reached() {
+    let data_plane = MockDataPlane::start();
+    let analytics = buffered_analytics(
+        data_plane.url(),
+        BufferConfig {
+            max_batch_size: 2,
+            flush_interval: Duration::from_secs(60),
+            ..Default::default()
+        },
+    );
+
+    for i in 0..4 {
+        analytics
+            .enqueue(Message::Track(track(&format!("user-{}", i))))
+            .unwrap();
+    }
+    analytics.shutdown();
+    let requests = data_plane.requests();
+
+    assert_eq!(requests.len(), 2);
+    for request in &requests {
+        assert_eq!(request.path, "/v1/batch");
+        assert_eq!(batch_body(&request.body).len(), 2);
+    }
+}
+
+#[test]
+fn flush_sends_partial_batch() {
+    let data_plane = MockDataPlane::start();
+    let analytics = buffered_analytics(
+        data_plane.url(),
+        BufferConfig {
+            flush_interval: Duration::from_secs(60),
+            ..Default::default()
+        },
+    );
+
+    analytics.enqueue(Message::Track(track("user-1"))).unwrap();
+    analytics.flush().unwrap();
+    let requests = data_plane.requests();
+    analytics.shutdown();
+
+    assert_eq!(requests.len(), 1);
+    let events = batch_body(&requests[0].body);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["userId"], "user-1");
+    assert_eq!(events[0]["type"], "track");
+}
+
+#[test]
+fn flushes_on_interval() {
+    let data_plane = MockDataPlane::start();
+    let analytics = buffered_analytics(
+        data_plane.url(),
+        BufferConfig {
+            flush_interval: Duration::from_millis(100),
+            ..Default::default()
+        },
+    );
+
+    analytics.enqueue(Message::Track(track("user-1"))).unwrap();
+    let flushed = data_plane.wait_for_requests(1, Duration::from_millis(500));
+    analytics.shutdown();
+
+    assert!(flushed);
+    data_plane.assert_request_count(1);
+}
+
+#[test]
+fn enqueue_rejects_invalid_messages() {
+    let data_plane = MockDataPlane::start();
+    let analytics = buffered_analytics(data_plane.url(), BufferConfig::default());
+
+    let result = analytics.enqueue(Message::Track(Track {
+        event: "No User".to_string(),
+        ..Default::default()
+    }));
+    analytics.shutdown();
+
+    assert!(matches!(result, Err(AnalyticsError::Validation { .. })));
+    data_plane.assert_request_count(0);
+}
+
+#[test]
+fn batches_that_do_not_fit_are_not_queued() {
+    let data_plane = MockDataPlane::start();
+    let analytics = buffered_analytics(
+        data_plane.url(),
+        BufferConfig {
+            queue_capacity: 2,
+            ..Default::default()
+        },
+    );
+    let batch = |size: usize| {
+        Message::Batch(Batch {
+            batch: (0..size)
+                .map(|i| BatchMessage::Track(track(&format!("user-{}", i))))
+                .collect(),
+            ..Default::default()
+        })
+    };
+
+    let too_large = analytics.enqueue(batch(3));
+    let fits = analytics.enqueue(batch(2));
+    analytics.shutdown();
+
+    assert!(matches!(too_large, Err(AnalyticsError::QueueFull)));
+    assert!(fits.is_ok());
+    data_plane.assert_request_count(1);
+    assert_eq!(batch_body(&data_plane.requests()[0].body).len(), 2);
+}
+
+#[test]
+fn enqueued_batch_members_inherit_batch_context() {
+    let data_plane = MockDataPlane::start();
+    let analytics = buffered_analytics(data_plane.url(), BufferConfig::default());
+
+    analytics
+        .enqueue(Message::Batch(Batch {
+            batch: vec![
+                BatchMessage::Track(track("user-1")),
+                BatchMessage::Track(Track {
+                    context: Some(json!({ "foo": "event" })),
+                    ..track("user-2")
+                }),
+            ],
+            context: Some(json!({ "foo": "batch", "bar": "batch" })),
+            ..Default::default()
+        }))
+        .unwrap();
+    analytics.shutdown();
+    let requests = data_plane.requests();
+
+    let events = batch_body(&requests[0].body);
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["context"]["foo"], "batch");
+    assert_eq!(events[1]["context"]["foo"], "event");
+    assert_eq!(events[1]["context"]["bar"], "batch");
+}
+
+#[test]
+fn events_keep_their_enqueue_time() {
+    let data_plane = MockDataPlane::start();
+    let analytics = buffered_analytics(
+        data_plane.url(),
+        BufferConfig {
+            max_batch_size: 2,
+            flush_interval: Duration::from_secs(60),
+            ..Default::default()
+        },
+    );
+
+    analytics.enqueue(Message::Track(track("user-1"))).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    analytics.enqueue(Message::Track(track("user-2"))).unwrap();
+    analytics.shutdown();
+    let requests = data_plane.requests();
+
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    let members = body["batch"].as_array().unwrap();
+    let first = members[0]["originalTimestamp"].as_str().unwrap();
+    let second = members[1]["originalTimestamp"].as_str().unwrap();
+    assert_ne!(first, second);
+    assert_eq!(members[0]["sentAt"], body["sentAt"]);
+    assert_eq!(members[1]["sentAt"], body["sentAt"]);
+}
@@ -0,0 +1,158 @@
+use reqwest::StatusCode;
+use rudderanalytics::buffered::BufferConfig;
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::message::{Message, Track};
+use rudderanalytics::redaction::{FieldRule, RedactionAction, RedactionConfig};
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::test_util::MockDataPlane;
+use rudderanalytics::transport::{TransportRequest, TransportResponse};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+fn track_message() -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: "Builder Event".to_string(),
+        context: Some(json!({ "app": { "version": "2.0" } })),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn sends_configured_headers_and_default_context() {
+    let data_plane = MockDataPlane::start();
+    let analytics =
+        RudderAnalytics::builder("write-key".to_string(), format!("{}/", data_plane.url()))
+            .timeout(Duration::from_secs(5))
+            .user_agent("builder-test/1.0")
+            .header("X-Deployment", "canary")
+            .retry_config(RetryConfig::disabled())
+            .default_context(json!({
+                "app": { "name": "builder-test", "version": "1.0" },
+                "region": "eu",
+            }))
+            .build()
+            .unwrap();
+
+    let result = analytics.send(&track_message());
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    let request = &requests[0];
+    assert_eq!(request.path, "/v1/track");
+    assert_eq!(request.header("user-agent"), Some("builder-test/1.0"));
+    assert_eq!(request.header("x-deployment"), Some("canary"));
+
+    let body: Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["context"]["region"], "eu");
+    assert_eq!(body["context"]["app"]["name"], "builder-test");
+    assert_eq!(body["context"]["app"]["version"], "2.0");
+    assert_eq!(body["context"]["library"]["name"], "RudderStack Rust SDK");
+}
+
+#[test]
+fn rejects_invalid_data_plane_url_at_build_time() {
+    for url in ["localhost:8080", "ftp://example.com", "not a url"] {
+        let result = RudderAnalytics::builder("write-key".to_string(), url.to_string()).build();
+        assert!(
+            matches!(result, Err(AnalyticsError::InvalidConfig(_))),
+            "expected {:?} to be rejected",
+            url
+        );
+    }
+}
+
+#[test]
+fn rejects_invalid_headers_and_default_context() {
+    let result = RudderAnalytics::builder("write-key".to_string(), "https://example.com".into())
+        .header("bad header", "value")
+        .build();
+    assert!(matches!(result, Err(AnalyticsError::InvalidConfig(_))));
+
+    let result = RudderAnalytics::builder("write-key".to_string(), "https://example.com".into())
+        .default_context(json!({ "library": { "name": "other" } }))
+        .build();
+    assert!(matches!(result, Err(AnalyticsError::InvalidConfig(_))));
+}
+
+#[test]
+fn rejects_client_settings_with_prebuilt_client() {
+    let result = RudderAnalytics::builder("write-key".to_string(), "https://example.com".into())
+        .http_client(reqwest::blocking::Client::new())
+        .timeout(Duration::from_secs(1))
+        .build();
+
+    assert!(matches!(result, Err(AnalyticsError::InvalidConfig(_))));
+}
+
+#[test]
+fn sends_through_custom_transport() {
+    let analytics = RudderAnalytics::builder("write-key".to_string(), "https://example.com".into())
+        .retry_config(RetryConfig::disabled())
+        .transport(|request: &TransportRequest| {
+            assert_eq!(request.url, "https://example.com/v1/track");
+            Ok(TransportResponse::new(StatusCode::OK))
+        })
+        .build()
+        .unwrap();
+
+    assert!(analytics.send(&track_message()).is_ok());
+    assert_eq!(analytics.stats().attempts, 1);
+
+    let result = RudderAnalytics::builder("write-key".to_string(), "https://example.com".into())
+        .timeout(Duration::from_secs(1))
+        .transport(|_: &TransportRequest| Ok(TransportResponse::new(StatusCode::OK)))
+        .build();
+    assert!(matches!(result, Err(AnalyticsError::InvalidConfig(_))));
+}
+
+#[test]
+fn builds_buffered_client() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::builder("write-key".to_string(), data_plane.url())
+        .buffer_config(BufferConfig {
+            max_batch_size: 2,
+            flush_interval: Duration::from_secs(60),
+            ..Default::default()
+        })
+        .build_buffered()
+        .unwrap();
+
+    analytics.enqueue(track_message()).unwrap();
+    analytics.enqueue(track_message()).unwrap();
+    analytics.shutdown();
+    let requests = data_plane.requests();
+
+    assert_eq!(requests[0].path, "/v1/batch");
+}
+
+#[test]
+fn redacts_pii_before_sending() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::builder("write-key".to_string(), data_plane.url())
+        .retry_config(RetryConfig::disabled())
+        .redaction(RedactionConfig {
+            rules: vec![FieldRule::new("properties.card", RedactionAction::Drop)],
+            ..RedactionConfig::hash_pii("salt")
+        })
+        .build()
+        .unwrap();
+
+    let result = analytics.send(&Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: "Checkout".to_string(),
+        properties: Some(json!({ "email": "jane@example.com", "card": "4111" })),
+        context: Some(json!({ "ip": "10.0.0.1" })),
+        ..Default::default()
+    }));
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    let body = String::from_utf8_lossy(&requests[0].body);
+    assert!(!body.contains("jane@example.com"));
+    assert!(!body.contains("10.0.0.1"));
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["properties"]["email"].as_str().unwrap().len(), 64);
+    assert!(body["properties"].get("card").is_none());
+}
@@ -0,0 +1,112 @@
+use rudderanalytics::buffered::BufferConfig;
+use rudderanalytics::capture::{FileSink, MemorySink};
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::message::{Message, Track};
+use rudderanalytics::ruddermessage::Ruddermessage;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+// nothing listens here, so any attempt to deliver would fail
+const OFFLINE_URL: &str = "http://127.0.0.1:1";
+
+fn track_message(event: &str) -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: event.to_string(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn captures_final_payloads_without_sending() {
+    let sink = MemorySink::new();
+    let analytics = RudderAnalytics::builder("write-key".to_string(), OFFLINE_URL.to_string())
+        .default_context(json!({ "app": { "name": "capture-test" } }))
+        .capture(sink.clone())
+        .build()
+        .unwrap();
+
+    analytics.send(&track_message("First")).unwrap();
+    analytics.send(&track_message("Second")).unwrap();
+
+    let captured = sink.drain();
+    assert_eq!(captured.len(), 2);
+    assert_eq!(captured[0].path, "/v1/track");
+    match &captured[1].payload {
+        Ruddermessage::Track(track) => {
+            assert_eq!(track.event, "Second");
+            assert!(!track.message_id.is_empty());
+            let context = track.context.as_ref().unwrap();
+            assert_eq!(context["app"]["name"], "capture-test");
+            assert_eq!(context["library"]["name"], "RudderStack Rust SDK");
+        }
+        other => panic!("expected a track payload, got {:?}", other),
+    }
+    assert!(sink.is_empty());
+
+    let stats = analytics.stats();
+    assert_eq!(stats.attempts, 0);
+    assert_eq!(stats.events_delivered, 0);
+    assert_eq!(stats.events_captured, 2);
+}
+
+#[test]
+fn invalid_messages_are_not_captured() {
+    let sink = MemorySink::new();
+    let analytics = RudderAnalytics::load("write-key".to_string(), OFFLINE_URL.to_string())
+        .with_capture(sink.clone());
+
+    let result = analytics.send(&Message::Track(Track {
+        event: "No User".to_string(),
+        ..Default::default()
+    }));
+
+    assert!(matches!(result, Err(AnalyticsError::Validation { .. })));
+    assert!(sink.is_empty());
+}
+
+#[test]
+fn file_sink_appends_json_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("captured.ndjson");
+    let analytics = RudderAnalytics::load("write-key".to_string(), OFFLINE_URL.to_string())
+        .with_capture(FileSink::new(&path).unwrap());
+
+    analytics.send(&track_message("First")).unwrap();
+    analytics.send(&track_message("Second")).unwrap();
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<Value> = contents
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["path"], "/v1/track");
+    assert_eq!(lines[1]["payload"]["event"], "Second");
+}
+
+#[test]
+fn buffered_client_captures_batches() {
+    let sink = MemorySink::new();
+    let analytics = RudderAnalytics::builder("write-key".to_string(), OFFLINE_URL.to_string())
+        .capture(sink.clone())
+        .buffer_config(BufferConfig {
+            flush_interval: Duration::from_secs(60),
+            ..Default::default()
+        })
+        .build_buffered()
+        .unwrap();
+
+    analytics.enqueue(track_message("First")).unwrap();
+    analytics.enqueue(track_message("Second")).unwrap();
+    analytics.flush().unwrap();
+
+    let captured = sink.drain();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(captured[0].path, "/v1/batch");
+    match &captured[0].payload {
+        Ruddermessage::Batch(batch) => assert_eq!(batch.batch.len(), 2),
+        other => panic!("expected a batch payload, got {:?}", other),
+    }
+}
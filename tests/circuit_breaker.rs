@@ -0,0 +1,104 @@
+use reqwest::StatusCode;
+use rudderanalytics::capture::MemorySink;
+use rudderanalytics::circuit_breaker::{CircuitBreakerConfig, CircuitState};
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::message::{Message, Track};
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::transport::{TransportError, TransportRequest, TransportResponse};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn track_message(event: &str) -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: event.to_string(),
+        ..Default::default()
+    })
+}
+
+// A client whose transport answers 503 until `healthy` is set, counting the
+// requests it receives.
+fn client(config: CircuitBreakerConfig) -> (RudderAnalytics, Arc<AtomicUsize>, Arc<AtomicBool>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let healthy = Arc::new(AtomicBool::new(false));
+    let counted = Arc::clone(&requests);
+    let status = Arc::clone(&healthy);
+    let retry_config = RetryConfig {
+        max_retries: 5,
+        base_delay: Duration::from_millis(1),
+        max_backoff_delay: Duration::from_millis(1),
+        jitter_ratio: 0.0,
+        ..Default::default()
+    };
+    let analytics = RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        "https://dataplane.example.com".to_string(),
+        retry_config,
+    )
+    .with_transport(
+        move |_: &TransportRequest| -> Result<TransportResponse, TransportError> {
+            counted.fetch_add(1, Ordering::SeqCst);
+            if status.load(Ordering::SeqCst) {
+                Ok(TransportResponse::new(StatusCode::OK))
+            } else {
+                Ok(TransportResponse::new(StatusCode::SERVICE_UNAVAILABLE))
+            }
+        },
+    )
+    .with_circuit_breaker(config);
+    (analytics, requests, healthy)
+}
+
+#[test]
+fn open_circuit_stops_retries_and_fails_fast() {
+    let (analytics, requests, _) = client(CircuitBreakerConfig::new(3, Duration::from_secs(60)));
+    assert_eq!(analytics.circuit_state(), Some(CircuitState::Closed));
+
+    let result = analytics.send(&track_message("First"));
+
+    assert!(matches!(result, Err(AnalyticsError::CircuitOpen)));
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    assert_eq!(analytics.circuit_state(), Some(CircuitState::Open));
+
+    let result = analytics.send(&track_message("Second"));
+
+    assert!(matches!(result, Err(AnalyticsError::CircuitOpen)));
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    assert_eq!(analytics.stats().events_failed, 2);
+}
+
+#[test]
+fn successful_probe_closes_the_circuit() {
+    let (analytics, requests, healthy) =
+        client(CircuitBreakerConfig::new(2, Duration::from_millis(50)));
+    analytics.send(&track_message("First")).unwrap_err();
+    assert_eq!(analytics.circuit_state(), Some(CircuitState::Open));
+
+    std::thread::sleep(Duration::from_millis(60));
+    assert_eq!(analytics.circuit_state(), Some(CircuitState::HalfOpen));
+    healthy.store(true, Ordering::SeqCst);
+    analytics.send(&track_message("Second")).unwrap();
+
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    assert_eq!(analytics.circuit_state(), Some(CircuitState::Closed));
+}
+
+#[test]
+fn open_circuit_diverts_to_fallback_sink() {
+    let (analytics, _, _) = client(CircuitBreakerConfig::new(1, Duration::from_secs(60)));
+    let fallback = MemorySink::new();
+    let analytics = analytics.with_fallback(fallback.clone());
+
+    analytics.send(&track_message("First")).unwrap();
+    analytics.send(&track_message("Second")).unwrap();
+
+    let diverted = fallback.drain();
+    assert_eq!(diverted.len(), 2);
+    assert_eq!(diverted[1].path, "/v1/track");
+    let stats = analytics.stats();
+    assert_eq!(stats.attempts, 1);
+    assert_eq!(stats.events_diverted, 2);
+    assert_eq!(stats.events_failed, 0);
+}
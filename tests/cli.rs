@@ -0,0 +1,179 @@
+#![cfg(feature = "cli")]
+
+use rudderanalytics::test_util::MockDataPlane;
+use serde_json::Value;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run_cli(server_url: &str, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rudderanalytics"))
+        .env("RUDDER_WRITE_KEY", "write-key")
+        .env("RUDDER_DATA_PLANE_URL", server_url)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn batch_sends_events_from_stdin() {
+    let data_plane = MockDataPlane::start();
+    let input = concat!(
+        r#"{"type":"track","userId":"u1","event":"Signup"}"#,
+        "\n\n",
+        r#"{"type":"identify","anonymousId":"a1","traits":{"plan":"pro"}}"#,
+        "\n",
+    );
+
+    let output = run_cli(&data_plane.url(), &["batch"], input);
+    let requests = data_plane.requests();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(requests[0].path, "/v1/batch");
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["batch"][0]["event"], "Signup");
+    assert_eq!(body["batch"][1]["traits"]["plan"], "pro");
+}
+
+#[test]
+fn replay_reports_bad_lines_and_exits_non_zero() {
+    let data_plane = MockDataPlane::start();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("events.ndjson");
+    std::fs::write(
+        &path,
+        concat!(
+            r#"{"type":"track","userId":"u1","event":"One"}"#,
+            "\n",
+            "not json\n",
+            r#"{"type":"track","userId":"u2","event":"Two"}"#,
+            "\n",
+            r#"{"type":"track","event":"No User"}"#,
+            "\n",
+            r#"{"type":"track","userId":"u3","event":"Three"}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    let output = run_cli(
+        &data_plane.url(),
+        &["replay", path.to_str().unwrap(), "--batch-size", "2"],
+        "",
+    );
+    let requests = data_plane.requests();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(requests.len(), 2);
+    assert!(stderr.contains("line 2:"), "{}", stderr);
+    assert!(stderr.contains("line 4:"), "{}", stderr);
+    assert!(
+        stderr.contains("sent 3 events in 2 batches, 2 failed"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn dry_run_prints_payload_built_from_flags() {
+    let output = run_cli(
+        "http://127.0.0.1:1",
+        &[
+            "--dry-run",
+            "track",
+            "--user-id",
+            "u1",
+            "--event",
+            "Signup",
+            "--property",
+            "plan=pro",
+            "--property",
+            "seats:=5",
+            "--context",
+            "app.version=1.2",
+        ],
+        "",
+    );
+
+    assert!(output.status.success(), "{:?}", output);
+    let payload: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(payload["type"], "track");
+    assert_eq!(payload["userId"], "u1");
+    assert_eq!(payload["event"], "Signup");
+    assert_eq!(payload["properties"]["plan"], "pro");
+    assert_eq!(payload["properties"]["seats"], 5);
+    assert_eq!(payload["context"]["app"]["version"], "1.2");
+    assert_eq!(
+        payload["context"]["library"]["name"],
+        "RudderStack Rust SDK"
+    );
+}
+
+#[test]
+fn dry_run_after_the_subcommand_needs_no_credentials() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rudderanalytics"))
+        .env_remove("RUDDER_WRITE_KEY")
+        .env_remove("RUDDER_DATA_PLANE_URL")
+        .args(["track", "--dry-run"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(br#"{"userId":"u1","event":"Signup"}"#)
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{:?}", output);
+    let payload: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(payload["type"], "track");
+    assert_eq!(payload["event"], "Signup");
+}
+
+#[test]
+fn sends_event_built_from_flags() {
+    let data_plane = MockDataPlane::start();
+
+    let output = run_cli(
+        &data_plane.url(),
+        &[
+            "group",
+            "--anonymous-id",
+            "a1",
+            "--group-id",
+            "g1",
+            "--trait",
+            "company.name=Acme",
+        ],
+        "",
+    );
+    let requests = data_plane.requests();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(requests[0].path, "/v1/group");
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["groupId"], "g1");
+    assert_eq!(body["traits"]["company"]["name"], "Acme");
+}
+
+#[test]
+fn missing_required_flag_fails() {
+    let output = run_cli("http://127.0.0.1:1", &["track", "--user-id", "u1"], "");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--event is required"));
+}
@@ -0,0 +1,119 @@
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::compression::CompressionConfig;
+use rudderanalytics::message::{Batch, BatchMessage, Message, Track};
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::test_util::{MockDataPlane, MockResponse};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+fn analytics_with_compression(
+    server_url: String,
+    compression: CompressionConfig,
+) -> RudderAnalytics {
+    let mut analytics = RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        server_url,
+        RetryConfig {
+            base_delay: Duration::from_millis(0),
+            jitter_ratio: 0.0,
+            ..Default::default()
+        },
+    );
+    analytics.compression = compression;
+    analytics
+}
+
+fn large_batch() -> Message {
+    Message::Batch(Batch {
+        batch: (0..200)
+            .map(|i| {
+                BatchMessage::Track(Track {
+                    user_id: Some(format!("user-{}", i)),
+                    event: "Compressible Event".to_string(),
+                    properties: Some(json!({ "plan": "pro", "index": i })),
+                    ..Default::default()
+                })
+            })
+            .collect(),
+        ..Default::default()
+    })
+}
+
+fn small_track() -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: "Small Event".to_string(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn gzips_large_bodies_when_enabled() {
+    let data_plane = MockDataPlane::start();
+    let analytics = analytics_with_compression(data_plane.url(), CompressionConfig::gzip());
+
+    let result = analytics.send(&large_batch());
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    let request = &requests[0];
+    assert_eq!(request.header("content-encoding"), Some("gzip"));
+    assert_eq!(request.header("content-type"), Some("application/json"));
+
+    let body: Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["type"], "batch");
+    assert_eq!(body["batch"].as_array().unwrap().len(), 200);
+    let content_length: usize = request.header("content-length").unwrap().parse().unwrap();
+    assert!(content_length < serde_json::to_vec(&body).unwrap().len());
+}
+
+#[test]
+fn sends_small_bodies_uncompressed() {
+    let data_plane = MockDataPlane::start();
+    let analytics = analytics_with_compression(data_plane.url(), CompressionConfig::gzip());
+
+    let result = analytics.send(&small_track());
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    let request = &requests[0];
+    assert_eq!(request.header("content-encoding"), None);
+    let body: Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["event"], "Small Event");
+}
+
+#[test]
+fn compression_is_disabled_by_default() {
+    let data_plane = MockDataPlane::start();
+    let analytics = analytics_with_compression(data_plane.url(), CompressionConfig::default());
+
+    let result = analytics.send(&large_batch());
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    let request = &requests[0];
+    assert_eq!(request.header("content-encoding"), None);
+    let body: Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["batch"].as_array().unwrap().len(), 200);
+}
+
+#[test]
+fn retries_resend_the_compressed_body() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503));
+    let analytics = analytics_with_compression(data_plane.url(), CompressionConfig::gzip());
+
+    let result = analytics.send(&large_batch());
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    assert_eq!(requests.len(), 2);
+    for request in &requests {
+        assert_eq!(request.header("content-encoding"), Some("gzip"));
+    }
+    assert_eq!(requests[0].body, requests[1].body);
+    assert_eq!(
+        requests[0].header("content-length"),
+        requests[1].header("content-length")
+    );
+}
@@ -0,0 +1,164 @@
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::config::{ClientConfig, DATA_PLANE_URL_ENV, WRITE_KEY_ENV};
+use rudderanalytics::errors::Error as AnalyticsError;
+use serde_json::json;
+use std::fs;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Held by the tests that set or read the process environment, so they
+// cannot race with each other.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+#[test]
+fn loads_json_config_file() {
+    let _env = ENV_LOCK.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("rudder.json");
+    fs::write(
+        &path,
+        r#"{
+            "write_key": "file-key",
+            "data_plane_url": "https://dataplane.example.com",
+            "timeout_ms": 5000,
+            "retry": { "max_retries": 7, "base_delay_ms": 250 },
+            "default_context": { "app": { "name": "config-test" } }
+        }"#,
+    )
+    .unwrap();
+
+    let analytics = RudderAnalytics::from_config_file(&path).unwrap();
+
+    assert_eq!(analytics.write_key, "file-key");
+    assert_eq!(analytics.data_plane_url, "https://dataplane.example.com");
+    assert_eq!(analytics.retry_config.max_retries, 7);
+    assert_eq!(
+        analytics.retry_config.base_delay,
+        Duration::from_millis(250)
+    );
+    assert_eq!(analytics.retry_config.jitter_ratio, 0.2);
+    assert_eq!(
+        analytics.default_context,
+        Some(json!({ "app": { "name": "config-test" } }))
+    );
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn loads_toml_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("rudder.toml");
+    fs::write(
+        &path,
+        r#"
+            write_key = "file-key"
+            data_plane_url = "https://dataplane.example.com"
+            connect_timeout_ms = 1000
+
+            [retry]
+            enabled = false
+
+            [default_context.app]
+            name = "config-test"
+        "#,
+    )
+    .unwrap();
+
+    let config = ClientConfig::from_file(&path).unwrap();
+
+    assert_eq!(config.write_key.as_deref(), Some("file-key"));
+    assert_eq!(config.connect_timeout_ms, Some(1000));
+    assert!(!config.retry.to_retry_config().enabled);
+    assert_eq!(
+        config.default_context,
+        Some(json!({ "app": { "name": "config-test" } }))
+    );
+}
+
+#[test]
+fn rejects_unknown_and_malformed_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("rudder.json");
+
+    fs::write(&path, r#"{ "write_kee": "typo" }"#).unwrap();
+    assert!(matches!(
+        ClientConfig::from_file(&path),
+        Err(AnalyticsError::InvalidConfig(_))
+    ));
+
+    fs::write(&path, "not json").unwrap();
+    assert!(matches!(
+        ClientConfig::from_file(&path),
+        Err(AnalyticsError::InvalidConfig(_))
+    ));
+
+    assert!(matches!(
+        ClientConfig::from_file(dir.path().join("missing.json")),
+        Err(AnalyticsError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn later_config_takes_precedence() {
+    let base = ClientConfig {
+        write_key: Some("env-key".to_string()),
+        data_plane_url: Some("https://env.example.com".to_string()),
+        timeout_ms: Some(1000),
+        ..Default::default()
+    };
+    let overrides = ClientConfig {
+        write_key: Some("flag-key".to_string()),
+        ..Default::default()
+    };
+
+    let config = base.merge(overrides);
+
+    assert_eq!(config.write_key.as_deref(), Some("flag-key"));
+    assert_eq!(
+        config.data_plane_url.as_deref(),
+        Some("https://env.example.com")
+    );
+    assert_eq!(config.timeout_ms, Some(1000));
+}
+
+#[test]
+fn reads_write_key_and_url_from_env() {
+    let _env = ENV_LOCK.lock().unwrap();
+    std::env::set_var(WRITE_KEY_ENV, "env-key");
+    std::env::set_var(DATA_PLANE_URL_ENV, "https://env.example.com");
+
+    let analytics = RudderAnalytics::from_env().unwrap();
+    assert_eq!(analytics.write_key, "env-key");
+    assert_eq!(analytics.data_plane_url, "https://env.example.com");
+
+    std::env::remove_var(WRITE_KEY_ENV);
+    let result = RudderAnalytics::from_env();
+    std::env::remove_var(DATA_PLANE_URL_ENV);
+
+    assert!(matches!(result, Err(AnalyticsError::InvalidConfig(_))));
+}
+
+#[test]
+fn environment_overrides_config_file() {
+    let _env = ENV_LOCK.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("rudder.json");
+    fs::write(
+        &path,
+        r#"{
+            "write_key": "file-key",
+            "data_plane_url": "https://file.example.com",
+            "retry": { "max_retries": 7 }
+        }"#,
+    )
+    .unwrap();
+    std::env::set_var(WRITE_KEY_ENV, "env-key");
+
+    let result = RudderAnalytics::from_config_file(&path);
+    std::env::remove_var(WRITE_KEY_ENV);
+
+    let analytics = result.unwrap();
+    assert_eq!(analytics.write_key, "env-key");
+    assert_eq!(analytics.data_plane_url, "https://file.example.com");
+    assert_eq!(analytics.retry_config.max_retries, 7);
+}
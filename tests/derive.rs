@@ -0,0 +1,111 @@
+#![cfg(feature = "derive")]
+
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::event::{IdentifyTraits, TrackEvent};
+use rudderanalytics::message::{Identify, Message, Track};
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::test_util::MockDataPlane;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+#[derive(Serialize, TrackEvent)]
+#[rudder(event = "Order Completed")]
+struct OrderCompleted {
+    order_id: String,
+    revenue: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    coupon: Option<String>,
+}
+
+#[derive(Serialize, TrackEvent)]
+struct AppOpened;
+
+#[derive(Serialize, IdentifyTraits)]
+struct UserTraits {
+    email: String,
+    plan: &'static str,
+}
+
+#[derive(Serialize, TrackEvent)]
+#[serde(transparent)]
+struct NotAnObject {
+    value: u32,
+}
+
+#[test]
+fn track_event_uses_fixed_name_and_properties() {
+    let track = OrderCompleted {
+        order_id: "1001".to_string(),
+        revenue: 42.5,
+        coupon: None,
+    }
+    .to_track()
+    .unwrap();
+
+    assert_eq!(track.event, "Order Completed");
+    assert_eq!(
+        track.properties,
+        Some(json!({ "order_id": "1001", "revenue": 42.5 }))
+    );
+}
+
+#[test]
+fn track_event_name_defaults_to_struct_name() {
+    let track = AppOpened.to_track().unwrap();
+
+    assert_eq!(AppOpened::EVENT_NAME, "AppOpened");
+    assert_eq!(track.event, "AppOpened");
+    assert_eq!(track.properties, None);
+}
+
+#[test]
+fn identify_traits_become_identify_traits() {
+    let identify = UserTraits {
+        email: "user@example.com".to_string(),
+        plan: "pro",
+    }
+    .to_identify()
+    .unwrap();
+
+    assert_eq!(
+        identify,
+        Identify {
+            traits: Some(json!({ "email": "user@example.com", "plan": "pro" })),
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn rejects_events_that_do_not_serialize_to_objects() {
+    let result = NotAnObject { value: 1 }.to_track();
+
+    assert!(matches!(result, Err(AnalyticsError::Validation { .. })));
+}
+
+#[test]
+fn sends_typed_track_event() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        data_plane.url(),
+        RetryConfig::disabled(),
+    );
+    let event = OrderCompleted {
+        order_id: "1002".to_string(),
+        revenue: 10.0,
+        coupon: Some("SPRING".to_string()),
+    };
+
+    let result = analytics.send(&Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        ..event.to_track().unwrap()
+    }));
+
+    assert!(result.is_ok());
+    let body: Value = serde_json::from_slice(&data_plane.requests()[0].body).unwrap();
+    assert_eq!(body["event"], "Order Completed");
+    assert_eq!(body["userId"], "user-1");
+    assert_eq!(body["properties"]["coupon"], "SPRING");
+}
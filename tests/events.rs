@@ -1,5 +1,5 @@
-// use rudderanalytics::batcher::Batcher;
-// use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::batcher::{Batcher, MAX_MESSAGE_SIZE};
+use rudderanalytics::errors::Error as AnalyticsError;
 use rudderanalytics::message::{
     Alias, Batch, BatchMessage, Group, Identify, Message, Page, Screen, Track,
 };
@@ -91,6 +91,17 @@ mod tests {
             r#"{"userId":"foo","previousId":"bar"}"#.to_owned(),
         );
 
+        assert_eq!(
+            serde_json::to_string(&Message::Track(Track {
+                user_id: Some("foo".to_string()),
+                event: "Foo".to_owned(),
+                message_id: Some("bar".to_string()),
+                ..Default::default()
+            }))
+            .unwrap(),
+            r#"{"userId":"foo","event":"Foo","messageId":"bar"}"#.to_owned(),
+        );
+
         assert_eq!(
             serde_json::to_string(&Message::Batch(Batch {
                 batch: vec![
@@ -124,68 +135,96 @@ mod tests {
         );
     }
 
-    // #[test]
-    // fn test_push_and_into() {
-    //     let batch_msg = BatchMessage::Track(Track {
-    //         ..Default::default()
-    //     });
-
-    //     let context = json!({
-    //         "foo": "bar",
-    //     });
-
-    //     let mut batcher = Batcher::new(Some(context.clone()));
-    //     let result = batcher.push(batch_msg.clone());
-    //     assert_eq!(None, result.ok().unwrap());
-
-    //     let batch = batcher.into_message();
-    //     let inner_batch = match batch {
-    //         Message::Batch(b) => b,
-    //         _ => panic!("invalid message type"),
-    //     };
-    //     assert_eq!(context, inner_batch.context.unwrap());
-    //     assert_eq!(1, inner_batch.batch.len());
-
-    //     assert_eq!(inner_batch.batch, vec![batch_msg]);
-    // }
-
-    // #[test]
-    // fn test_bad_message_size() {
-    //     let batch_msg = BatchMessage::Track(Track {
-    //         user_id: Some(String::from_utf8(vec![b'a'; 1024 * 33]).unwrap()),
-    //         ..Default::default()
-    //     });
-
-    //     let mut batcher = Batcher::new(None);
-    //     let result = batcher.push(batch_msg.into());
-
-    //     let err = result.err().unwrap();
-    //     let err: &AnalyticsError = err.as_fail().downcast_ref().unwrap();
-
-    //     match err {
-    //         AnalyticsError::MessageTooLarge(_str) => {}
-    //         AnalyticsError::InvalidRequest(_str) => {}
-    //     }
-    // }
-
-    // #[test]
-    // fn test_max_buffer() {
-    //     let batch_msg = BatchMessage::Track(Track {
-    //         user_id: Some(String::from_utf8(vec![b'a'; 1024 * 30]).unwrap()),
-    //         ..Default::default()
-    //     });
-
-    //     let mut batcher = Batcher::new(None);
-    //     let mut result = Ok(None);
-    //     for _i in 0..20 {
-    //         result = batcher.push(batch_msg.clone().into());
-    //         dbg!(&result);
-    //         if result.is_ok() && result.as_ref().ok().unwrap().is_some() {
-    //             break;
-    //         }
-    //     }
-
-    //     let msg = result.ok().unwrap();
-    //     assert_eq!(batch_msg, msg.unwrap());
-    // }
+    #[test]
+    fn deserialize_without_message_id() {
+        let track: Track = serde_json::from_str(r#"{"userId":"foo","event":"Foo"}"#).unwrap();
+        assert_eq!(track.message_id, None);
+
+        let alias: Alias = serde_json::from_str(r#"{"userId":"foo","previousId":"bar"}"#).unwrap();
+        assert_eq!(alias.message_id, None);
+    }
+
+    #[test]
+    fn test_push_and_into() {
+        let batch_msg = BatchMessage::Track(Track {
+            ..Default::default()
+        });
+
+        let context = json!({
+            "foo": "bar",
+        });
+
+        let mut batcher = Batcher::new(Some(context.clone()));
+        let result = batcher.push(batch_msg.clone());
+        assert_eq!(None, result.ok().unwrap());
+        assert_eq!(1, batcher.len());
+
+        let batch = batcher.into_message();
+        let inner_batch = match batch {
+            Message::Batch(b) => b,
+            _ => panic!("invalid message type"),
+        };
+        assert_eq!(context, inner_batch.context.unwrap());
+        assert_eq!(1, inner_batch.batch.len());
+
+        assert_eq!(inner_batch.batch, vec![batch_msg]);
+    }
+
+    #[test]
+    fn test_bad_message_size() {
+        let batch_msg = BatchMessage::Track(Track {
+            user_id: Some(String::from_utf8(vec![b'a'; 1024 * 33]).unwrap()),
+            ..Default::default()
+        });
+
+        let mut batcher = Batcher::new(None);
+        let result = batcher.push(batch_msg);
+
+        match result {
+            Err(AnalyticsError::MessageTooLarge(_)) => {}
+            other => panic!("expected message too large error, got {:?}", other),
+        }
+        assert!(batcher.is_empty());
+    }
+
+    #[test]
+    fn test_message_size_includes_injected_context() {
+        let batch_msg = BatchMessage::Track(Track {
+            user_id: Some(String::from_utf8(vec![b'a'; MAX_MESSAGE_SIZE - 1024]).unwrap()),
+            ..Default::default()
+        });
+
+        let mut batcher = Batcher::new(None);
+        assert_eq!(None, batcher.push(batch_msg.clone()).unwrap());
+
+        let context = json!({
+            "padding": String::from_utf8(vec![b'b'; 2048]).unwrap(),
+        });
+        let mut batcher = Batcher::new(Some(context));
+        match batcher.push(batch_msg) {
+            Err(AnalyticsError::MessageTooLarge(_)) => {}
+            other => panic!("expected message too large error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_max_buffer() {
+        let batch_msg = BatchMessage::Track(Track {
+            user_id: Some(String::from_utf8(vec![b'a'; 1024 * 30]).unwrap()),
+            ..Default::default()
+        });
+
+        let mut batcher = Batcher::new(None);
+        let mut result = Ok(None);
+        for _i in 0..20 {
+            result = batcher.push(batch_msg.clone());
+            if result.is_ok() && result.as_ref().ok().unwrap().is_some() {
+                break;
+            }
+        }
+
+        let msg = result.ok().unwrap();
+        assert_eq!(batch_msg, msg.unwrap());
+        assert_eq!(16, batcher.len());
+    }
 }
@@ -0,0 +1,211 @@
+use reqwest::StatusCode;
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::failover::FailoverConfig;
+use rudderanalytics::message::{Message, Track};
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::transport::{
+    TransportError, TransportErrorKind, TransportRequest, TransportResponse,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const PRIMARY: &str = "https://eu.dataplane.example.com";
+const SECONDARY: &str = "https://us.dataplane.example.com";
+
+type Statuses = Arc<Mutex<HashMap<&'static str, u16>>>;
+
+fn track_message(event: &str) -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: event.to_string(),
+        ..Default::default()
+    })
+}
+
+// A client failing over from PRIMARY to SECONDARY, whose transport answers
+// each data plane with the status in `statuses` (0 for a connection error)
+// and records the url of every request.
+fn client(cool_down: Duration) -> (RudderAnalytics, Statuses, Arc<Mutex<Vec<String>>>) {
+    client_with_retry_config(
+        cool_down,
+        RetryConfig {
+            max_retries: 2,
+            base_delay: Duration::from_millis(1),
+            jitter_ratio: 0.0,
+            ..Default::default()
+        },
+    )
+}
+
+fn client_with_retry_config(
+    cool_down: Duration,
+    retry_config: RetryConfig,
+) -> (RudderAnalytics, Statuses, Arc<Mutex<Vec<String>>>) {
+    let statuses: Statuses = Arc::new(Mutex::new(HashMap::new()));
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let answers = Arc::clone(&statuses);
+    let recorded = Arc::clone(&requests);
+    let analytics = RudderAnalytics::builder("write-key".to_string(), PRIMARY.to_string())
+        .retry_config(retry_config)
+        .failover(FailoverConfig {
+            cool_down,
+            ..FailoverConfig::ordered(vec![PRIMARY, SECONDARY])
+        })
+        .transport(
+            move |request: &TransportRequest| -> Result<TransportResponse, TransportError> {
+                recorded.lock().unwrap().push(request.url.clone());
+                let host = if request.url.starts_with(PRIMARY) {
+                    PRIMARY
+                } else {
+                    SECONDARY
+                };
+                match answers.lock().unwrap().get(host).copied().unwrap_or(200) {
+                    0 => Err(TransportError::new(
+                        TransportErrorKind::Connect,
+                        "connection refused",
+                    )),
+                    status => Ok(TransportResponse::new(
+                        StatusCode::from_u16(status).unwrap(),
+                    )),
+                }
+            },
+        )
+        .build()
+        .unwrap();
+    (analytics, statuses, requests)
+}
+
+#[test]
+fn fails_over_to_the_next_endpoint_on_server_errors() {
+    let (analytics, statuses, requests) = client(Duration::from_secs(60));
+    statuses.lock().unwrap().insert(PRIMARY, 503);
+
+    analytics.send(&track_message("First")).unwrap();
+    analytics.send(&track_message("Second")).unwrap();
+
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec![
+            format!("{}/v1/track", PRIMARY),
+            format!("{}/v1/track", SECONDARY),
+            format!("{}/v1/track", SECONDARY),
+        ]
+    );
+    let health = analytics.endpoint_health();
+    assert_eq!(health[0].url, PRIMARY);
+    assert!(!health[0].healthy);
+    assert_eq!(health[0].consecutive_failures, 1);
+    assert!(health[1].healthy);
+}
+
+#[test]
+fn returns_to_the_primary_after_cool_down() {
+    let (analytics, statuses, requests) = client(Duration::from_millis(50));
+    statuses.lock().unwrap().insert(PRIMARY, 0);
+    analytics.send(&track_message("First")).unwrap();
+
+    statuses.lock().unwrap().remove(PRIMARY);
+    std::thread::sleep(Duration::from_millis(60));
+    analytics.send(&track_message("Second")).unwrap();
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[2], format!("{}/v1/track", PRIMARY));
+    assert!(analytics.endpoint_health()[0].healthy);
+}
+
+#[test]
+fn rate_limited_requests_stay_on_the_same_endpoint() {
+    let (analytics, statuses, requests) = client(Duration::from_secs(60));
+    statuses.lock().unwrap().insert(PRIMARY, 429);
+
+    let result = analytics.send(&track_message("First"));
+
+    assert!(matches!(
+        result,
+        Err(AnalyticsError::RetriesExhausted {
+            status: Some(429),
+            ..
+        })
+    ));
+    assert!(requests
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|url| url.starts_with(PRIMARY)));
+    assert!(analytics.endpoint_health()[0].healthy);
+}
+
+#[test]
+fn failover_is_not_stopped_by_the_backoff_deadline() {
+    // the backoff alone would cross the deadline, but the failover retry
+    // goes out right away
+    let (analytics, statuses, requests) = client_with_retry_config(
+        Duration::from_secs(60),
+        RetryConfig {
+            max_retries: 2,
+            base_delay: Duration::from_secs(10),
+            jitter_ratio: 0.0,
+            deadline: Some(Duration::from_secs(1)),
+            ..Default::default()
+        },
+    );
+    statuses.lock().unwrap().insert(PRIMARY, 503);
+
+    analytics.send(&track_message("First")).unwrap();
+
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn backs_off_when_every_endpoint_is_down() {
+    let (analytics, statuses, requests) = client_with_retry_config(
+        Duration::from_secs(60),
+        RetryConfig {
+            max_retries: 2,
+            base_delay: Duration::from_millis(100),
+            jitter_ratio: 0.0,
+            ..Default::default()
+        },
+    );
+    statuses.lock().unwrap().insert(PRIMARY, 503);
+    statuses.lock().unwrap().insert(SECONDARY, 503);
+
+    let start = Instant::now();
+    let result = analytics.send(&track_message("First"));
+    let elapsed = start.elapsed();
+
+    // the failover to SECONDARY goes out right away, but the retry back on
+    // PRIMARY waits out the second backoff of 200ms
+    assert!(matches!(
+        result,
+        Err(AnalyticsError::RetriesExhausted {
+            attempts: 3,
+            status: Some(503),
+            ..
+        })
+    ));
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec![
+            format!("{}/v1/track", PRIMARY),
+            format!("{}/v1/track", SECONDARY),
+            format!("{}/v1/track", PRIMARY),
+        ]
+    );
+    assert!(
+        elapsed >= Duration::from_millis(200),
+        "elapsed {:?}",
+        elapsed
+    );
+}
+
+#[test]
+fn invalid_failover_endpoints_are_rejected() {
+    let result = RudderAnalytics::load("write-key".to_string(), PRIMARY.to_string())
+        .with_failover(FailoverConfig::ordered(vec![PRIMARY, "not a url"]));
+
+    assert!(matches!(result, Err(AnalyticsError::InvalidConfig(_))));
+}
@@ -0,0 +1,110 @@
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::message::{Message, Track};
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::ruddermessage::{self, Ruddermessage};
+use rudderanalytics::test_util::MockDataPlane;
+use serde_json::{json, Value};
+
+fn track(event: &str) -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: event.to_string(),
+        ..Default::default()
+    })
+}
+
+fn add_deployment(msg: Ruddermessage) -> Vec<Ruddermessage> {
+    match msg {
+        Ruddermessage::Track(mut a_) => {
+            if let Some(Value::Object(context)) = &mut a_.context {
+                context.insert("deployment".to_string(), json!("canary"));
+            }
+            vec![Ruddermessage::Track(a_)]
+        }
+        other => vec![other],
+    }
+}
+
+fn drop_heartbeats(msg: Ruddermessage) -> Vec<Ruddermessage> {
+    match &msg {
+        Ruddermessage::Track(a_) if a_.event == "Heartbeat" => Vec::new(),
+        _ => vec![msg],
+    }
+}
+
+#[test]
+fn middleware_modifies_outgoing_event() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::builder("write-key".to_string(), data_plane.url())
+        .retry_config(RetryConfig::disabled())
+        .middleware(add_deployment)
+        .build()
+        .unwrap();
+
+    let result = analytics.send(&track("Signed Up"));
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["context"]["deployment"], "canary");
+    assert_eq!(body["context"]["library"]["name"], "RudderStack Rust SDK");
+}
+
+#[test]
+fn middleware_drops_event() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        data_plane.url(),
+        RetryConfig::disabled(),
+    )
+    .with_middleware(drop_heartbeats);
+
+    let result = analytics.send(&track("Heartbeat"));
+
+    assert!(result.is_ok());
+    data_plane.assert_request_count(0);
+}
+
+#[test]
+fn middleware_splits_event_into_separate_requests() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        data_plane.url(),
+        RetryConfig::disabled(),
+    )
+    .with_middleware(|msg: Ruddermessage| match msg {
+        Ruddermessage::Track(a_) => {
+            let identify = Ruddermessage::Identify(ruddermessage::Identify {
+                user_id: a_.user_id.clone(),
+                message_id: format!("{}-identify", a_.message_id),
+                r#type: "identify".to_string(),
+                channel: a_.channel.clone(),
+                ..Default::default()
+            });
+            vec![Ruddermessage::Track(a_), identify]
+        }
+        other => vec![other],
+    })
+    .with_middleware(add_deployment);
+
+    let result = analytics.send(&track("Signed Up"));
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    let paths: Vec<&str> = requests
+        .iter()
+        .map(|request| request.path.as_str())
+        .collect();
+    assert_eq!(paths, vec!["/v1/track", "/v1/identify"]);
+
+    let track: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    let identify: Value = serde_json::from_slice(&requests[1].body).unwrap();
+    assert_eq!(track["context"]["deployment"], "canary");
+    assert_eq!(identify["userId"], "user-1");
+    assert_eq!(
+        identify["messageId"],
+        format!("{}-identify", track["messageId"].as_str().unwrap())
+    );
+}
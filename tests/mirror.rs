@@ -0,0 +1,221 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::header::AUTHORIZATION;
+use reqwest::StatusCode;
+use rudderanalytics::circuit_breaker::CircuitBreakerConfig;
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::message::{Message, Track};
+use rudderanalytics::mirror::Destination;
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::transport::{TransportError, TransportRequest, TransportResponse};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const OLD_URL: &str = "https://old.dataplane.example.com";
+const NEW_URL: &str = "https://new.dataplane.example.com";
+
+type Script = Arc<Mutex<HashMap<&'static str, VecDeque<u16>>>>;
+// (url, write key) of each request
+type Requests = Arc<Mutex<Vec<(String, String)>>>;
+
+fn track_message(event: &str) -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: event.to_string(),
+        ..Default::default()
+    })
+}
+
+// A client sending to OLD_URL whose transport answers each data plane with
+// its scripted statuses, then 200, and records the url and write key of
+// every request.
+fn client(destination: Destination) -> (RudderAnalytics, Script, Requests) {
+    let script: Script = Arc::new(Mutex::new(HashMap::new()));
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let answers = Arc::clone(&script);
+    let recorded = Arc::clone(&requests);
+    let retry_config = RetryConfig {
+        max_retries: 1,
+        base_delay: Duration::from_millis(1),
+        jitter_ratio: 0.0,
+        ..Default::default()
+    };
+    let analytics = RudderAnalytics::builder("old-key".to_string(), OLD_URL.to_string())
+        .retry_config(retry_config)
+        .mirror(destination)
+        .transport(
+            move |request: &TransportRequest| -> Result<TransportResponse, TransportError> {
+                let credentials = request.headers[AUTHORIZATION].to_str().unwrap();
+                let decoded = STANDARD
+                    .decode(credentials.trim_start_matches("Basic "))
+                    .unwrap();
+                let write_key = String::from_utf8(decoded).unwrap();
+                recorded.lock().unwrap().push((
+                    request.url.clone(),
+                    write_key.trim_end_matches(':').to_string(),
+                ));
+
+                let host = if request.url.starts_with(OLD_URL) {
+                    OLD_URL
+                } else {
+                    NEW_URL
+                };
+                let status = answers
+                    .lock()
+                    .unwrap()
+                    .get_mut(host)
+                    .and_then(VecDeque::pop_front)
+                    .unwrap_or(200);
+                Ok(TransportResponse::new(
+                    StatusCode::from_u16(status).unwrap(),
+                ))
+            },
+        )
+        .build()
+        .unwrap();
+    (analytics, script, requests)
+}
+
+#[test]
+fn mirrors_every_payload_with_its_own_write_key() {
+    let (analytics, _, requests) = client(Destination::mirror("new", "new-key", NEW_URL));
+
+    analytics.send(&track_message("Signup")).unwrap();
+
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec![
+            (format!("{}/v1/track", OLD_URL), "old-key".to_string()),
+            (format!("{}/v1/track", NEW_URL), "new-key".to_string()),
+        ]
+    );
+    assert_eq!(analytics.stats().attempts, 1);
+    let mirror_stats = analytics.mirror_stats();
+    assert_eq!(mirror_stats[0].name, "new");
+    assert_eq!(mirror_stats[0].events_delivered, 1);
+}
+
+#[test]
+fn mirrors_are_not_retried_by_default() {
+    let (analytics, script, requests) = client(Destination::mirror("new", "new-key", NEW_URL));
+    script.lock().unwrap().insert(NEW_URL, vec![503].into());
+
+    analytics.send(&track_message("Signup")).unwrap();
+
+    assert_eq!(requests.lock().unwrap().len(), 2);
+    assert_eq!(analytics.mirror_stats()[0].events_failed, 1);
+}
+
+#[test]
+fn mirror_failures_do_not_fail_send() {
+    let (analytics, script, requests) = client(Destination {
+        retry_config: RetryConfig {
+            max_retries: 1,
+            base_delay: Duration::from_millis(1),
+            jitter_ratio: 0.0,
+            ..Default::default()
+        },
+        ..Destination::mirror("new", "new-key", NEW_URL)
+    });
+    script
+        .lock()
+        .unwrap()
+        .insert(NEW_URL, vec![503, 503].into());
+
+    analytics.send(&track_message("First")).unwrap();
+    analytics.send(&track_message("Second")).unwrap();
+
+    // two attempts at the mirror for the first event, one for the second
+    assert_eq!(requests.lock().unwrap().len(), 5);
+    let mirror_stats = &analytics.mirror_stats()[0];
+    assert_eq!(mirror_stats.events_failed, 1);
+    assert_eq!(mirror_stats.events_delivered, 1);
+    assert!(mirror_stats.last_error.is_some());
+    assert_eq!(analytics.stats().events_delivered, 2);
+}
+
+#[test]
+fn primary_failures_do_not_stop_the_mirror() {
+    let (analytics, script, _) = client(Destination::mirror("new", "new-key", NEW_URL));
+    script.lock().unwrap().insert(OLD_URL, vec![400].into());
+
+    let result = analytics.send(&track_message("Signup"));
+
+    assert!(matches!(
+        result,
+        Err(AnalyticsError::Rejected { status: 400, .. })
+    ));
+    assert_eq!(analytics.mirror_stats()[0].events_delivered, 1);
+}
+
+#[test]
+fn shadow_destinations_receive_a_sample() {
+    let (analytics, _, requests) = client(Destination::shadow("new", "new-key", NEW_URL, 0.0));
+
+    analytics.send(&track_message("First")).unwrap();
+    analytics.send(&track_message("Second")).unwrap();
+
+    assert!(requests
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|(url, _)| url.starts_with(OLD_URL)));
+    assert_eq!(analytics.mirror_stats()[0].events_skipped, 2);
+}
+
+#[test]
+fn open_mirror_circuit_fails_fast() {
+    let (analytics, script, requests) = client(Destination {
+        circuit_breaker: CircuitBreakerConfig::new(1, Duration::from_secs(60)),
+        ..Destination::mirror("new", "new-key", NEW_URL)
+    });
+    script.lock().unwrap().insert(NEW_URL, vec![503].into());
+
+    analytics.send(&track_message("First")).unwrap();
+    analytics.send(&track_message("Second")).unwrap();
+
+    // the mirror's circuit opened on its first failure, so it was neither
+    // retried nor sent the second event
+    assert_eq!(requests.lock().unwrap().len(), 3);
+    let mirror_stats = &analytics.mirror_stats()[0];
+    assert_eq!(mirror_stats.events_failed, 2);
+    assert_eq!(
+        mirror_stats.last_error.as_deref(),
+        Some("circuit breaker is open")
+    );
+}
+
+#[test]
+fn mirrors_are_sent_while_the_primary_circuit_is_open() {
+    let (analytics, script, requests) = client(Destination::mirror("new", "new-key", NEW_URL));
+    let analytics =
+        analytics.with_circuit_breaker(CircuitBreakerConfig::new(1, Duration::from_secs(60)));
+    script.lock().unwrap().insert(OLD_URL, vec![503].into());
+
+    let first = analytics.send(&track_message("First"));
+    let second = analytics.send(&track_message("Second"));
+
+    assert!(matches!(first, Err(AnalyticsError::CircuitOpen)));
+    assert!(matches!(second, Err(AnalyticsError::CircuitOpen)));
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec![
+            (format!("{}/v1/track", OLD_URL), "old-key".to_string()),
+            (format!("{}/v1/track", NEW_URL), "new-key".to_string()),
+            (format!("{}/v1/track", NEW_URL), "new-key".to_string()),
+        ]
+    );
+    let mirror_stats = &analytics.mirror_stats()[0];
+    assert_eq!(mirror_stats.events_delivered, 2);
+    assert_eq!(mirror_stats.events_skipped, 0);
+}
+
+#[test]
+fn invalid_destinations_are_rejected() {
+    let result = RudderAnalytics::load("old-key".to_string(), OLD_URL.to_string())
+        .with_mirror(Destination::shadow("new", "new-key", NEW_URL, 2.0));
+
+    assert!(matches!(result, Err(AnalyticsError::InvalidConfig(_))));
+}
@@ -3,198 +3,9 @@ use rudderanalytics::client::RudderAnalytics;
 use rudderanalytics::errors::Error as AnalyticsError;
 use rudderanalytics::message::{Message, Track};
 use rudderanalytics::retry::RetryConfig;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
-use std::thread::{self, JoinHandle};
+use rudderanalytics::test_util::{MockDataPlane, MockResponse};
 use std::time::{Duration, Instant, SystemTime};
 
-struct TestResponse {
-    status: u16,
-    reason: &'static str,
-    headers: Vec<(String, String)>,
-}
-
-struct TestServer {
-    url: String,
-    request_count: Arc<AtomicUsize>,
-    requests: Arc<Mutex<Vec<TestRequest>>>,
-    handle: JoinHandle<()>,
-}
-
-#[derive(Debug, Clone)]
-struct TestRequest {
-    method: String,
-    path: String,
-    headers: Vec<(String, String)>,
-    body: String,
-}
-
-struct TestServerObservation {
-    request_count: usize,
-    requests: Vec<TestRequest>,
-}
-
-impl TestServer {
-    fn wait(self) -> usize {
-        self.wait_with_requests().request_count
-    }
-
-    fn wait_with_requests(self) -> TestServerObservation {
-        let _ = self.handle.join();
-        let requests = self.requests.lock().unwrap().clone();
-        TestServerObservation {
-            request_count: self.request_count.load(Ordering::SeqCst),
-            requests,
-        }
-    }
-}
-
-fn start_server(responses: Vec<TestResponse>) -> TestServer {
-    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-    listener.set_nonblocking(true).unwrap();
-    let url = format!("http://{}", listener.local_addr().unwrap());
-    let request_count = Arc::new(AtomicUsize::new(0));
-    let requests = Arc::new(Mutex::new(Vec::new()));
-    let thread_request_count = Arc::clone(&request_count);
-    let thread_requests = Arc::clone(&requests);
-
-    let handle = thread::spawn(move || {
-        for response in responses {
-            let deadline = Instant::now() + Duration::from_secs(2);
-            let (mut stream, _) = loop {
-                match listener.accept() {
-                    Ok(conn) => break conn,
-                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
-                        if Instant::now() >= deadline {
-                            return;
-                        }
-                        thread::sleep(Duration::from_millis(10));
-                    }
-                    Err(_) => return,
-                }
-            };
-
-            thread_request_count.fetch_add(1, Ordering::SeqCst);
-            let _ = stream.set_read_timeout(Some(Duration::from_millis(200)));
-            if let Some(request) = read_http_request(&mut stream) {
-                thread_requests.lock().unwrap().push(request);
-            }
-
-            let mut raw_response = format!(
-                "HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n",
-                response.status, response.reason
-            );
-            for (name, value) in response.headers {
-                raw_response.push_str(&format!("{}: {}\r\n", name, value));
-            }
-            raw_response.push_str("\r\n");
-
-            let _ = stream.write_all(raw_response.as_bytes());
-            let _ = stream.flush();
-        }
-    });
-
-    TestServer {
-        url,
-        request_count,
-        requests,
-        handle,
-    }
-}
-
-fn read_http_request(stream: &mut TcpStream) -> Option<TestRequest> {
-    let mut buffer = Vec::new();
-    let mut temp = [0; 1024];
-    let mut expected_len = None;
-
-    loop {
-        match stream.read(&mut temp) {
-            Ok(0) => break,
-            Ok(n) => {
-                buffer.extend_from_slice(&temp[..n]);
-                if expected_len.is_none() {
-                    if let Some(header_end) = find_header_end(&buffer) {
-                        let headers = String::from_utf8_lossy(&buffer[..header_end]);
-                        let content_length = parse_content_length(&headers).unwrap_or(0);
-                        expected_len = Some(header_end + 4 + content_length);
-                    }
-                }
-                if expected_len
-                    .map(|expected_len| buffer.len() >= expected_len)
-                    .unwrap_or(false)
-                {
-                    break;
-                }
-            }
-            Err(_) => break,
-        }
-    }
-
-    parse_http_request(&buffer)
-}
-
-fn find_header_end(buffer: &[u8]) -> Option<usize> {
-    buffer.windows(4).position(|window| window == b"\r\n\r\n")
-}
-
-fn parse_content_length(headers: &str) -> Option<usize> {
-    headers.lines().find_map(|line| {
-        let (name, value) = line.split_once(':')?;
-        if name.eq_ignore_ascii_case("content-length") {
-            value.trim().parse().ok()
-        } else {
-            None
-        }
-    })
-}
-
-fn parse_http_request(buffer: &[u8]) -> Option<TestRequest> {
-    let header_end = find_header_end(buffer)?;
-    let header_text = String::from_utf8_lossy(&buffer[..header_end]);
-    let mut lines = header_text.lines();
-    let request_line = lines.next()?;
-    let mut request_parts = request_line.split_whitespace();
-    let method = request_parts.next()?.to_string();
-    let path = request_parts.next()?.to_string();
-    let headers = lines
-        .filter_map(|line| {
-            let (name, value) = line.split_once(':')?;
-            Some((name.trim().to_ascii_lowercase(), value.trim().to_string()))
-        })
-        .collect();
-    let body = String::from_utf8_lossy(&buffer[header_end + 4..]).to_string();
-
-    Some(TestRequest {
-        method,
-        path,
-        headers,
-        body,
-    })
-}
-
-fn response(status: u16, reason: &'static str) -> TestResponse {
-    TestResponse {
-        status,
-        reason,
-        headers: Vec::new(),
-    }
-}
-
-fn response_with_header(
-    status: u16,
-    reason: &'static str,
-    name: impl Into<String>,
-    value: impl Into<String>,
-) -> TestResponse {
-    TestResponse {
-        status,
-        reason,
-        headers: vec![(name.into(), value.into())],
-    }
-}
-
 fn retry_config(max_retries: u32) -> RetryConfig {
     RetryConfig {
         max_retries,
@@ -229,15 +40,6 @@ fn track_message_with_event(event: &str) -> Message {
     })
 }
 
-impl TestRequest {
-    fn header(&self, name: &str) -> Option<&str> {
-        self.headers
-            .iter()
-            .find(|(header_name, _)| header_name == &name.to_ascii_lowercase())
-            .map(|(_, value)| value.as_str())
-    }
-}
-
 fn expected_authorization_header() -> String {
     let encoded_write_key = ["d3JpdGU", "ta2V5", "Og=="].concat();
     format!("{} {}", "Basic", encoded_write_key)
@@ -245,14 +47,12 @@ fn expected_authorization_header() -> String {
 
 #[test]
 fn retries_429_until_success() {
-    let server = start_server(vec![
-        response(429, "Too Many Requests"),
-        response(200, "OK"),
-    ]);
-    let analytics = analytics_with_retries(server.url.clone(), 3);
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(429));
+    let analytics = analytics_with_retries(data_plane.url(), 3);
 
     let result = analytics.send(&track_message());
-    let request_count = server.wait();
+    let request_count = data_plane.request_count();
 
     assert!(result.is_ok());
     assert_eq!(request_count, 2);
@@ -260,20 +60,17 @@ fn retries_429_until_success() {
 
 #[test]
 fn retries_against_mock_rudderstack_http_api() {
-    let server = start_server(vec![
-        response(429, "Too Many Requests"),
-        response(200, "OK"),
-    ]);
-    let analytics = analytics_with_retries(server.url.clone(), 3);
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(429));
+    let analytics = analytics_with_retries(data_plane.url(), 3);
 
     let result = analytics.send(&track_message_with_event("Retry Contract Event"));
-    let observation = server.wait_with_requests();
+    let requests = data_plane.requests();
 
     assert!(result.is_ok());
-    assert_eq!(observation.request_count, 2);
-    assert_eq!(observation.requests.len(), 2);
+    assert_eq!(requests.len(), 2);
 
-    for request in &observation.requests {
+    for request in &requests {
         assert_eq!(request.method, "POST");
         assert_eq!(request.path, "/v1/track");
         let expected_authorization_header = expected_authorization_header();
@@ -290,7 +87,7 @@ fn retries_against_mock_rudderstack_http_api() {
             request.header("content-type")
         );
 
-        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
         assert_eq!(body["type"], "track");
         assert_eq!(body["channel"], "server");
         assert_eq!(body["userId"], "user-1");
@@ -298,18 +95,19 @@ fn retries_against_mock_rudderstack_http_api() {
     }
 
     assert_eq!(
-        observation.requests[0].body, observation.requests[1].body,
+        requests[0].body, requests[1].body,
         "retry should resend the same event payload"
     );
 }
 
 #[test]
 fn disabled_retry_config_does_not_retry_429() {
-    let server = start_server(vec![response(429, "Too Many Requests")]);
-    let analytics = analytics_with_retry_config(server.url.clone(), RetryConfig::disabled());
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(429));
+    let analytics = analytics_with_retry_config(data_plane.url(), RetryConfig::disabled());
 
     let result = analytics.send(&track_message());
-    let request_count = server.wait();
+    let request_count = data_plane.request_count();
 
     assert!(result.is_err());
     assert_eq!(request_count, 1);
@@ -317,11 +115,12 @@ fn disabled_retry_config_does_not_retry_429() {
 
 #[test]
 fn does_not_retry_non_retryable_400() {
-    let server = start_server(vec![response(400, "Bad Request")]);
-    let analytics = analytics_with_retries(server.url.clone(), 3);
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(400));
+    let analytics = analytics_with_retries(data_plane.url(), 3);
 
     let result = analytics.send(&track_message());
-    let request_count = server.wait();
+    let request_count = data_plane.request_count();
 
     assert!(result.is_err());
     assert_eq!(request_count, 1);
@@ -330,11 +129,12 @@ fn does_not_retry_non_retryable_400() {
 #[test]
 fn does_not_retry_terminal_4xx_statuses() {
     for status in [401, 403, 404, 413, 422] {
-        let server = start_server(vec![response(status, "Terminal Client Error")]);
-        let analytics = analytics_with_retries(server.url.clone(), 3);
+        let data_plane = MockDataPlane::start();
+        data_plane.enqueue(MockResponse::status(status));
+        let analytics = analytics_with_retries(data_plane.url(), 3);
 
         let result = analytics.send(&track_message());
-        let request_count = server.wait();
+        let request_count = data_plane.request_count();
 
         assert!(result.is_err(), "expected status {} to fail", status);
         assert_eq!(request_count, 1, "expected status {} not to retry", status);
@@ -343,32 +143,66 @@ fn does_not_retry_terminal_4xx_statuses() {
 
 #[test]
 fn returns_error_after_retry_budget_is_exhausted() {
-    let server = start_server(vec![
-        response(429, "Too Many Requests"),
-        response(429, "Too Many Requests"),
-    ]);
-    let analytics = analytics_with_retries(server.url.clone(), 1);
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(429));
+    data_plane.enqueue(MockResponse::status(429));
+    let analytics = analytics_with_retries(data_plane.url(), 1);
 
     let result = analytics.send(&track_message());
-    let request_count = server.wait();
+    let request_count = data_plane.request_count();
 
     match result {
-        Err(AnalyticsError::InvalidRequest(message)) => {
-            assert!(message.contains("retries exhausted"));
+        Err(AnalyticsError::RetriesExhausted {
+            attempts, status, ..
+        }) => {
+            assert_eq!(attempts, 2);
+            assert_eq!(status, Some(429));
         }
         other => panic!("expected retries exhausted error, got {:?}", other),
     }
     assert_eq!(request_count, 2);
 }
 
+#[test]
+fn rejected_request_exposes_status_and_response_body() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(400).body(r#"{"error":"invalid write key"}"#));
+    let analytics = analytics_with_retries(data_plane.url(), 3);
+
+    let err = analytics.send(&track_message()).unwrap_err();
+
+    assert!(matches!(err, AnalyticsError::Rejected { .. }), "{:?}", err);
+    assert_eq!(err.status(), Some(400));
+    assert_eq!(err.attempts(), Some(1));
+    assert_eq!(
+        err.response_body(),
+        Some(r#"{"error":"invalid write key"}"#)
+    );
+}
+
+#[test]
+fn retries_exhausted_keeps_last_response_body() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503));
+    data_plane.enqueue(MockResponse::status(503).body("overloaded"));
+    let analytics = analytics_with_retries(data_plane.url(), 1);
+
+    let err = analytics.send(&track_message()).unwrap_err();
+
+    assert_eq!(err.status(), Some(503));
+    assert_eq!(err.attempts(), Some(2));
+    assert_eq!(err.response_body(), Some("overloaded"));
+}
+
 #[test]
 fn retries_common_5xx_until_success() {
     for status in [500, 502, 503, 504] {
-        let server = start_server(vec![response(status, "Server Error"), response(200, "OK")]);
-        let analytics = analytics_with_retries(server.url.clone(), 3);
+        let data_plane = MockDataPlane::start();
+        data_plane.enqueue(MockResponse::status(status));
+        let analytics = analytics_with_retries(data_plane.url(), 3);
 
         let result = analytics.send(&track_message());
-        let request_count = server.wait();
+        let request_count = data_plane.request_count();
 
         assert!(result.is_ok(), "expected status {} to retry", status);
         assert_eq!(request_count, 2, "expected status {} to retry once", status);
@@ -377,14 +211,12 @@ fn retries_common_5xx_until_success() {
 
 #[test]
 fn retries_503_without_retry_after_using_normal_backoff() {
-    let server = start_server(vec![
-        response(503, "Service Unavailable"),
-        response(200, "OK"),
-    ]);
-    let analytics = analytics_with_retries(server.url.clone(), 3);
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503));
+    let analytics = analytics_with_retries(data_plane.url(), 3);
 
     let result = analytics.send(&track_message());
-    let request_count = server.wait();
+    let request_count = data_plane.request_count();
 
     assert!(result.is_ok());
     assert_eq!(request_count, 2);
@@ -392,16 +224,14 @@ fn retries_503_without_retry_after_using_normal_backoff() {
 
 #[test]
 fn honors_retry_after_delay_seconds() {
-    let server = start_server(vec![
-        response_with_header(429, "Too Many Requests", "Retry-After", "1"),
-        response(200, "OK"),
-    ]);
-    let analytics = analytics_with_retries(server.url.clone(), 3);
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(429).retry_after("1"));
+    let analytics = analytics_with_retries(data_plane.url(), 3);
 
     let start = Instant::now();
     let result = analytics.send(&track_message());
     let elapsed = start.elapsed();
-    let request_count = server.wait();
+    let request_count = data_plane.request_count();
 
     assert!(result.is_ok());
     assert_eq!(request_count, 2);
@@ -414,22 +244,17 @@ fn honors_retry_after_delay_seconds() {
 
 #[test]
 fn honors_retry_after_http_date() {
-    let retry_at = SystemTime::now() + Duration::from_secs(2);
-    let server = start_server(vec![
-        response_with_header(
-            429,
-            "Too Many Requests",
-            "Retry-After",
-            fmt_http_date(retry_at),
-        ),
-        response(200, "OK"),
-    ]);
-    let analytics = analytics_with_retries(server.url.clone(), 3);
+    // HTTP dates drop the fraction of a second, so leave room for that and
+    // for a slow start of the request
+    let retry_at = SystemTime::now() + Duration::from_secs(3);
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(429).retry_after(fmt_http_date(retry_at)));
+    let analytics = analytics_with_retries(data_plane.url(), 3);
 
     let start = Instant::now();
     let result = analytics.send(&track_message());
     let elapsed = start.elapsed();
-    let request_count = server.wait();
+    let request_count = data_plane.request_count();
 
     assert!(result.is_ok());
     assert_eq!(request_count, 2);
@@ -442,16 +267,14 @@ fn honors_retry_after_http_date() {
 
 #[test]
 fn honors_retry_after_on_503() {
-    let server = start_server(vec![
-        response_with_header(503, "Service Unavailable", "Retry-After", "1"),
-        response(200, "OK"),
-    ]);
-    let analytics = analytics_with_retries(server.url.clone(), 3);
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503).retry_after("1"));
+    let analytics = analytics_with_retries(data_plane.url(), 3);
 
     let start = Instant::now();
     let result = analytics.send(&track_message());
     let elapsed = start.elapsed();
-    let request_count = server.wait();
+    let request_count = data_plane.request_count();
 
     assert!(result.is_ok());
     assert_eq!(request_count, 2);
@@ -461,3 +284,130 @@ fn honors_retry_after_on_503() {
         elapsed
     );
 }
+
+#[test]
+fn retries_reuse_generated_message_id() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503));
+    let analytics = analytics_with_retries(data_plane.url(), 3);
+
+    let result = analytics.send(&track_message());
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    let message_ids: Vec<String> = requests
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            body["messageId"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(message_ids.len(), 2);
+    assert!(!message_ids[0].is_empty());
+    assert_eq!(message_ids[0], message_ids[1]);
+}
+
+#[test]
+fn sends_caller_supplied_message_id() {
+    let data_plane = MockDataPlane::start();
+    let analytics = analytics_with_retries(data_plane.url(), 3);
+
+    let result = analytics.send(&Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: "Test Event".to_string(),
+        message_id: Some("message-1".to_string()),
+        ..Default::default()
+    }));
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["messageId"], "message-1");
+}
+
+#[test]
+fn gives_up_when_retry_after_crosses_deadline() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(429).retry_after("3600"));
+    let analytics = analytics_with_retry_config(
+        data_plane.url(),
+        RetryConfig {
+            deadline: Some(Duration::from_secs(5)),
+            ..retry_config(3)
+        },
+    );
+
+    let start = Instant::now();
+    let result = analytics.send(&track_message());
+    let elapsed = start.elapsed();
+    let request_count = data_plane.request_count();
+
+    assert_eq!(request_count, 1);
+    assert!(elapsed < Duration::from_secs(5), "elapsed {:?}", elapsed);
+    match result {
+        Err(err @ AnalyticsError::DeadlineExceeded { .. }) => {
+            assert_eq!(err.status(), Some(429));
+            assert_eq!(err.attempts(), Some(1));
+        }
+        other => panic!("expected the deadline to be exceeded, got {:?}", other),
+    }
+}
+
+#[test]
+fn deadline_stops_backoff_retries() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503));
+    data_plane.enqueue(MockResponse::status(503));
+    let analytics = analytics_with_retry_config(
+        data_plane.url(),
+        RetryConfig {
+            max_retries: 5,
+            base_delay: Duration::from_millis(200),
+            max_backoff_delay: Duration::from_secs(5),
+            jitter_ratio: 0.0,
+            deadline: Some(Duration::from_millis(500)),
+            ..Default::default()
+        },
+    );
+
+    let result = analytics.send(&track_message());
+    let request_count = data_plane.request_count();
+
+    // the second retry would wait 400ms more, ending past the deadline
+    assert_eq!(request_count, 2);
+    assert!(matches!(
+        result,
+        Err(AnalyticsError::DeadlineExceeded {
+            attempts: 2,
+            status: Some(503),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn caps_retry_after_at_max_retry_after() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503).retry_after("3600"));
+    let analytics = analytics_with_retry_config(
+        data_plane.url(),
+        RetryConfig {
+            max_retry_after: Some(Duration::from_millis(100)),
+            ..retry_config(3)
+        },
+    );
+
+    let start = Instant::now();
+    let result = analytics.send(&track_message());
+    let elapsed = start.elapsed();
+    let request_count = data_plane.request_count();
+
+    assert!(result.is_ok());
+    assert_eq!(request_count, 2);
+    assert!(
+        elapsed >= Duration::from_millis(100),
+        "elapsed {:?}",
+        elapsed
+    );
+    assert!(elapsed < Duration::from_secs(5), "elapsed {:?}", elapsed);
+}
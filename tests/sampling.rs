@@ -0,0 +1,149 @@
+use rudderanalytics::buffered::BufferConfig;
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::message::{Batch, BatchMessage, Message, Track};
+use rudderanalytics::rate_limit::RateLimitConfig;
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::ruddermessage::Ruddermessage;
+use rudderanalytics::sampling::{SamplingConfig, SamplingRule};
+use rudderanalytics::test_util::MockDataPlane;
+use serde_json::Value;
+
+fn track(event: &str) -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: event.to_string(),
+        ..Default::default()
+    })
+}
+
+fn track_member(event: &str) -> BatchMessage {
+    BatchMessage::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: event.to_string(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn sampled_events_record_their_rate() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::builder("write-key".to_string(), data_plane.url())
+        .retry_config(RetryConfig::disabled())
+        .sampling(
+            SamplingConfig::default()
+                .event("Heartbeat", SamplingRule::random(0.0))
+                .event("Scroll", SamplingRule::per_user(1.0)),
+        )
+        .build()
+        .unwrap();
+
+    assert!(analytics.send(&track("Heartbeat")).is_ok());
+    assert!(analytics.send(&track("Scroll")).is_ok());
+
+    data_plane.assert_request_count(1);
+    let body: Value = serde_json::from_slice(&data_plane.requests()[0].body).unwrap();
+    assert_eq!(body["event"], "Scroll");
+    assert_eq!(body["context"]["sampleRate"], 1.0);
+}
+
+#[test]
+fn rate_limited_events_are_not_sent() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        data_plane.url(),
+        RetryConfig::disabled(),
+    )
+    .with_rate_limit(RateLimitConfig::new(0.01, 1))
+    .unwrap();
+
+    let first = analytics.send(&track("Clicked"));
+    let second = analytics.send(&track("Clicked"));
+
+    assert!(first.is_ok());
+    assert!(matches!(second, Err(AnalyticsError::RateLimited)));
+    data_plane.assert_request_count(1);
+}
+
+#[test]
+fn sampled_out_events_do_not_use_the_rate_limit() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::builder("write-key".to_string(), data_plane.url())
+        .retry_config(RetryConfig::disabled())
+        .sampling(SamplingConfig::default().event("Heartbeat", SamplingRule::random(0.0)))
+        .rate_limit(RateLimitConfig::new(0.01, 1))
+        .build()
+        .unwrap();
+
+    assert!(analytics.send(&track("Heartbeat")).is_ok());
+    assert!(analytics.send(&track("Heartbeat")).is_ok());
+    assert!(analytics.send(&track("Clicked")).is_ok());
+    data_plane.assert_request_count(1);
+}
+
+#[test]
+fn buffered_client_takes_tokens_after_sampling() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::builder("write-key".to_string(), data_plane.url())
+        .retry_config(RetryConfig::disabled())
+        .sampling(SamplingConfig::default().event("Heartbeat", SamplingRule::random(0.0)))
+        .rate_limit(RateLimitConfig::new(0.01, 1))
+        .buffer_config(BufferConfig::default())
+        .build_buffered()
+        .unwrap();
+
+    assert!(analytics.enqueue(track("Heartbeat")).is_ok());
+    assert!(analytics.enqueue(track("Heartbeat")).is_ok());
+    assert!(analytics.enqueue(track("Clicked")).is_ok());
+    assert!(analytics.enqueue(track("Scrolled")).is_ok());
+    analytics.flush().unwrap();
+
+    // the heartbeats are sampled out, and the last event is over the limit
+    assert_eq!(data_plane.events().len(), 1);
+    data_plane.assert_track_event("Clicked");
+    assert_eq!(analytics.stats().events_dropped, 3);
+}
+
+#[test]
+fn events_added_by_middleware_past_the_burst_are_rate_limited() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        data_plane.url(),
+        RetryConfig::disabled(),
+    )
+    .with_rate_limit(RateLimitConfig::new(0.01, 1))
+    .unwrap()
+    .with_middleware(|msg: Ruddermessage| vec![msg.clone(), msg]);
+
+    let result = analytics.send(&track("Clicked"));
+
+    assert!(matches!(result, Err(AnalyticsError::RateLimited)));
+    data_plane.assert_request_count(0);
+}
+
+#[test]
+fn batches_larger_than_the_burst_are_rejected() {
+    let analytics =
+        RudderAnalytics::load("write-key".to_string(), "https://example.com".to_string())
+            .with_rate_limit(RateLimitConfig::new(100.0, 1))
+            .unwrap();
+
+    let result = analytics.send(&Message::Batch(Batch {
+        batch: vec![track_member("First"), track_member("Second")],
+        ..Default::default()
+    }));
+
+    assert!(matches!(result, Err(AnalyticsError::Validation { .. })));
+}
+
+#[test]
+fn invalid_rate_limits_are_rejected() {
+    let result =
+        RudderAnalytics::builder("write-key".to_string(), "https://example.com".to_string())
+            .rate_limit(RateLimitConfig::new(0.0, 10))
+            .build();
+
+    assert!(matches!(result, Err(AnalyticsError::InvalidConfig(_))));
+}
@@ -0,0 +1,111 @@
+use chrono::{DateTime, Utc};
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::message::{Message, Track};
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::spool::{SpoolConfig, SpoolReplayer};
+use rudderanalytics::test_util::{MockDataPlane, MockResponse};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn spooling_analytics(server_url: String, dir: &Path) -> RudderAnalytics {
+    RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        server_url,
+        RetryConfig::disabled(),
+    )
+    .with_spool(SpoolConfig::new(dir))
+    .unwrap()
+}
+
+// Remove and parse the sentAt of a payload.
+fn sent_at(body: &mut serde_json::Value) -> DateTime<Utc> {
+    let sent_at = body.as_object_mut().unwrap().remove("sentAt").unwrap();
+    serde_json::from_value(sent_at).unwrap()
+}
+
+fn track_message() -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: "Spooled Event".to_string(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn spools_transient_failures_and_replays_on_startup() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503));
+    let analytics = spooling_analytics(data_plane.url(), dir.path());
+    let result = analytics.send(&track_message());
+
+    assert!(result.is_err());
+    assert_eq!(analytics.spool().unwrap().len().unwrap(), 1);
+    std::thread::sleep(Duration::from_millis(10));
+
+    let analytics = spooling_analytics(data_plane.url(), dir.path());
+    let delivered = analytics.replay_spool().unwrap();
+    let requests = data_plane.requests();
+
+    assert_eq!(delivered, 1);
+    assert!(analytics.spool().unwrap().is_empty().unwrap());
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].path, "/v1/track");
+    let mut failed_body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    let mut replayed_body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+    let failed_sent_at = sent_at(&mut failed_body);
+    let replayed_sent_at = sent_at(&mut replayed_body);
+    assert!(replayed_sent_at > failed_sent_at);
+    assert_eq!(replayed_body, failed_body);
+}
+
+#[test]
+fn does_not_spool_rejected_requests() {
+    let dir = tempfile::tempdir().unwrap();
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(400));
+    let analytics = spooling_analytics(data_plane.url(), dir.path());
+
+    let result = analytics.send(&track_message());
+
+    assert!(result.is_err());
+    assert!(analytics.spool().unwrap().is_empty().unwrap());
+}
+
+#[test]
+fn replay_keeps_events_when_data_plane_is_still_down() {
+    let dir = tempfile::tempdir().unwrap();
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503));
+    data_plane.enqueue(MockResponse::status(503));
+    data_plane.enqueue(MockResponse::status(503));
+    let analytics = spooling_analytics(data_plane.url(), dir.path());
+
+    assert!(analytics.send(&track_message()).is_err());
+    assert!(analytics.send(&track_message()).is_err());
+    let delivered = analytics.replay_spool().unwrap();
+
+    assert_eq!(delivered, 0);
+    data_plane.assert_request_count(3);
+    assert_eq!(analytics.spool().unwrap().len().unwrap(), 2);
+}
+
+#[test]
+fn replayer_delivers_spooled_events_in_background() {
+    let dir = tempfile::tempdir().unwrap();
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503));
+    let analytics = spooling_analytics(data_plane.url(), dir.path());
+    assert!(analytics.send(&track_message()).is_err());
+
+    let analytics = Arc::new(spooling_analytics(data_plane.url(), dir.path()));
+    let replayer = SpoolReplayer::start(Arc::clone(&analytics), Duration::from_secs(60));
+    let replayed = data_plane.wait_for_requests(2, Duration::from_secs(2));
+    replayer.stop();
+
+    assert!(replayed);
+    data_plane.assert_request_count(2);
+    assert!(analytics.spool().unwrap().is_empty().unwrap());
+}
@@ -0,0 +1,126 @@
+use rudderanalytics::buffered::BufferConfig;
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::message::{Message, Track};
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::sampling::{SamplingConfig, SamplingRule};
+use rudderanalytics::stats::DeliveryEvent;
+use rudderanalytics::test_util::{MockDataPlane, MockResponse};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn retry_config() -> RetryConfig {
+    RetryConfig {
+        max_retries: 3,
+        base_delay: Duration::from_millis(0),
+        max_backoff_delay: Duration::from_millis(0),
+        jitter_ratio: 0.0,
+        ..Default::default()
+    }
+}
+
+fn track(event: &str) -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: event.to_string(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn counts_attempts_retries_and_bytes() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503));
+    data_plane.enqueue(MockResponse::status(429));
+    data_plane.enqueue(MockResponse::status(503));
+    data_plane.enqueue(MockResponse::ok());
+    data_plane.enqueue(MockResponse::status(400));
+    let analytics = RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        data_plane.url(),
+        retry_config(),
+    );
+
+    assert!(analytics.send(&track("Delivered")).is_ok());
+    assert!(analytics.send(&track("Rejected")).is_err());
+    let requests = data_plane.requests();
+
+    let stats = analytics.stats();
+    assert_eq!(stats.attempts, 5);
+    assert_eq!(stats.retries_by_status.get(&503), Some(&2));
+    assert_eq!(stats.retries_by_status.get(&429), Some(&1));
+    assert_eq!(stats.retries(), 3);
+    assert_eq!(stats.events_delivered, 1);
+    assert_eq!(stats.events_failed, 1);
+    assert_eq!(stats.latency.count, 5);
+    let bytes: usize = requests.iter().map(|r| r.body.len()).sum();
+    assert_eq!(stats.bytes_sent, bytes as u64);
+}
+
+#[test]
+fn observers_receive_delivery_events() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(500));
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let observed = Arc::clone(&events);
+    let analytics = RudderAnalytics::builder("write-key".to_string(), data_plane.url())
+        .retry_config(retry_config())
+        .sampling(SamplingConfig::default().event("Heartbeat", SamplingRule::random(0.0)))
+        .observer(move |event: &DeliveryEvent| {
+            let name = match event {
+                DeliveryEvent::Attempt { status, .. } => format!("attempt {:?}", status),
+                DeliveryEvent::Retry { status, .. } => format!("retry {:?}", status),
+                DeliveryEvent::Delivered { path, events } => {
+                    format!("delivered {} {}", path, events)
+                }
+                DeliveryEvent::Failed { path, events } => format!("failed {} {}", path, events),
+                DeliveryEvent::Dropped { events } => format!("dropped {}", events),
+                DeliveryEvent::Diverted { path, events } => {
+                    format!("diverted {} {}", path, events)
+                }
+                DeliveryEvent::Captured { path, events } => {
+                    format!("captured {} {}", path, events)
+                }
+            };
+            observed.lock().unwrap().push(name);
+        })
+        .build()
+        .unwrap();
+
+    analytics.send(&track("Heartbeat")).unwrap();
+    analytics.send(&track("Signed Up")).unwrap();
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            "dropped 1",
+            "attempt Some(500)",
+            "retry Some(500)",
+            "attempt Some(200)",
+            "delivered /v1/track 1",
+        ]
+    );
+    assert_eq!(analytics.stats().events_dropped, 1);
+}
+
+#[test]
+fn buffered_client_exposes_stats() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::builder("write-key".to_string(), data_plane.url())
+        .retry_config(RetryConfig::disabled())
+        .buffer_config(BufferConfig {
+            max_batch_size: 3,
+            flush_interval: Duration::from_secs(60),
+            ..Default::default()
+        })
+        .build_buffered()
+        .unwrap();
+
+    for _ in 0..3 {
+        analytics.enqueue(track("Clicked")).unwrap();
+    }
+    analytics.flush().unwrap();
+
+    let stats = analytics.stats();
+    assert_eq!(stats.attempts, 1);
+    assert_eq!(stats.events_delivered, 3);
+}
@@ -0,0 +1,155 @@
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::compression::CompressionConfig;
+use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::message::{Batch, BatchMessage, Identify, Message, Track};
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::ruddermessage::Ruddermessage;
+use rudderanalytics::test_util::{MockDataPlane, MockResponse};
+use serde_json::json;
+use std::time::Duration;
+
+fn fast_retry() -> RetryConfig {
+    RetryConfig {
+        base_delay: Duration::from_millis(1),
+        max_backoff_delay: Duration::from_millis(5),
+        ..Default::default()
+    }
+}
+
+fn track_message(event: &str) -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: event.to_string(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn records_decoded_messages_per_endpoint() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::load("write-key".to_string(), data_plane.url());
+
+    analytics.send(&track_message("Signup")).unwrap();
+    analytics
+        .send(&Message::Identify(Identify {
+            user_id: Some("user-1".to_string()),
+            traits: Some(json!({ "plan": "pro" })),
+            ..Default::default()
+        }))
+        .unwrap();
+
+    data_plane.assert_request_count(2);
+    let requests = data_plane.requests();
+    assert_eq!(requests[0].method, "POST");
+    assert!(requests[0]
+        .header("Authorization")
+        .unwrap()
+        .starts_with("Basic "));
+    assert_eq!(data_plane.messages("/v1/track").len(), 1);
+    match &data_plane.messages("/v1/identify")[0] {
+        Ruddermessage::Identify(identify) => {
+            assert_eq!(identify.traits.as_ref().unwrap()["plan"], "pro")
+        }
+        other => panic!("expected an identify payload, got {:?}", other),
+    }
+    let track = data_plane.assert_track_event("Signup");
+    assert_eq!(track.user_id.as_deref(), Some("user-1"));
+}
+
+#[test]
+fn decodes_gzipped_batches() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::builder("write-key".to_string(), data_plane.url())
+        .compression(CompressionConfig {
+            enabled: true,
+            min_size: 0,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+
+    analytics
+        .send(&Message::Batch(Batch {
+            batch: vec![
+                BatchMessage::Track(Track {
+                    user_id: Some("user-1".to_string()),
+                    event: "First".to_string(),
+                    ..Default::default()
+                }),
+                BatchMessage::Track(Track {
+                    user_id: Some("user-1".to_string()),
+                    event: "Second".to_string(),
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }))
+        .unwrap();
+
+    assert_eq!(
+        data_plane.requests()[0].header("content-encoding"),
+        Some("gzip")
+    );
+    assert_eq!(data_plane.events().len(), 2);
+    data_plane.assert_track_event("Second");
+}
+
+#[test]
+fn replays_scripted_responses_in_order() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::status(503).retry_after("0"));
+    data_plane.enqueue(MockResponse::status(429).retry_after("0"));
+    data_plane.enqueue(MockResponse::status(400).body("bad payload"));
+    let analytics = RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        data_plane.url(),
+        fast_retry(),
+    );
+
+    let result = analytics.send(&track_message("Signup"));
+
+    data_plane.assert_request_count(3);
+    match result {
+        Err(AnalyticsError::Rejected { status, body, .. }) => {
+            assert_eq!(status, 400);
+            assert_eq!(body, "bad payload");
+        }
+        other => panic!("expected a rejected request, got {:?}", other),
+    }
+
+    // the script is used up, so later requests succeed
+    analytics.send(&track_message("Signup")).unwrap();
+    data_plane.assert_request_count(4);
+}
+
+#[test]
+fn dropped_connections_fail_the_request() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::drop_connection());
+    let analytics = RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        data_plane.url(),
+        RetryConfig::disabled(),
+    );
+
+    let result = analytics.send(&track_message("Dropped"));
+
+    assert!(result.is_err());
+    data_plane.assert_request_count(1);
+}
+
+#[test]
+fn delayed_responses_trigger_client_timeouts() {
+    let data_plane = MockDataPlane::start();
+    data_plane.enqueue(MockResponse::ok().delay(Duration::from_millis(500)));
+    let analytics = RudderAnalytics::builder("write-key".to_string(), data_plane.url())
+        .timeout(Duration::from_millis(100))
+        .retry_config(RetryConfig::disabled())
+        .build()
+        .unwrap();
+
+    let result = analytics.send(&track_message("Slow"));
+
+    assert!(result.is_err());
+    assert!(data_plane.wait_for_requests(1, Duration::from_secs(1)));
+}
@@ -0,0 +1,138 @@
+#![cfg(feature = "tracking-plan")]
+
+use rudderanalytics::buffered::BufferConfig;
+use rudderanalytics::client::RudderAnalytics;
+use rudderanalytics::errors::Error as AnalyticsError;
+use rudderanalytics::message::{Identify, Message, Track};
+use rudderanalytics::retry::RetryConfig;
+use rudderanalytics::test_util::MockDataPlane;
+use rudderanalytics::tracking_plan::{TrackingPlan, ValidationMode, Validator};
+use serde_json::{json, Value};
+use std::io::Write;
+
+fn tracking_plan() -> TrackingPlan {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        file,
+        "{}",
+        json!({
+            "events": {
+                "Order Completed": {
+                    "type": "object",
+                    "properties": {
+                        "order_id": { "type": "string" },
+                        "revenue": { "type": "number" }
+                    },
+                    "required": ["order_id"]
+                }
+            },
+            "identify": {
+                "type": "object",
+                "properties": { "email": { "type": "string", "format": "email" } }
+            },
+            "allowUnplannedEvents": false
+        })
+    )
+    .unwrap();
+    TrackingPlan::from_file(file.path()).unwrap()
+}
+
+fn order_completed(properties: Value) -> Message {
+    Message::Track(Track {
+        user_id: Some("user-1".to_string()),
+        event: "Order Completed".to_string(),
+        properties: Some(properties),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn validates_track_properties_and_identify_traits() {
+    let plan = tracking_plan();
+
+    assert!(plan
+        .validate_track(
+            "Order Completed",
+            Some(&json!({ "order_id": "1", "revenue": 10 }))
+        )
+        .is_empty());
+
+    let violations = plan.validate_track("Order Completed", Some(&json!({ "revenue": "10" })));
+    let fields: Vec<&str> = violations.iter().map(|v| v.field.as_str()).collect();
+    assert!(fields.contains(&"/properties"), "{:?}", violations);
+    assert!(fields.contains(&"/properties/revenue"), "{:?}", violations);
+
+    let violations = plan.validate_track("Unplanned Event", None);
+    assert_eq!(violations[0].field, "/event");
+
+    assert!(plan
+        .validate_identify(Some(&json!({ "email": 42 })))
+        .iter()
+        .any(|v| v.field == "/traits/email"));
+}
+
+#[test]
+fn rejects_invalid_tracking_plan_file() {
+    let result = TrackingPlan::from_value(&json!({
+        "events": { "Order Completed": { "type": "not-a-type" } }
+    }));
+
+    assert!(matches!(result, Err(AnalyticsError::InvalidConfig(_))));
+}
+
+#[test]
+fn reject_mode_does_not_send_event() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::load_with_retry_config(
+        "write-key".to_string(),
+        data_plane.url(),
+        RetryConfig::disabled(),
+    )
+    .with_validator(tracking_plan(), ValidationMode::Reject);
+
+    let result = analytics.send(&order_completed(json!({ "revenue": 10 })));
+
+    assert!(matches!(result, Err(AnalyticsError::Validation { .. })));
+    data_plane.assert_request_count(0);
+}
+
+#[test]
+fn annotate_mode_adds_violations_to_context() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::builder("write-key".to_string(), data_plane.url())
+        .retry_config(RetryConfig::disabled())
+        .validator(tracking_plan(), ValidationMode::Annotate)
+        .build()
+        .unwrap();
+
+    let result = analytics.send(&Message::Identify(Identify {
+        user_id: Some("user-1".to_string()),
+        traits: Some(json!({ "email": 42 })),
+        ..Default::default()
+    }));
+    let requests = data_plane.requests();
+
+    assert!(result.is_ok());
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    let violations = body["context"]["trackingPlanViolations"]
+        .as_array()
+        .unwrap();
+    assert_eq!(violations[0]["field"], "/traits/email");
+    assert_eq!(body["context"]["library"]["name"], "RudderStack Rust SDK");
+}
+
+#[test]
+fn buffered_client_rejects_on_enqueue() {
+    let data_plane = MockDataPlane::start();
+    let analytics = RudderAnalytics::builder("write-key".to_string(), data_plane.url())
+        .validator(tracking_plan(), ValidationMode::Reject)
+        .buffer_config(BufferConfig::default())
+        .build_buffered()
+        .unwrap();
+
+    let result = analytics.enqueue(order_completed(json!({})));
+    analytics.shutdown();
+
+    assert!(matches!(result, Err(AnalyticsError::Validation { .. })));
+    data_plane.assert_request_count(0);
+}